@@ -1,27 +1,61 @@
+use std::fmt::Debug;
+use std::process::exit;
+
+use larva::la::LaDecoder;
 use larva::rv::RvDecoder;
 
+#[derive(Clone, Copy)]
+enum Arch {
+    Riscv64,
+    Loongarch64,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: larva-disas [--arch riscv64|loongarch64] file...");
+    exit(1);
+}
+
 fn main() {
-    let argv = std::env::args();
-    for input_path in argv.skip(1) {
-        process(&input_path);
+    let mut arch = Arch::Riscv64;
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--arch" => {
+                arch = match argv.next().as_deref() {
+                    Some("riscv64") => Arch::Riscv64,
+                    Some("loongarch64") => Arch::Loongarch64,
+                    _ => usage(),
+                }
+            }
+            "-h" | "--help" => usage(),
+            x if x.starts_with('-') => usage(),
+            _ => process(arch, &arg),
+        }
     }
 }
 
-fn process(input_path: &str) {
+fn process(arch: Arch, input_path: &str) {
     let mem = std::fs::read(input_path).unwrap();
 
-    let d = RvDecoder::new(64);
-    let mut p = 0;
-    loop {
-        if let Some((insn, size)) = d.disas(&mem[p..mem.len()]) {
-            println!("{:16x}: {:?}", p, insn);
-            p += size;
-        } else {
-            break;
+    match arch {
+        Arch::Riscv64 => {
+            let d = RvDecoder::new(64);
+            dump(&mem, |x| d.disas(x));
+        }
+        Arch::Loongarch64 => {
+            let d = LaDecoder::new();
+            dump(&mem, |x| d.disas(x));
         }
+    }
+}
 
-        if p == mem.len() {
+fn dump<T: Debug>(mem: &[u8], disas: impl Fn(&[u8]) -> Option<(T, usize)>) {
+    let mut p = 0;
+    while p < mem.len() {
+        let Some((insn, size)) = disas(&mem[p..]) else {
             break;
-        }
+        };
+        println!("{:16x}: {:?}", p, insn);
+        p += size;
     }
 }
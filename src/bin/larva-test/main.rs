@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use larva::exec;
 
 fn main() {
@@ -24,17 +26,17 @@ fn main() {
         0x00, 0x00, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x0a,
     ];
 
-    let mut state = exec::RvIsaState::default();
+    let state = exec::RvIsaState::default();
 
     // init MMU, consume the code block
-    let mut mmu = exec::mem::GuestMmu::new(4096); // RV uses 4K pages
+    let mmu = exec::mem::GuestMmu::new(4096); // RV uses 4K pages
     mmu.consume_host(mem.as_ptr(), mem.len()).unwrap();
 
-    let mut executor = exec::interp::RvInterpreterExecutor::new(64, &mut state, &mut mmu);
+    let mut executor = exec::interp::RvInterpreterExecutor::new(64, state, Arc::new(mmu));
     executor.stack(4096).unwrap();
 
     let block_addr = mem.as_ptr() as u64;
-    let entry_pc = block_addr + 0;
+    let entry_pc = block_addr;
     println!("code addr = {:016x}", block_addr);
     println!(" entry pc = {:016x}", entry_pc);
     let exit_reason = executor.exec(entry_pc);
@@ -0,0 +1,179 @@
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::Arc;
+
+use larva::exec;
+use larva::exec::interp::{Dispatch, MisalignedPolicy};
+use larva::exec::StopReason;
+
+const DEFAULT_STACK_SIZE: usize = 8 * 1024 * 1024;
+// 256 GiB of guest address space, enough for the load addresses the loader
+// picks, falling back to per-mapping offsets where it cannot be reserved
+const GUEST_SPACE_SIZE: u64 = 1 << 38;
+
+struct Options {
+    sysroot: Option<PathBuf>,
+    env: Vec<(OsString, Option<OsString>)>,
+    debug: bool,
+    misaligned: MisalignedPolicy,
+    dispatch: Dispatch,
+    stack_size: usize,
+    prog: OsString,
+    args: Vec<OsString>,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: larva [options] program [arguments...]");
+    eprintln!();
+    eprintln!("options:");
+    eprintln!("  -L path      look up the program interpreter under this prefix first");
+    eprintln!("  -E var=value set an environment variable for the guest");
+    eprintln!("  -U var       remove an environment variable for the guest");
+    eprintln!("  -d           log every executed instruction and system call");
+    eprintln!("  -b           run decoded basic blocks, which is faster");
+    eprintln!("  -s size      set the stack size, with an optional k/M/G suffix");
+    eprintln!("  -m mode      emulate (the default) or trap on misaligned accesses");
+    exit(1);
+}
+
+// accepts a k, M or G suffix like qemu does
+fn parse_size(s: &str) -> Option<usize> {
+    let (num, shift) = match s.char_indices().last()? {
+        (i, 'k' | 'K') => (&s[..i], 10),
+        (i, 'm' | 'M') => (&s[..i], 20),
+        (i, 'g' | 'G') => (&s[..i], 30),
+        _ => (s, 0),
+    };
+    num.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        sysroot: None,
+        env: Vec::new(),
+        debug: false,
+        misaligned: MisalignedPolicy::Emulate,
+        dispatch: Dispatch::Step,
+        stack_size: DEFAULT_STACK_SIZE,
+        prog: OsString::new(),
+        args: Vec::new(),
+    };
+
+    let mut argv = std::env::args_os().skip(1);
+    // options stop at the program name, everything after it is the guest's
+    loop {
+        let arg = match argv.next() {
+            Some(x) => x,
+            None => usage(),
+        };
+        // the options themselves are ASCII, anything else is the program
+        match arg.to_str().unwrap_or("") {
+            "-L" => opts.sysroot = Some(argv.next().unwrap_or_else(|| usage()).into()),
+            "-E" => {
+                let kv = argv.next().unwrap_or_else(|| usage());
+                let kv = kv.as_bytes();
+                match kv.iter().position(|&b| b == b'=') {
+                    Some(i) => opts.env.push((
+                        OsStr::from_bytes(&kv[..i]).into(),
+                        Some(OsStr::from_bytes(&kv[i + 1..]).into()),
+                    )),
+                    None => usage(),
+                }
+            }
+            "-U" => opts
+                .env
+                .push((argv.next().unwrap_or_else(|| usage()), None)),
+            "-d" => opts.debug = true,
+            "-b" => opts.dispatch = Dispatch::Blocks,
+            "-s" => {
+                let size = argv.next().unwrap_or_else(|| usage());
+                opts.stack_size = size
+                    .to_str()
+                    .and_then(parse_size)
+                    .unwrap_or_else(|| usage());
+            }
+            "-m" => {
+                opts.misaligned = match argv.next().as_deref().and_then(OsStr::to_str) {
+                    Some("emulate") => MisalignedPolicy::Emulate,
+                    Some("trap") => MisalignedPolicy::Trap,
+                    _ => usage(),
+                }
+            }
+            "-h" | "--help" => usage(),
+            _ if arg.as_bytes().starts_with(b"-") => usage(),
+            _ => {
+                opts.prog = arg;
+                break;
+            }
+        }
+    }
+    opts.args = argv.collect();
+    opts
+}
+
+// the guest starts out with the host environment, edited by -E and -U
+fn guest_env(edits: &[(OsString, Option<OsString>)]) -> Vec<OsString> {
+    let mut env: Vec<(OsString, OsString)> = std::env::vars_os().collect();
+    for (k, v) in edits {
+        env.retain(|(x, _)| x != k);
+        if let Some(v) = v {
+            env.push((k.clone(), v.clone()));
+        }
+    }
+    env.into_iter()
+        .map(|(mut k, v)| {
+            k.push("=");
+            k.push(v);
+            k
+        })
+        .collect()
+}
+
+fn main() {
+    let opts = parse_args();
+
+    let state = exec::RvIsaState::default();
+    // RV uses 4K pages
+    let mmu = exec::mem::GuestMmu::with_address_space(
+        4096,
+        exec::mem::AddressSpace::GuestBase {
+            size: GUEST_SPACE_SIZE,
+        },
+    )
+    .unwrap_or_else(|_| exec::mem::GuestMmu::new(4096));
+
+    let prog =
+        match exec::loader::load_program(&mmu, Path::new(&opts.prog), opts.sysroot.as_deref()) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("larva: {}: {}", Path::new(&opts.prog).display(), e);
+                exit(1);
+            }
+        };
+
+    let mut argv = vec![opts.prog.clone()];
+    argv.extend(opts.args);
+    let envp = guest_env(&opts.env);
+
+    mmu.init_brk(prog.exe.brk);
+    let mut executor = exec::interp::RvInterpreterExecutor::new(64, state, Arc::new(mmu));
+    executor.debug(opts.debug);
+    executor.misaligned(opts.misaligned);
+    executor.dispatch(opts.dispatch);
+    if let Err(e) =
+        executor.stack_with_args(opts.stack_size, &opts.prog, &argv, &envp, &prog.auxv())
+    {
+        eprintln!("larva: cannot set up the stack: {}", e);
+        exit(1);
+    }
+
+    match executor.run(prog.entry()) {
+        StopReason::Exit(code) => exit(code),
+        reason => {
+            eprintln!("larva: guest stopped: {:?}", reason);
+            exit(128 + reason.signal().unwrap_or(0));
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::sync::atomic::{AtomicI32, AtomicI64, AtomicU32, AtomicU64, Ordering};
+
+use super::{sext_u32, Access, GuestAddr, RvInterpreterExecutor, StopReason};
+use crate::rv::{AmoArgs, AmoLrArgs};
+
+/// LR/SC reservation held by one hart.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Reservation {
+    gaddr: u64,
+    size: usize,
+    // value observed by the LR, SC succeeds only if memory still holds it
+    val: u64,
+}
+
+impl Reservation {
+    fn overlaps(&self, gaddr: u64, size: usize) -> bool {
+        gaddr < self.gaddr + self.size as u64 && self.gaddr < gaddr + size as u64
+    }
+}
+
+pub(super) enum AmoOp {
+    Swap,
+    Add,
+    Xor,
+    And,
+    Or,
+    Min,
+    Max,
+    Minu,
+    Maxu,
+}
+
+fn amo_ordering(aq: bool, rl: bool) -> Ordering {
+    match (aq, rl) {
+        (false, false) => Ordering::Relaxed,
+        (true, false) => Ordering::Acquire,
+        (false, true) => Ordering::Release,
+        (true, true) => Ordering::SeqCst,
+    }
+}
+
+// loads cannot have release semantics, so be conservative with `lr.rl`
+fn lr_ordering(aq: bool, rl: bool) -> Ordering {
+    match (aq, rl) {
+        (false, false) => Ordering::Relaxed,
+        (true, false) => Ordering::Acquire,
+        (_, true) => Ordering::SeqCst,
+    }
+}
+
+fn sc_failure_ordering(aq: bool) -> Ordering {
+    if aq {
+        Ordering::Acquire
+    } else {
+        Ordering::Relaxed
+    }
+}
+
+impl RvInterpreterExecutor {
+    pub(super) fn invalidate_reservation(&mut self, gaddr: GuestAddr, size: usize) {
+        if let Some(r) = self.reservation {
+            if r.overlaps(gaddr.as_u64(), size) {
+                self.reservation = None;
+            }
+        }
+    }
+
+    // AMOs always require natural alignment, and we access the host memory
+    // with real atomics which have the same requirement
+    fn amo_host_ptr(&self, gaddr: u64, size: usize, read: bool) -> Result<*mut u8, StopReason> {
+        if gaddr & (size as u64 - 1) != 0 {
+            return Err(StopReason::Misaligned { read, gaddr });
+        }
+
+        // aligned accesses only end up split by oddly sized injected memory
+        let access = if read { Access::Read } else { Access::Write };
+        match self.translate_access(gaddr, size, access)? {
+            Some(p) => Ok(p),
+            None => Err(StopReason::Segv { access, gaddr }),
+        }
+    }
+
+    pub(super) fn do_lr_w(&mut self, a: &AmoLrArgs) -> StopReason {
+        let gaddr = self.gx(a.rs1);
+        let p = match self.amo_host_ptr(gaddr, 4, true) {
+            Ok(p) => p,
+            Err(e) => return e,
+        };
+
+        let v = unsafe { AtomicU32::from_ptr(p as *mut u32) }.load(lr_ordering(a.aq, a.rl));
+        self.reservation = Some(Reservation {
+            gaddr,
+            size: 4,
+            val: v as u64,
+        });
+        self.sx(a.rd, sext_u32(v));
+        StopReason::Next
+    }
+
+    pub(super) fn do_lr_d(&mut self, a: &AmoLrArgs) -> StopReason {
+        let gaddr = self.gx(a.rs1);
+        let p = match self.amo_host_ptr(gaddr, 8, true) {
+            Ok(p) => p,
+            Err(e) => return e,
+        };
+
+        let v = unsafe { AtomicU64::from_ptr(p as *mut u64) }.load(lr_ordering(a.aq, a.rl));
+        self.reservation = Some(Reservation {
+            gaddr,
+            size: 8,
+            val: v,
+        });
+        self.sx(a.rd, v);
+        StopReason::Next
+    }
+
+    pub(super) fn do_sc_w(&mut self, a: &AmoArgs) -> StopReason {
+        let gaddr = self.gx(a.rs1);
+        let p = match self.amo_host_ptr(gaddr, 4, false) {
+            Ok(p) => p,
+            Err(e) => return e,
+        };
+
+        // the reservation is consumed regardless of the outcome
+        let ok = match self.reservation.take() {
+            Some(r) if r.gaddr == gaddr && r.size == 4 => {
+                let new = self.gx(a.rs2) as u32;
+                unsafe { AtomicU32::from_ptr(p as *mut u32) }
+                    .compare_exchange(
+                        r.val as u32,
+                        new,
+                        amo_ordering(a.aq, a.rl),
+                        sc_failure_ordering(a.aq),
+                    )
+                    .is_ok()
+            }
+            _ => false,
+        };
+
+        if ok {
+            self.icache.invalidate(gaddr, 4);
+            self.blocks.invalidate(gaddr, 4);
+        }
+        self.sx(a.rd, if ok { 0 } else { 1 });
+        StopReason::Next
+    }
+
+    pub(super) fn do_sc_d(&mut self, a: &AmoArgs) -> StopReason {
+        let gaddr = self.gx(a.rs1);
+        let p = match self.amo_host_ptr(gaddr, 8, false) {
+            Ok(p) => p,
+            Err(e) => return e,
+        };
+
+        let ok = match self.reservation.take() {
+            Some(r) if r.gaddr == gaddr && r.size == 8 => {
+                let new = self.gx(a.rs2);
+                unsafe { AtomicU64::from_ptr(p as *mut u64) }
+                    .compare_exchange(
+                        r.val,
+                        new,
+                        amo_ordering(a.aq, a.rl),
+                        sc_failure_ordering(a.aq),
+                    )
+                    .is_ok()
+            }
+            _ => false,
+        };
+
+        if ok {
+            self.icache.invalidate(gaddr, 8);
+            self.blocks.invalidate(gaddr, 8);
+        }
+        self.sx(a.rd, if ok { 0 } else { 1 });
+        StopReason::Next
+    }
+
+    pub(super) fn do_amo_w(&mut self, a: &AmoArgs, op: AmoOp) -> StopReason {
+        let gaddr = self.gx(a.rs1);
+        let p = match self.amo_host_ptr(gaddr, 4, false) {
+            Ok(p) => p,
+            Err(e) => return e,
+        };
+        self.invalidate_reservation(gaddr.into(), 4);
+        self.icache.invalidate(gaddr, 4);
+        self.blocks.invalidate(gaddr, 4);
+
+        let ord = amo_ordering(a.aq, a.rl);
+        let src = self.gx(a.rs2) as u32;
+        let u = unsafe { AtomicU32::from_ptr(p as *mut u32) };
+        let s = unsafe { AtomicI32::from_ptr(p as *mut i32) };
+        let old = match op {
+            AmoOp::Swap => u.swap(src, ord),
+            AmoOp::Add => u.fetch_add(src, ord),
+            AmoOp::Xor => u.fetch_xor(src, ord),
+            AmoOp::And => u.fetch_and(src, ord),
+            AmoOp::Or => u.fetch_or(src, ord),
+            AmoOp::Min => s.fetch_min(src as i32, ord) as u32,
+            AmoOp::Max => s.fetch_max(src as i32, ord) as u32,
+            AmoOp::Minu => u.fetch_min(src, ord),
+            AmoOp::Maxu => u.fetch_max(src, ord),
+        };
+
+        self.sx(a.rd, sext_u32(old));
+        StopReason::Next
+    }
+
+    pub(super) fn do_amo_d(&mut self, a: &AmoArgs, op: AmoOp) -> StopReason {
+        let gaddr = self.gx(a.rs1);
+        let p = match self.amo_host_ptr(gaddr, 8, false) {
+            Ok(p) => p,
+            Err(e) => return e,
+        };
+        self.invalidate_reservation(gaddr.into(), 8);
+        self.icache.invalidate(gaddr, 8);
+        self.blocks.invalidate(gaddr, 8);
+
+        let ord = amo_ordering(a.aq, a.rl);
+        let src = self.gx(a.rs2);
+        let u = unsafe { AtomicU64::from_ptr(p as *mut u64) };
+        let s = unsafe { AtomicI64::from_ptr(p as *mut i64) };
+        let old = match op {
+            AmoOp::Swap => u.swap(src, ord),
+            AmoOp::Add => u.fetch_add(src, ord),
+            AmoOp::Xor => u.fetch_xor(src, ord),
+            AmoOp::And => u.fetch_and(src, ord),
+            AmoOp::Or => u.fetch_or(src, ord),
+            AmoOp::Min => s.fetch_min(src as i64, ord) as u64,
+            AmoOp::Max => s.fetch_max(src as i64, ord) as u64,
+            AmoOp::Minu => u.fetch_min(src, ord),
+            AmoOp::Maxu => u.fetch_max(src, ord),
+        };
+
+        self.sx(a.rd, old);
+        StopReason::Next
+    }
+}
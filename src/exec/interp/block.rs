@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::{sext_u16, sext_u32, sext_u8, GuestAddr, RvInterpreterExecutor, StopReason};
+use crate::rv::RvInsn;
+
+// longest run of instructions decoded into one block
+const MAX_BLOCK_LEN: usize = 64;
+// blocks never span pages of this size, so a store invalidates only the
+// blocks on its own pages
+const PAGE_SHIFT: u32 = 12;
+const FILTER_BITS: usize = 4096;
+// direct-mapped front of the block table, which spares most lookups the
+// hashing
+const RECENT_SLOTS: usize = 1024;
+
+type Handler = fn(&mut RvInterpreterExecutor, &MicroOp) -> StopReason;
+
+/// One pre-decoded instruction: the handler that runs it, and its operands
+/// unpacked for the handlers of common instructions. Everything else goes
+/// through the generic handler and the full `RvInsn`.
+#[derive(Clone, Copy)]
+pub(super) struct MicroOp {
+    handler: Handler,
+    rd: u8,
+    rs1: u8,
+    rs2: u8,
+    len: u8,
+    // immediate, or shift amount
+    imm: i64,
+    insn: RvInsn,
+}
+
+fn micro_op(insn: RvInsn, len: usize) -> MicroOp {
+    let op = |handler: Handler, rd: u8, rs1: u8, rs2: u8, imm: i32| MicroOp {
+        handler,
+        rd,
+        rs1,
+        rs2,
+        len: len as u8,
+        imm: imm as i64,
+        insn,
+    };
+    match insn {
+        RvInsn::Lui(a) => op(op_li, a.rd, 0, 0, a.imm),
+        RvInsn::Auipc(a) => op(op_auipc, a.rd, 0, 0, a.imm),
+        RvInsn::Jal(a) => op(op_jal, a.rd, 0, 0, a.imm),
+        RvInsn::Jalr(a) => op(op_jalr, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Beq(a) => op(op_beq, 0, a.rs1, a.rs2, a.imm),
+        RvInsn::Bne(a) => op(op_bne, 0, a.rs1, a.rs2, a.imm),
+        RvInsn::Blt(a) => op(op_blt, 0, a.rs1, a.rs2, a.imm),
+        RvInsn::Bge(a) => op(op_bge, 0, a.rs1, a.rs2, a.imm),
+        RvInsn::Bltu(a) => op(op_bltu, 0, a.rs1, a.rs2, a.imm),
+        RvInsn::Bgeu(a) => op(op_bgeu, 0, a.rs1, a.rs2, a.imm),
+        RvInsn::Lb(a) => op(op_lb, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Lh(a) => op(op_lh, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Lw(a) => op(op_lw, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Ld(a) => op(op_ld, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Lbu(a) => op(op_lbu, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Lhu(a) => op(op_lhu, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Lwu(a) => op(op_lwu, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Sb(a) => op(op_sb, 0, a.rs1, a.rs2, a.imm),
+        RvInsn::Sh(a) => op(op_sh, 0, a.rs1, a.rs2, a.imm),
+        RvInsn::Sw(a) => op(op_sw, 0, a.rs1, a.rs2, a.imm),
+        RvInsn::Sd(a) => op(op_sd, 0, a.rs1, a.rs2, a.imm),
+        RvInsn::Addi(a) => op(op_addi, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Andi(a) => op(op_andi, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Ori(a) => op(op_ori, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Xori(a) => op(op_xori, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Addiw(a) => op(op_addiw, a.rd, a.rs1, 0, a.imm),
+        RvInsn::Slli(a) => op(op_slli, a.rd, a.rs1, 0, a.shamt as i32),
+        RvInsn::Srli(a) => op(op_srli, a.rd, a.rs1, 0, a.shamt as i32),
+        RvInsn::Srai(a) => op(op_srai, a.rd, a.rs1, 0, a.shamt as i32),
+        RvInsn::Add(a) => op(op_add, a.rd, a.rs1, a.rs2, 0),
+        RvInsn::Sub(a) => op(op_sub, a.rd, a.rs1, a.rs2, 0),
+        RvInsn::And(a) => op(op_and, a.rd, a.rs1, a.rs2, 0),
+        RvInsn::Or(a) => op(op_or, a.rd, a.rs1, a.rs2, 0),
+        RvInsn::Xor(a) => op(op_xor, a.rd, a.rs1, a.rs2, 0),
+        RvInsn::Addw(a) => op(op_addw, a.rd, a.rs1, a.rs2, 0),
+        RvInsn::Subw(a) => op(op_subw, a.rd, a.rs1, a.rs2, 0),
+        _ => op(op_generic, 0, 0, 0, 0),
+    }
+}
+
+// instructions after which the next pc is not known when decoding
+fn ends_block(insn: &RvInsn) -> bool {
+    matches!(
+        insn,
+        RvInsn::Invalid(_)
+            | RvInsn::Ecall
+            | RvInsn::Ebreak
+            | RvInsn::Jal(_)
+            | RvInsn::Jalr(_)
+            | RvInsn::Beq(_)
+            | RvInsn::Bne(_)
+            | RvInsn::Blt(_)
+            | RvInsn::Bge(_)
+            | RvInsn::Bltu(_)
+            | RvInsn::Bgeu(_)
+            | RvInsn::FenceI(_)
+    )
+}
+
+fn op_generic(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.interpret_one(&op.insn, op.len as usize)
+}
+
+fn op_li(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, op.imm as u64);
+    StopReason::Next
+}
+
+fn op_auipc(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, e.pcrel(op.imm));
+    StopReason::Next
+}
+
+fn op_jal(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    let pc = e.state.get_pc();
+    e.sx(op.rd, pc + op.len as u64);
+    StopReason::ContinueAt(pc.wrapping_add(op.imm as u64))
+}
+
+fn op_jalr(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    let target = ea(e, op) & !1;
+    e.sx(op.rd, e.state.get_pc() + op.len as u64);
+    StopReason::ContinueAt(target)
+}
+
+fn branch(e: &RvInterpreterExecutor, op: &MicroOp, taken: bool) -> StopReason {
+    if taken {
+        StopReason::ContinueAt(e.pcrel(op.imm))
+    } else {
+        StopReason::Next
+    }
+}
+
+fn op_beq(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    branch(e, op, e.gx(op.rs1) == e.gx(op.rs2))
+}
+
+fn op_bne(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    branch(e, op, e.gx(op.rs1) != e.gx(op.rs2))
+}
+
+fn op_blt(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    branch(e, op, (e.gx(op.rs1) as i64) < e.gx(op.rs2) as i64)
+}
+
+fn op_bge(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    branch(e, op, e.gx(op.rs1) as i64 >= e.gx(op.rs2) as i64)
+}
+
+fn op_bltu(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    branch(e, op, e.gx(op.rs1) < e.gx(op.rs2))
+}
+
+fn op_bgeu(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    branch(e, op, e.gx(op.rs1) >= e.gx(op.rs2))
+}
+
+fn ea(e: &RvInterpreterExecutor, op: &MicroOp) -> u64 {
+    e.gx(op.rs1).wrapping_add(op.imm as u64)
+}
+
+fn load<T>(
+    e: &mut RvInterpreterExecutor,
+    op: &MicroOp,
+    get: fn(&RvInterpreterExecutor, GuestAddr) -> Result<T, StopReason>,
+    ext: fn(T) -> u64,
+) -> StopReason {
+    match get(e, ea(e, op).into()) {
+        Ok(v) => {
+            e.sx(op.rd, ext(v));
+            StopReason::Next
+        }
+        Err(x) => x,
+    }
+}
+
+fn store<T>(
+    e: &mut RvInterpreterExecutor,
+    op: &MicroOp,
+    set: fn(&mut RvInterpreterExecutor, GuestAddr, T) -> Result<(), StopReason>,
+    val: T,
+) -> StopReason {
+    let addr = ea(e, op);
+    set(e, addr.into(), val).err().unwrap_or(StopReason::Next)
+}
+
+fn op_lb(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    load(e, op, RvInterpreterExecutor::get_u8, sext_u8)
+}
+
+fn op_lh(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    load(e, op, RvInterpreterExecutor::get_u16, sext_u16)
+}
+
+fn op_lw(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    load(e, op, RvInterpreterExecutor::get_u32, sext_u32)
+}
+
+fn op_ld(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    load(e, op, RvInterpreterExecutor::get_u64, |v| v)
+}
+
+fn op_lbu(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    load(e, op, RvInterpreterExecutor::get_u8, u64::from)
+}
+
+fn op_lhu(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    load(e, op, RvInterpreterExecutor::get_u16, u64::from)
+}
+
+fn op_lwu(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    load(e, op, RvInterpreterExecutor::get_u32, u64::from)
+}
+
+fn op_sb(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    let v = e.gx(op.rs2) as u8;
+    store(e, op, RvInterpreterExecutor::set_u8, v)
+}
+
+fn op_sh(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    let v = e.gx(op.rs2) as u16;
+    store(e, op, RvInterpreterExecutor::set_u16, v)
+}
+
+fn op_sw(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    let v = e.gx(op.rs2) as u32;
+    store(e, op, RvInterpreterExecutor::set_u32, v)
+}
+
+fn op_sd(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    let v = e.gx(op.rs2);
+    store(e, op, RvInterpreterExecutor::set_u64, v)
+}
+
+fn op_addi(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, ea(e, op));
+    StopReason::Next
+}
+
+fn op_andi(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, e.gx(op.rs1) & op.imm as u64);
+    StopReason::Next
+}
+
+fn op_ori(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, e.gx(op.rs1) | op.imm as u64);
+    StopReason::Next
+}
+
+fn op_xori(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, e.gx(op.rs1) ^ op.imm as u64);
+    StopReason::Next
+}
+
+fn op_addiw(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, sext_u32(ea(e, op) as u32));
+    StopReason::Next
+}
+
+fn op_slli(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, e.gx(op.rs1) << op.imm);
+    StopReason::Next
+}
+
+fn op_srli(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, e.gx(op.rs1) >> op.imm);
+    StopReason::Next
+}
+
+fn op_srai(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, (e.gx(op.rs1) as i64 >> op.imm) as u64);
+    StopReason::Next
+}
+
+fn op_add(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, e.gx(op.rs1).wrapping_add(e.gx(op.rs2)));
+    StopReason::Next
+}
+
+fn op_sub(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, e.gx(op.rs1).wrapping_sub(e.gx(op.rs2)));
+    StopReason::Next
+}
+
+fn op_and(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, e.gx(op.rs1) & e.gx(op.rs2));
+    StopReason::Next
+}
+
+fn op_or(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, e.gx(op.rs1) | e.gx(op.rs2));
+    StopReason::Next
+}
+
+fn op_xor(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    e.sx(op.rd, e.gx(op.rs1) ^ e.gx(op.rs2));
+    StopReason::Next
+}
+
+fn op_addw(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    let v = e.gx(op.rs1).wrapping_add(e.gx(op.rs2));
+    e.sx(op.rd, sext_u32(v as u32));
+    StopReason::Next
+}
+
+fn op_subw(e: &mut RvInterpreterExecutor, op: &MicroOp) -> StopReason {
+    let v = e.gx(op.rs1).wrapping_sub(e.gx(op.rs2));
+    e.sx(op.rd, sext_u32(v as u32));
+    StopReason::Next
+}
+
+type Block = Arc<[MicroOp]>;
+
+/// Basic blocks decoded into micro-ops by start pc, private to one thread.
+/// Invalidated like `InsnCache`, with exact page tracking since a miss
+/// costs a whole block.
+pub(super) struct BlockCache {
+    generation: u64,
+    blocks: HashMap<u64, Block>,
+    recent: Vec<Option<(u64, Block)>>,
+    // start pcs of the blocks on each page, and a filter of those pages so
+    // that most stores skip the lookup
+    pages: HashMap<u64, Vec<u64>>,
+    filter: Vec<u64>,
+    // set when blocks are dropped, so that the running one stops early
+    dirty: bool,
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self {
+            generation: u64::MAX,
+            blocks: HashMap::new(),
+            recent: vec![None; RECENT_SLOTS],
+            pages: HashMap::new(),
+            filter: vec![0; FILTER_BITS / 64],
+            dirty: false,
+        }
+    }
+}
+
+fn filter_bit(page: u64) -> (usize, u64) {
+    let bit = page as usize % FILTER_BITS;
+    (bit / 64, 1 << (bit % 64))
+}
+
+impl BlockCache {
+    // Takes the block at `pc` out of the front table to run it, saving
+    // the reference counting. It goes back in with `put_back`.
+    fn take(&mut self, generation: u64, pc: u64) -> Option<Block> {
+        if generation != self.generation {
+            self.flush();
+            self.generation = generation;
+            return None;
+        }
+        let slot = &mut self.recent[(pc >> 1) as usize % RECENT_SLOTS];
+        match slot.take() {
+            Some((x, b)) if x == pc => Some(b),
+            _ => self.blocks.get(&pc).cloned(),
+        }
+    }
+
+    // Returns a block taken by `take`, unless blocks were dropped meanwhile,
+    // possibly this one.
+    fn put_back(&mut self, pc: u64, block: Block) {
+        if !self.dirty {
+            self.recent[(pc >> 1) as usize % RECENT_SLOTS] = Some((pc, block));
+        }
+    }
+
+    fn insert(&mut self, pc: u64, block: Block) {
+        let page = pc >> PAGE_SHIFT;
+        let (word, mask) = filter_bit(page);
+        self.filter[word] |= mask;
+        self.pages.entry(page).or_default().push(pc);
+        self.blocks.insert(pc, block);
+    }
+
+    /// Drops the blocks on the pages `[g, g + len)` touches.
+    pub(super) fn invalidate(&mut self, g: u64, len: usize) {
+        if self.blocks.is_empty() {
+            return;
+        }
+        let first = g >> PAGE_SHIFT;
+        let last = (g + len as u64 - 1) >> PAGE_SHIFT;
+        for page in first..=last {
+            let (word, mask) = filter_bit(page);
+            if self.filter[word] & mask == 0 {
+                continue;
+            }
+            if let Some(pcs) = self.pages.remove(&page) {
+                for pc in pcs {
+                    self.blocks.remove(&pc);
+                    self.recent[(pc >> 1) as usize % RECENT_SLOTS] = None;
+                }
+                self.dirty = true;
+            }
+        }
+    }
+
+    pub(super) fn flush(&mut self) {
+        if !self.blocks.is_empty() {
+            self.dirty = true;
+        }
+        self.blocks.clear();
+        self.recent.fill(None);
+        self.pages.clear();
+        self.filter.fill(0);
+    }
+}
+
+impl RvInterpreterExecutor {
+    // Decodes the block starting at `pc`, unless it is cached, which is
+    // returned too. It ends at the first control
+    // transfer, at the end of its page, or after MAX_BLOCK_LEN instructions.
+    fn block_at(&mut self, pc: u64) -> Result<(Block, bool), StopReason> {
+        if let Some(b) = self.blocks.take(self.mmu.code_generation(), pc) {
+            return Ok((b, true));
+        }
+
+        let page = pc >> PAGE_SHIFT;
+        let mut ops = Vec::new();
+        let mut p = pc;
+        // an instruction straddling pages is left for a block of its own,
+        // which is not cached
+        let mut cacheable = true;
+        while ops.len() < MAX_BLOCK_LEN && p >> PAGE_SHIFT == page {
+            let (insn, len) = match self.decode_at(p) {
+                Ok(x) => x,
+                // faults are raised once the guest gets there
+                Err(e) if ops.is_empty() => return Err(e),
+                Err(_) => break,
+            };
+            if (p + len as u64 - 1) >> PAGE_SHIFT != page {
+                if !ops.is_empty() {
+                    break;
+                }
+                cacheable = false;
+            }
+            ops.push(micro_op(insn, len));
+            p += len as u64;
+            if ends_block(&insn) {
+                break;
+            }
+        }
+
+        let block: Block = ops.into();
+        if cacheable {
+            self.blocks.insert(pc, block.clone());
+        }
+        Ok((block, cacheable))
+    }
+
+    // Runs a block, returning how the guest stopped if it did. Faults are
+    // delivered as signals like in `exec`.
+    fn run_block(&mut self, block: &[MicroOp]) -> Option<StopReason> {
+        self.blocks.dirty = false;
+        for op in block {
+            match (op.handler)(self, op) {
+                StopReason::Next => self.state.set_pc(self.state.get_pc() + op.len as u64),
+                StopReason::ContinueAt(x) => {
+                    self.state.set_pc(x);
+                    self.instret += 1;
+                    return None;
+                }
+                x @ (StopReason::Segv { .. }
+                | StopReason::Misaligned { .. }
+                | StopReason::ReservedInsn
+                | StopReason::Break) => return self.deliver_fault(x),
+                x => return Some(x),
+            }
+            self.instret += 1;
+
+            // the guest changed its code, possibly what comes next here
+            if self.blocks.dirty {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// `exec` with `Dispatch::Blocks`.
+    pub(super) fn exec_blocks(&mut self) -> StopReason {
+        loop {
+            if self.signal_pending() {
+                if let Some(x) = self.deliver_pending() {
+                    return x;
+                }
+            }
+
+            let pc = self.state.get_pc();
+            let (block, cached) = match self.block_at(pc) {
+                Ok(x) => x,
+                Err(x) => match self.deliver_fault(x) {
+                    Some(x) => return x,
+                    None => continue,
+                },
+            };
+            let res = self.run_block(&block);
+            if cached {
+                self.blocks.put_back(pc, block);
+            }
+            if let Some(x) = res {
+                return x;
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use super::{RvInterpreterExecutor, StopReason};
+use crate::rv::{Csr, CsrArgs, CsrImmArgs};
+
+// the `time` CSR ticks at the same rate as on most RISC-V Linux platforms
+const TIMEBASE_FREQ: u64 = 10_000_000;
+
+pub(super) enum CsrOp {
+    Write,
+    Set,
+    Clear,
+}
+
+fn host_time() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    ts.tv_sec as u64 * TIMEBASE_FREQ + ts.tv_nsec as u64 / (1_000_000_000 / TIMEBASE_FREQ)
+}
+
+impl RvInterpreterExecutor {
+    fn read_csr(&self, csr: &Csr) -> Option<u64> {
+        match csr {
+            Csr::Fflags => Some(self.state.get_fflags() as u64),
+            Csr::Frm => Some(self.state.get_frm() as u64),
+            Csr::Fcsr => Some(self.state.get_fcsr() as u64),
+            // there is no timing model, so one instruction is one cycle
+            Csr::Cycle | Csr::Instret => Some(self.instret),
+            Csr::Time => Some(host_time()),
+            // privileged or unimplemented
+            Csr::Other(_) => None,
+        }
+    }
+
+    fn write_csr(&mut self, csr: &Csr, val: u64) -> Option<()> {
+        match csr {
+            Csr::Fflags => self.state.set_fflags(val as u8),
+            Csr::Frm => self.state.set_frm(val as u8),
+            Csr::Fcsr => self.state.set_fcsr(val as u32),
+            // the user counters are read-only
+            _ => return None,
+        }
+        Some(())
+    }
+
+    // `src` is the rs1 value or the zero-extended immediate, and `src_is_x0`
+    // tells whether the set/clear variants should skip the write
+    fn do_csr(&mut self, csr: &Csr, rd: u8, op: CsrOp, src: u64, src_is_x0: bool) -> StopReason {
+        let write = match op {
+            CsrOp::Write => true,
+            CsrOp::Set | CsrOp::Clear => !src_is_x0,
+        };
+
+        let old = match self.read_csr(csr) {
+            Some(x) => x,
+            None => return StopReason::ReservedInsn,
+        };
+
+        if write {
+            let new = match op {
+                CsrOp::Write => src,
+                CsrOp::Set => old | src,
+                CsrOp::Clear => old & !src,
+            };
+            if self.write_csr(csr, new).is_none() {
+                return StopReason::ReservedInsn;
+            }
+        }
+
+        self.sx(rd, old);
+        StopReason::Next
+    }
+
+    pub(super) fn do_csr_reg(&mut self, a: &CsrArgs, op: CsrOp) -> StopReason {
+        let src = self.gx(a.rs1);
+        self.do_csr(&a.csr, a.rd, op, src, a.rs1 == 0)
+    }
+
+    pub(super) fn do_csr_imm(&mut self, a: &CsrImmArgs, op: CsrOp) -> StopReason {
+        self.do_csr(&a.csr, a.rd, op, a.uimm as u64, a.uimm == 0)
+    }
+}
@@ -0,0 +1,420 @@
+use super::softfp::{self, Fmt, Rm, F32, F64, NV};
+use super::{sext_u32, RvInterpreterExecutor, StopReason};
+use crate::exec::{CANONICAL_NAN_F32, CANONICAL_NAN_F64};
+use crate::rv::{R2FTypeArgs, R2TypeArgs, R4TypeArgs, RFTypeArgs, RTypeArgs, RoundingMode};
+
+const SIGN_F32: u32 = 1 << 31;
+const QUIET_F32: u32 = 1 << 22;
+const SIGN_F64: u64 = 1 << 63;
+const QUIET_F64: u64 = 1 << 51;
+
+fn is_snan_f32(x: f32) -> bool {
+    x.is_nan() && x.to_bits() & QUIET_F32 == 0
+}
+
+pub(super) fn fsgnj_f32(a: f32, b: f32) -> f32 {
+    f32::from_bits((a.to_bits() & !SIGN_F32) | (b.to_bits() & SIGN_F32))
+}
+
+pub(super) fn fsgnjn_f32(a: f32, b: f32) -> f32 {
+    f32::from_bits((a.to_bits() & !SIGN_F32) | (!b.to_bits() & SIGN_F32))
+}
+
+pub(super) fn fsgnjx_f32(a: f32, b: f32) -> f32 {
+    f32::from_bits(a.to_bits() ^ (b.to_bits() & SIGN_F32))
+}
+
+// fmin/fmax return the non-NaN operand if only one of them is NaN, and -0.0
+// is considered less than +0.0
+pub(super) fn fmin_f32(a: f32, b: f32) -> f32 {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => f32::from_bits(CANONICAL_NAN_F32),
+        (true, false) => b,
+        (false, true) => a,
+        _ => {
+            if a < b || (a == b && a.is_sign_negative()) {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+pub(super) fn fmax_f32(a: f32, b: f32) -> f32 {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => f32::from_bits(CANONICAL_NAN_F32),
+        (true, false) => b,
+        (false, true) => a,
+        _ => {
+            if a > b || (a == b && a.is_sign_positive()) {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+pub(super) fn fclass_f32(x: f32) -> u64 {
+    use std::num::FpCategory::*;
+
+    let bit = match (x.classify(), x.is_sign_negative()) {
+        (Infinite, true) => 0,
+        (Normal, true) => 1,
+        (Subnormal, true) => 2,
+        (Zero, true) => 3,
+        (Zero, false) => 4,
+        (Subnormal, false) => 5,
+        (Normal, false) => 6,
+        (Infinite, false) => 7,
+        (Nan, _) => {
+            if is_snan_f32(x) {
+                8
+            } else {
+                9
+            }
+        }
+    };
+    1 << bit
+}
+
+fn is_snan_f64(x: f64) -> bool {
+    x.is_nan() && x.to_bits() & QUIET_F64 == 0
+}
+
+pub(super) fn fsgnj_f64(a: f64, b: f64) -> f64 {
+    f64::from_bits((a.to_bits() & !SIGN_F64) | (b.to_bits() & SIGN_F64))
+}
+
+pub(super) fn fsgnjn_f64(a: f64, b: f64) -> f64 {
+    f64::from_bits((a.to_bits() & !SIGN_F64) | (!b.to_bits() & SIGN_F64))
+}
+
+pub(super) fn fsgnjx_f64(a: f64, b: f64) -> f64 {
+    f64::from_bits(a.to_bits() ^ (b.to_bits() & SIGN_F64))
+}
+
+pub(super) fn fmin_f64(a: f64, b: f64) -> f64 {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => f64::from_bits(CANONICAL_NAN_F64),
+        (true, false) => b,
+        (false, true) => a,
+        _ => {
+            if a < b || (a == b && a.is_sign_negative()) {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+pub(super) fn fmax_f64(a: f64, b: f64) -> f64 {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => f64::from_bits(CANONICAL_NAN_F64),
+        (true, false) => b,
+        (false, true) => a,
+        _ => {
+            if a > b || (a == b && a.is_sign_positive()) {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+pub(super) fn fclass_f64(x: f64) -> u64 {
+    use std::num::FpCategory::*;
+
+    let bit = match (x.classify(), x.is_sign_negative()) {
+        (Infinite, true) => 0,
+        (Normal, true) => 1,
+        (Subnormal, true) => 2,
+        (Zero, true) => 3,
+        (Zero, false) => 4,
+        (Subnormal, false) => 5,
+        (Normal, false) => 6,
+        (Infinite, false) => 7,
+        (Nan, _) => {
+            if is_snan_f64(x) {
+                8
+            } else {
+                9
+            }
+        }
+    };
+    1 << bit
+}
+
+/// Integer operand of the FCVT instructions.
+pub(super) enum IntFmt {
+    W,
+    Wu,
+    L,
+    Lu,
+}
+
+impl IntFmt {
+    fn range(&self) -> (i128, i128) {
+        match self {
+            IntFmt::W => (i32::MIN as i128, i32::MAX as i128),
+            IntFmt::Wu => (0, u32::MAX as i128),
+            IntFmt::L => (i64::MIN as i128, i64::MAX as i128),
+            IntFmt::Lu => (0, u64::MAX as i128),
+        }
+    }
+
+    fn value_of(&self, x: u64) -> i128 {
+        match self {
+            IntFmt::W => x as i32 as i128,
+            IntFmt::Wu => x as u32 as i128,
+            IntFmt::L => x as i64 as i128,
+            IntFmt::Lu => x as i128,
+        }
+    }
+
+    // 32-bit results are always sign-extended, even for the unsigned variants
+    fn to_x(&self, v: i128) -> u64 {
+        match self {
+            IntFmt::W | IntFmt::Wu => sext_u32(v as u32),
+            IntFmt::L | IntFmt::Lu => v as u64,
+        }
+    }
+}
+
+type Op2 = fn(&Fmt, u64, u64, Rm) -> (u64, u8);
+type Op3 = fn(&Fmt, u64, u64, u64, Rm) -> (u64, u8);
+
+impl RvInterpreterExecutor {
+    // the dynamic rounding mode comes from frm, where the reserved encodings
+    // and DYN itself make the instruction illegal
+    fn resolve_rm(&self, rm: &RoundingMode) -> Option<Rm> {
+        match rm {
+            RoundingMode::Dyn => Rm::from_static(&RoundingMode::from(self.state.get_frm())),
+            _ => Rm::from_static(rm),
+        }
+    }
+
+    fn gfb(&self, f: &Fmt, idx: u8) -> u64 {
+        if f.is_single() {
+            self.gf32(idx).to_bits() as u64
+        } else {
+            self.state.get_f_bits(idx)
+        }
+    }
+
+    fn sfb(&mut self, f: &Fmt, idx: u8, val: u64) {
+        if f.is_single() {
+            self.state.set_f32_bits(idx, val as u32)
+        } else {
+            self.state.set_f_bits(idx, val)
+        }
+    }
+
+    pub(super) fn fop2(&mut self, f: &Fmt, a: &RFTypeArgs, op: Op2) -> StopReason {
+        let Some(rm) = self.resolve_rm(&a.rm) else {
+            return StopReason::ReservedInsn;
+        };
+        let (v, flags) = op(f, self.gfb(f, a.rs1), self.gfb(f, a.rs2), rm);
+        self.sfb(f, a.rd, v);
+        self.state.accrue_fflags(flags);
+        StopReason::Next
+    }
+
+    pub(super) fn fop3(&mut self, f: &Fmt, a: &R4TypeArgs, op: Op3) -> StopReason {
+        let Some(rm) = self.resolve_rm(&a.rm) else {
+            return StopReason::ReservedInsn;
+        };
+        let (x, y, z) = (self.gfb(f, a.rs1), self.gfb(f, a.rs2), self.gfb(f, a.rs3));
+        let (v, flags) = op(f, x, y, z, rm);
+        self.sfb(f, a.rd, v);
+        self.state.accrue_fflags(flags);
+        StopReason::Next
+    }
+
+    pub(super) fn fsqrt(&mut self, f: &Fmt, a: &R2FTypeArgs) -> StopReason {
+        let Some(rm) = self.resolve_rm(&a.rm) else {
+            return StopReason::ReservedInsn;
+        };
+        let (v, flags) = softfp::sqrt(f, self.gfb(f, a.rs1), rm);
+        self.sfb(f, a.rd, v);
+        self.state.accrue_fflags(flags);
+        StopReason::Next
+    }
+
+    pub(super) fn fcvt_int(&mut self, f: &Fmt, a: &R2FTypeArgs, i: IntFmt) -> StopReason {
+        let Some(rm) = self.resolve_rm(&a.rm) else {
+            return StopReason::ReservedInsn;
+        };
+        let (min, max) = i.range();
+        let (v, flags) = softfp::to_int(f, self.gfb(f, a.rs1), rm, min, max);
+        self.sx(a.rd, i.to_x(v));
+        self.state.accrue_fflags(flags);
+        StopReason::Next
+    }
+
+    pub(super) fn fcvt_from_int(&mut self, f: &Fmt, a: &R2FTypeArgs, i: IntFmt) -> StopReason {
+        let Some(rm) = self.resolve_rm(&a.rm) else {
+            return StopReason::ReservedInsn;
+        };
+        let (v, flags) = softfp::from_int(f, i.value_of(self.gx(a.rs1)), rm);
+        self.sfb(f, a.rd, v);
+        self.state.accrue_fflags(flags);
+        StopReason::Next
+    }
+
+    pub(super) fn fcvt_s_d(&mut self, a: &R2FTypeArgs) -> StopReason {
+        let Some(rm) = self.resolve_rm(&a.rm) else {
+            return StopReason::ReservedInsn;
+        };
+        let (v, flags) = softfp::convert(&F64, &F32, self.gfb(&F64, a.rs1), rm);
+        self.sfb(&F32, a.rd, v);
+        self.state.accrue_fflags(flags);
+        StopReason::Next
+    }
+
+    // widening is always exact, but the rm field is still validated
+    pub(super) fn fcvt_d_s(&mut self, a: &R2FTypeArgs) -> StopReason {
+        let Some(rm) = self.resolve_rm(&a.rm) else {
+            return StopReason::ReservedInsn;
+        };
+        let (v, flags) = softfp::convert(&F32, &F64, self.gfb(&F32, a.rs1), rm);
+        self.sfb(&F64, a.rd, v);
+        self.state.accrue_fflags(flags);
+        StopReason::Next
+    }
+
+    // sign injection never raises exceptions; NaN handling is done by the
+    // callback
+    pub(super) fn fsop_s(&mut self, a: &RTypeArgs, f: fn(f32, f32) -> f32) -> StopReason {
+        let v = f(self.gf32(a.rs1), self.gf32(a.rs2));
+        self.sf32(a.rd, v);
+        StopReason::Next
+    }
+
+    // fmin/fmax additionally signal invalid on signaling NaN inputs
+    pub(super) fn fminmax_s(&mut self, a: &RTypeArgs, f: fn(f32, f32) -> f32) -> StopReason {
+        let (x, y) = (self.gf32(a.rs1), self.gf32(a.rs2));
+        if is_snan_f32(x) || is_snan_f32(y) {
+            self.state.accrue_fflags(NV);
+        }
+        self.sf32(a.rd, f(x, y));
+        StopReason::Next
+    }
+
+    // feq is a quiet comparison and only signals on signaling NaNs, flt and
+    // fle signal on any NaN
+    pub(super) fn fcmp_s(
+        &mut self,
+        a: &RTypeArgs,
+        f: fn(&f32, &f32) -> bool,
+        signaling: bool,
+    ) -> StopReason {
+        let (x, y) = (self.gf32(a.rs1), self.gf32(a.rs2));
+        let invalid = if signaling {
+            x.is_nan() || y.is_nan()
+        } else {
+            is_snan_f32(x) || is_snan_f32(y)
+        };
+        if invalid {
+            self.state.accrue_fflags(NV);
+        }
+        self.sx(a.rd, f(&x, &y) as u64);
+        StopReason::Next
+    }
+
+    pub(super) fn fclass_s(&mut self, a: &R2TypeArgs) -> StopReason {
+        self.sx(a.rd, fclass_f32(self.gf32(a.rs1)));
+        StopReason::Next
+    }
+
+    pub(super) fn fsop_d(&mut self, a: &RTypeArgs, f: fn(f64, f64) -> f64) -> StopReason {
+        let v = f(self.gf64(a.rs1), self.gf64(a.rs2));
+        self.sf64(a.rd, v);
+        StopReason::Next
+    }
+
+    pub(super) fn fminmax_d(&mut self, a: &RTypeArgs, f: fn(f64, f64) -> f64) -> StopReason {
+        let (x, y) = (self.gf64(a.rs1), self.gf64(a.rs2));
+        if is_snan_f64(x) || is_snan_f64(y) {
+            self.state.accrue_fflags(NV);
+        }
+        self.sf64(a.rd, f(x, y));
+        StopReason::Next
+    }
+
+    pub(super) fn fcmp_d(
+        &mut self,
+        a: &RTypeArgs,
+        f: fn(&f64, &f64) -> bool,
+        signaling: bool,
+    ) -> StopReason {
+        let (x, y) = (self.gf64(a.rs1), self.gf64(a.rs2));
+        let invalid = if signaling {
+            x.is_nan() || y.is_nan()
+        } else {
+            is_snan_f64(x) || is_snan_f64(y)
+        };
+        if invalid {
+            self.state.accrue_fflags(NV);
+        }
+        self.sx(a.rd, f(&x, &y) as u64);
+        StopReason::Next
+    }
+
+    pub(super) fn fclass_d(&mut self, a: &R2TypeArgs) -> StopReason {
+        self.sx(a.rd, fclass_f64(self.gf64(a.rs1)));
+        StopReason::Next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fmin_fmax_f32() {
+        let qnan = f32::from_bits(CANONICAL_NAN_F32);
+        let snan = f32::from_bits(0x7f80_0001);
+
+        assert_eq!(fmin_f32(1.0, 2.0), 1.0);
+        assert_eq!(fmax_f32(1.0, 2.0), 2.0);
+        assert!(fmin_f32(0.0, -0.0).is_sign_negative());
+        assert!(fmax_f32(-0.0, 0.0).is_sign_positive());
+        assert_eq!(fmin_f32(qnan, 3.0), 3.0);
+        assert_eq!(fmax_f32(-3.0, snan), -3.0);
+        assert_eq!(fmin_f32(snan, qnan).to_bits(), CANONICAL_NAN_F32);
+    }
+
+    #[test]
+    fn test_fclass_f32() {
+        assert_eq!(fclass_f32(f32::NEG_INFINITY), 1 << 0);
+        assert_eq!(fclass_f32(-1.0), 1 << 1);
+        assert_eq!(fclass_f32(-f32::from_bits(1)), 1 << 2);
+        assert_eq!(fclass_f32(-0.0), 1 << 3);
+        assert_eq!(fclass_f32(0.0), 1 << 4);
+        assert_eq!(fclass_f32(f32::from_bits(1)), 1 << 5);
+        assert_eq!(fclass_f32(1.0), 1 << 6);
+        assert_eq!(fclass_f32(f32::INFINITY), 1 << 7);
+        assert_eq!(fclass_f32(f32::from_bits(0x7f80_0001)), 1 << 8);
+        assert_eq!(fclass_f32(f32::from_bits(CANONICAL_NAN_F32)), 1 << 9);
+    }
+
+    #[test]
+    fn test_int_fmt() {
+        assert_eq!(IntFmt::W.value_of(0xffff_ffff), -1);
+        assert_eq!(IntFmt::Wu.value_of(0x1_ffff_ffff), u32::MAX as i128);
+        assert_eq!(IntFmt::Lu.value_of(u64::MAX), u64::MAX as i128);
+        assert_eq!(IntFmt::W.to_x(-1), u64::MAX);
+        assert_eq!(IntFmt::Wu.to_x(u32::MAX as i128), u64::MAX);
+        assert_eq!(IntFmt::Wu.to_x(0x7fff_ffff), 0x7fff_ffff);
+        assert_eq!(IntFmt::Lu.to_x(u64::MAX as i128), u64::MAX);
+
+        // NaN saturates to the maximum of the integer format
+        let (min, max) = IntFmt::Wu.range();
+        let (v, flags) = softfp::to_int(&F64, CANONICAL_NAN_F64, Rm::Rtz, min, max);
+        assert_eq!((IntFmt::Wu.to_x(v), flags), (u64::MAX, NV));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::rv::RvInsn;
+
+// granularity of invalidation, the guest page size
+const PAGE_SHIFT: u32 = 12;
+// one slot per halfword of guest code, so a page of code fits at once
+const SLOTS: usize = 4096;
+const FILTER_BITS: usize = 4096;
+
+#[derive(Clone, Copy)]
+struct Slot {
+    pc: u64,
+    insn: RvInsn,
+    len: usize,
+}
+
+/// Direct-mapped cache of decoded instructions by guest pc, private to one
+/// thread. It is flushed whenever executable mappings change and by
+/// fence.i, and for one page whenever the thread stores to it.
+pub(super) struct InsnCache {
+    generation: u64,
+    slots: Box<[Option<Slot>]>,
+    // pages that may have instructions cached, hashed by page number
+    pages: Box<[u64]>,
+}
+
+impl Default for InsnCache {
+    fn default() -> Self {
+        Self {
+            generation: u64::MAX,
+            slots: vec![None; SLOTS].into_boxed_slice(),
+            pages: vec![0; FILTER_BITS / 64].into_boxed_slice(),
+        }
+    }
+}
+
+fn slot_index(pc: u64) -> usize {
+    (pc >> 1) as usize % SLOTS
+}
+
+fn page_bit(page: u64) -> (usize, u64) {
+    let bit = page as usize % FILTER_BITS;
+    (bit / 64, 1 << (bit % 64))
+}
+
+impl InsnCache {
+    /// Looks up the instruction at `pc`. `generation` is the code
+    /// generation of the `GuestMmu`, a change of which flushes everything.
+    pub(super) fn get(&mut self, generation: u64, pc: u64) -> Option<(RvInsn, usize)> {
+        if generation != self.generation {
+            self.flush();
+            self.generation = generation;
+            return None;
+        }
+        match self.slots[slot_index(pc)] {
+            Some(s) if s.pc == pc => Some((s.insn, s.len)),
+            _ => None,
+        }
+    }
+
+    pub(super) fn insert(&mut self, pc: u64, insn: RvInsn, len: usize) {
+        // instructions straddling pages are decoded every time, so that a
+        // store only ever has to invalidate its own pages
+        let page = pc >> PAGE_SHIFT;
+        if (pc + len as u64 - 1) >> PAGE_SHIFT != page {
+            return;
+        }
+        self.slots[slot_index(pc)] = Some(Slot { pc, insn, len });
+        let (word, mask) = page_bit(page);
+        self.pages[word] |= mask;
+    }
+
+    /// Drops the instructions cached from the pages `[g, g + len)` touches.
+    pub(super) fn invalidate(&mut self, g: u64, len: usize) {
+        let first = g >> PAGE_SHIFT;
+        let last = (g + len as u64 - 1) >> PAGE_SHIFT;
+        for page in first..=last {
+            let (word, mask) = page_bit(page);
+            if self.pages[word] & mask == 0 {
+                continue;
+            }
+            // pages sharing the bit share these slots too, so the bit can
+            // go unless one of them still has instructions here
+            let mut shared = false;
+            let start = page << PAGE_SHIFT;
+            for pc in (start..start + (1 << PAGE_SHIFT)).step_by(2) {
+                let slot = &mut self.slots[slot_index(pc)];
+                match slot {
+                    Some(s) if s.pc == pc => *slot = None,
+                    Some(s) => shared |= page_bit(s.pc >> PAGE_SHIFT) == (word, mask),
+                    None => {}
+                }
+            }
+            if !shared {
+                self.pages[word] &= !mask;
+            }
+        }
+    }
+
+    pub(super) fn flush(&mut self) {
+        self.slots.fill(None);
+        self.pages.fill(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalidate_shared_filter_bit() {
+        // two pages that share a filter bit and the same half of the slots
+        let a = 0;
+        let b = (FILTER_BITS as u64) << PAGE_SHIFT | 2;
+        let mut c = InsnCache::default();
+        c.get(0, a);
+        c.insert(a, RvInsn::Ecall, 4);
+        c.insert(b, RvInsn::Ebreak, 4);
+        let (word, mask) = page_bit(0);
+
+        // the bit stays while the other page has instructions cached
+        c.invalidate(a, 4);
+        assert!(c.get(0, a).is_none());
+        assert!(matches!(c.get(0, b), Some((RvInsn::Ebreak, 4))));
+        assert_ne!(c.pages[word] & mask, 0);
+
+        c.invalidate(b, 4);
+        assert!(c.get(0, b).is_none());
+        assert_eq!(c.pages[word] & mask, 0);
+    }
+}
@@ -1,17 +1,92 @@
-use super::mem::{GuestAddr, GuestMmu};
-use super::{RvIsaState, StopReason};
+use std::ffi::{OsStr, OsString};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use super::mem::{Access, GuestAddr, GuestMmu, Tlb};
+use super::{stack, RvIsaState, StopReason};
 use crate::rv::{RvDecoder, RvInsn};
 
+mod amo;
+mod block;
+mod csr;
+mod fp;
+mod icache;
+mod muldiv;
+mod signal;
+mod softfp;
 mod syscall;
 
-pub struct RvInterpreterExecutor<'a> {
+use amo::{AmoOp, Reservation};
+use block::BlockCache;
+use csr::CsrOp;
+use fp::*;
+use icache::InsnCache;
+use muldiv::MulDivOp;
+use signal::{AltStack, SigProcess, SigQueue};
+use softfp::{F32, F64};
+
+/// What to do with misaligned loads and stores. Atomics always trap, as the
+/// spec requires.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum MisalignedPolicy {
+    /// Carry out the access, like Linux does for user space.
+    #[default]
+    Emulate,
+    /// Stop with `StopReason::Misaligned`.
+    Trap,
+}
+
+/// How `exec` runs guest code.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Dispatch {
+    /// Fetch, decode and run one instruction at a time.
+    #[default]
+    Step,
+    /// Decode straight-line code into blocks of micro-ops, and run those.
+    /// Debug logging always steps.
+    Blocks,
+}
+
+// threads of a guest process, and how the process ended once it has
+struct ThreadGroup {
+    live: AtomicUsize,
+    exit: Mutex<Option<StopReason>>,
+    exited: Condvar,
+}
+
+/// Interpreter for one guest thread. All threads of a guest process share
+/// the same `GuestMmu`.
+pub struct RvInterpreterExecutor {
     debug: bool,
     shamt_mask: u64,
+    misaligned: MisalignedPolicy,
+    dispatch: Dispatch,
 
-    state: &'a mut RvIsaState,
-    mmu: &'a mut GuestMmu,
+    state: RvIsaState,
+    mmu: Arc<GuestMmu>,
+    tlb: Tlb,
 
     decoder: RvDecoder,
+    icache: InsnCache,
+    blocks: BlockCache,
+
+    reservation: Option<Reservation>,
+    instret: u64,
+
+    // the threads of the guest process, this one included
+    threads: Arc<ThreadGroup>,
+    // set_tid_address(2) and set_robust_list(2) pointers
+    clear_child_tid: u64,
+    robust_list: u64,
+
+    signals: Arc<SigProcess>,
+    // signals sent to this thread, and the ones it blocks
+    pending: Arc<SigQueue>,
+    blocked: u64,
+    altstack: AltStack,
+    // a0 of a syscall that a signal about to be delivered cut short, for
+    // restarting it
+    restart_a0: Option<u64>,
 }
 
 fn sext_u8(x: u8) -> u64 {
@@ -26,14 +101,39 @@ fn sext_u32(x: u32) -> u64 {
     x as i32 as i64 as u64
 }
 
-impl<'a> RvInterpreterExecutor<'a> {
-    pub fn new(xlen: usize, state: &'a mut RvIsaState, mmu: &'a mut GuestMmu) -> Self {
+impl RvInterpreterExecutor {
+    /// Creates the executor for the main thread of a new guest process,
+    /// which runs on the calling host thread.
+    pub fn new(xlen: usize, state: RvIsaState, mmu: Arc<GuestMmu>) -> Self {
+        let signals = Arc::new(SigProcess::new());
+        let pending = Arc::new(SigQueue::default());
+        signals.register(unsafe { libc::gettid() } as i64, pending.clone());
+
         Self {
             debug: false,
             shamt_mask: (xlen - 1) as u64,
+            misaligned: MisalignedPolicy::default(),
+            dispatch: Dispatch::default(),
             state,
             mmu,
+            tlb: Tlb::default(),
             decoder: RvDecoder::new(xlen),
+            icache: InsnCache::default(),
+            blocks: BlockCache::default(),
+            reservation: None,
+            instret: 0,
+            threads: Arc::new(ThreadGroup {
+                live: AtomicUsize::new(1),
+                exit: Mutex::new(None),
+                exited: Condvar::new(),
+            }),
+            clear_child_tid: 0,
+            robust_list: 0,
+            signals,
+            pending,
+            blocked: 0,
+            altstack: AltStack::default(),
+            restart_a0: None,
         }
     }
 
@@ -41,6 +141,74 @@ impl<'a> RvInterpreterExecutor<'a> {
         self.debug = val;
     }
 
+    pub fn misaligned(&mut self, policy: MisalignedPolicy) {
+        self.misaligned = policy;
+    }
+
+    pub fn dispatch(&mut self, dispatch: Dispatch) {
+        self.dispatch = dispatch;
+    }
+
+    pub fn state(&self) -> &RvIsaState {
+        &self.state
+    }
+
+    // accounts for the exit of this executor's thread, returning whether it
+    // was the last one of the guest process
+    fn thread_exited(&self) -> bool {
+        self.threads.live.fetch_sub(1, Ordering::SeqCst) == 1
+    }
+
+    // Accounts for this executor's thread stopping with `reason`. The first
+    // thread to end the whole process, with exit_group(2), as its last
+    // thread, or by a fatal signal, records how it ended and kills the
+    // other threads, like Linux does.
+    fn thread_stopped(&self, reason: StopReason) {
+        let tid = unsafe { libc::gettid() } as i64;
+        self.signals.unregister(tid);
+        let last = self.thread_exited();
+        let end = match reason {
+            StopReason::ExitThread(code) if last => StopReason::Exit(code),
+            StopReason::ExitThread(_) => return,
+            x => x,
+        };
+
+        let mut exit = self.threads.exit.lock().unwrap();
+        if exit.is_none() {
+            *exit = Some(end);
+            self.signals.kill_others(tid);
+            self.threads.exited.notify_all();
+        }
+    }
+
+    // executor for a new thread of the same process, starting with a copy
+    // of this thread's registers and signal mask
+    fn new_thread(&self) -> Self {
+        self.threads.live.fetch_add(1, Ordering::SeqCst);
+        Self {
+            debug: self.debug,
+            shamt_mask: self.shamt_mask,
+            misaligned: self.misaligned,
+            dispatch: self.dispatch,
+            state: self.state.clone(),
+            mmu: self.mmu.clone(),
+            tlb: Tlb::default(),
+            decoder: RvDecoder::new((self.shamt_mask + 1) as usize),
+            icache: InsnCache::default(),
+            blocks: BlockCache::default(),
+            reservation: None,
+            instret: 0,
+            threads: self.threads.clone(),
+            clear_child_tid: 0,
+            robust_list: 0,
+            signals: self.signals.clone(),
+            pending: Arc::new(SigQueue::default()),
+            blocked: self.blocked,
+            altstack: AltStack::default(),
+            restart_a0: None,
+        }
+    }
+
     pub fn stack(&mut self, len: usize) -> ::std::io::Result<()> {
         let stack_block = self.mmu.mmap(len, true)?;
         let stack_top = stack_block + len;
@@ -48,96 +216,172 @@ impl<'a> RvInterpreterExecutor<'a> {
         Ok(())
     }
 
-    fn get_u8(&self, gaddr: GuestAddr) -> Result<u8, StopReason> {
-        if let Some(haddr) = self.mmu.g2h(gaddr) {
-            Ok(unsafe { (haddr.as_u64() as *const u8).read() })
-        } else {
-            Err(StopReason::Segv {
-                read: true,
-                gaddr: gaddr.into(),
-            })
+    /// Allocates a stack and lays out the process startup information on it
+    /// the way Linux does at execve. `auxv` holds the entries that describe
+    /// the loaded program.
+    pub fn stack_with_args(
+        &mut self,
+        len: usize,
+        execfn: &OsStr,
+        argv: &[OsString],
+        envp: &[OsString],
+        auxv: &[(u64, u64)],
+    ) -> ::std::io::Result<()> {
+        let stack_block = self.mmu.mmap(len, true)?;
+        let stack_top = stack_block + len;
+
+        let mut random = [0u8; 16];
+        let n = unsafe { libc::getrandom(random.as_mut_ptr() as *mut libc::c_void, 16, 0) };
+        if n != 16 {
+            return Err(::std::io::Error::last_os_error());
         }
-    }
 
-    fn get_u16(&self, gaddr: GuestAddr) -> Result<u16, StopReason> {
-        if let Some(haddr) = self.mmu.g2h(gaddr) {
-            Ok(unsafe { (haddr.as_u64() as *const u16).read() })
-        } else {
-            Err(StopReason::Segv {
-                read: true,
-                gaddr: gaddr.into(),
-            })
+        let mut aux = vec![
+            (stack::AT_PAGESZ, self.mmu.page_size() as u64),
+            (stack::AT_HWCAP, stack::HWCAP_RV64GC),
+            (stack::AT_CLKTCK, 100),
+            (stack::AT_UID, unsafe { libc::getuid() } as u64),
+            (stack::AT_EUID, unsafe { libc::geteuid() } as u64),
+            (stack::AT_GID, unsafe { libc::getgid() } as u64),
+            (stack::AT_EGID, unsafe { libc::getegid() } as u64),
+            (stack::AT_SECURE, 0),
+        ];
+        aux.extend_from_slice(auxv);
+
+        let st = stack::build_initial_stack(stack_top.as_u64(), execfn, argv, envp, &aux, &random);
+        if st.bytes.len() > len {
+            return Err(::std::io::ErrorKind::OutOfMemory.into());
         }
+        self.mmu.write_bytes(st.sp.into(), &st.bytes)?;
+
+        self.state.set_x(2, st.sp);
+        Ok(())
     }
 
-    fn get_u32(&self, gaddr: GuestAddr) -> Result<u32, StopReason> {
-        if let Some(haddr) = self.mmu.g2h(gaddr) {
-            Ok(unsafe { (haddr.as_u64() as *const u32).read() })
-        } else {
-            Err(StopReason::Segv {
-                read: true,
-                gaddr: gaddr.into(),
-            })
+    fn translate_byte(&self, g: u64, access: Access) -> Result<*mut u8, StopReason> {
+        match self.tlb.translate(&self.mmu, g.into(), access) {
+            Some(h) => Ok(h.as_u64() as *mut u8),
+            None => Err(StopReason::Segv { access, gaddr: g }),
         }
     }
 
-    fn get_u64(&self, gaddr: GuestAddr) -> Result<u64, StopReason> {
-        if let Some(haddr) = self.mmu.g2h(gaddr) {
-            Ok(unsafe { (haddr.as_u64() as *const u64).read() })
-        } else {
-            Err(StopReason::Segv {
-                read: true,
-                gaddr: gaddr.into(),
-            })
+    // Checks that all `len` bytes at `g` allow `access`. Returns their host
+    // address if they are contiguous there too, which is always the case
+    // unless the access spans mappings.
+    fn translate_access(
+        &self,
+        g: u64,
+        len: usize,
+        access: Access,
+    ) -> Result<Option<*mut u8>, StopReason> {
+        if let Some(h) = self.tlb.translate_range(&self.mmu, g.into(), len, access) {
+            return Ok(Some(h.as_u64() as *mut u8));
         }
-    }
 
-    fn set_u8(&self, gaddr: GuestAddr, val: u8) -> Result<(), StopReason> {
-        if let Some(haddr) = self.mmu.g2h(gaddr) {
-            Ok(unsafe { (haddr.as_u64() as *mut u8).write(val) })
-        } else {
-            Err(StopReason::Segv {
-                read: false,
-                gaddr: gaddr.into(),
-            })
+        // faults are reported at the first byte that is not accessible
+        let end = g.checked_add(len as u64).ok_or(StopReason::Segv {
+            access,
+            gaddr: u64::MAX,
+        })?;
+        for p in g..end {
+            self.translate_byte(p, access)?;
         }
+        Ok(None)
     }
 
-    fn set_u16(&self, gaddr: GuestAddr, val: u16) -> Result<(), StopReason> {
-        if let Some(haddr) = self.mmu.g2h(gaddr) {
-            Ok(unsafe { (haddr.as_u64() as *mut u16).write(val) })
-        } else {
-            Err(StopReason::Segv {
-                read: false,
-                gaddr: gaddr.into(),
-            })
+    fn check_aligned(&self, gaddr: GuestAddr, size: usize, read: bool) -> Result<(), StopReason> {
+        let gaddr = gaddr.as_u64();
+        if self.misaligned == MisalignedPolicy::Trap && gaddr & (size as u64 - 1) != 0 {
+            return Err(StopReason::Misaligned { read, gaddr });
         }
+        Ok(())
     }
 
-    fn set_u32(&self, gaddr: GuestAddr, val: u32) -> Result<(), StopReason> {
-        if let Some(haddr) = self.mmu.g2h(gaddr) {
-            Ok(unsafe { (haddr.as_u64() as *mut u32).write(val) })
-        } else {
-            Err(StopReason::Segv {
-                read: false,
-                gaddr: gaddr.into(),
-            })
+    // Reads guest memory, a byte at a time where it is split on the host.
+    fn load<const N: usize>(
+        &self,
+        gaddr: GuestAddr,
+        access: Access,
+    ) -> Result<[u8; N], StopReason> {
+        let g = gaddr.as_u64();
+        let mut buf = [0u8; N];
+        match self.translate_access(g, N, access)? {
+            Some(h) => unsafe { std::ptr::copy_nonoverlapping(h, buf.as_mut_ptr(), N) },
+            None => {
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = unsafe { self.translate_byte(g + i as u64, access)?.read() };
+                }
+            }
         }
+        Ok(buf)
     }
 
-    fn set_u64(&self, gaddr: GuestAddr, val: u64) -> Result<(), StopReason> {
-        if let Some(haddr) = self.mmu.g2h(gaddr) {
-            Ok(unsafe { (haddr.as_u64() as *mut u64).write(val) })
-        } else {
-            Err(StopReason::Segv {
-                read: false,
-                gaddr: gaddr.into(),
-            })
+    // Writes guest memory. Nothing is written unless all of it is writable.
+    fn store<const N: usize>(&mut self, gaddr: GuestAddr, val: [u8; N]) -> Result<(), StopReason> {
+        let g = gaddr.as_u64();
+        self.invalidate_reservation(gaddr, N);
+        match self.translate_access(g, N, Access::Write)? {
+            Some(h) => unsafe { std::ptr::copy_nonoverlapping(val.as_ptr(), h, N) },
+            None => {
+                for (i, b) in val.iter().enumerate() {
+                    unsafe { self.translate_byte(g + i as u64, Access::Write)?.write(*b) };
+                }
+            }
         }
+        self.icache.invalidate(g, N);
+        self.blocks.invalidate(g, N);
+        Ok(())
+    }
+
+    fn fetch_u16(&self, gaddr: GuestAddr) -> Result<u16, StopReason> {
+        self.load(gaddr, Access::Exec).map(u16::from_le_bytes)
+    }
+
+    fn get_u8(&self, gaddr: GuestAddr) -> Result<u8, StopReason> {
+        self.load(gaddr, Access::Read).map(u8::from_le_bytes)
+    }
+
+    fn get_u16(&self, gaddr: GuestAddr) -> Result<u16, StopReason> {
+        self.check_aligned(gaddr, 2, true)?;
+        self.load(gaddr, Access::Read).map(u16::from_le_bytes)
+    }
+
+    fn get_u32(&self, gaddr: GuestAddr) -> Result<u32, StopReason> {
+        self.check_aligned(gaddr, 4, true)?;
+        self.load(gaddr, Access::Read).map(u32::from_le_bytes)
+    }
+
+    fn get_u64(&self, gaddr: GuestAddr) -> Result<u64, StopReason> {
+        self.check_aligned(gaddr, 8, true)?;
+        self.load(gaddr, Access::Read).map(u64::from_le_bytes)
+    }
+
+    fn set_u8(&mut self, gaddr: GuestAddr, val: u8) -> Result<(), StopReason> {
+        self.store(gaddr, val.to_le_bytes())
+    }
+
+    fn set_u16(&mut self, gaddr: GuestAddr, val: u16) -> Result<(), StopReason> {
+        self.check_aligned(gaddr, 2, false)?;
+        self.store(gaddr, val.to_le_bytes())
+    }
+
+    fn set_u32(&mut self, gaddr: GuestAddr, val: u32) -> Result<(), StopReason> {
+        self.check_aligned(gaddr, 4, false)?;
+        self.store(gaddr, val.to_le_bytes())
+    }
+
+    fn set_u64(&mut self, gaddr: GuestAddr, val: u64) -> Result<(), StopReason> {
+        self.check_aligned(gaddr, 8, false)?;
+        self.store(gaddr, val.to_le_bytes())
     }
 
     fn pcrel(&self, imm: i64) -> u64 {
-        (self.state.get_pc() as i64 + imm) as u64
+        self.state.get_pc().wrapping_add(imm as u64)
+    }
+
+    // effective address of loads and stores
+    fn ea(&self, rs1: u8, imm: i32) -> u64 {
+        self.gx(rs1).wrapping_add(imm as i64 as u64)
     }
 
     fn gx(&self, idx: u8) -> u64 {
@@ -164,36 +408,84 @@ impl<'a> RvInterpreterExecutor<'a> {
         self.state.set_f64(idx, val)
     }
 
-    // returns None if successful exit
-    pub fn exec(&mut self, entry_pc: u64) -> Option<StopReason> {
+    /// Runs the guest from `entry_pc` until it exits or faults. The state is
+    /// left as of the instruction that stopped it.
+    /// Runs the guest process on this thread, its main one, and on the
+    /// threads it clones, until it is done. Returns how the process ended:
+    /// `Exit` once it has called exit_group(2) or its last thread has
+    /// exited, or the fault or signal that killed it. Printing that and
+    /// exiting the host process are left to the caller.
+    pub fn run(&mut self, entry_pc: u64) -> StopReason {
+        let reason = self.exec(entry_pc);
+        self.thread_stopped(reason);
+
+        let exit = self.threads.exit.lock().unwrap();
+        let exit = self
+            .threads
+            .exited
+            .wait_while(exit, |x| x.is_none())
+            .unwrap();
+        exit.clone().unwrap()
+    }
+
+    /// Runs this thread until it stops.
+    pub fn exec(&mut self, entry_pc: u64) -> StopReason {
         self.state.set_pc(entry_pc);
+        if self.dispatch == Dispatch::Blocks && !self.debug {
+            return self.exec_blocks();
+        }
 
         loop {
+            if self.signal_pending() {
+                if let Some(x) = self.deliver_pending() {
+                    return x;
+                }
+            }
+
             let x = self.exec_one();
             match x {
                 StopReason::Next | StopReason::ContinueAt(_) => {}
-                _ => return Some(x),
+                StopReason::Segv { .. }
+                | StopReason::Misaligned { .. }
+                | StopReason::ReservedInsn
+                | StopReason::Break => {
+                    if let Some(x) = self.deliver_fault(x) {
+                        return x;
+                    }
+                }
+                _ => return x,
             }
         }
     }
 
-    fn fetch_insn(&self) -> Result<(RvInsn, usize), StopReason> {
+    fn fetch_insn(&mut self) -> Result<(RvInsn, usize), StopReason> {
         let pc = self.state.get_pc();
         if self.debug {
             println!("pc = {:016x}", pc);
         }
+        if let Some(x) = self.icache.get(self.mmu.code_generation(), pc) {
+            return Ok(x);
+        }
+        let (insn, len) = self.decode_at(pc)?;
+        self.icache.insert(pc, insn, len);
+        Ok((insn, len))
+    }
 
+    fn decode_at(&self, pc: u64) -> Result<(RvInsn, usize), StopReason> {
         // XXX: this is duplicating code from decoder, ideally decoder will
         // handle all of this
-        let lsb_byte = self.get_u8(pc.into())?;
-        if lsb_byte & 0b11 == 0b11 {
+        //
+        // instructions are fetched in halfwords, so that each half of one
+        // straddling a page boundary is checked
+        let lo = self.fetch_u16(pc.into())?;
+        if lo & 0b11 == 0b11 {
             // 32-bit
-            let insn_word = self.get_u32(pc.into())?;
+            let hi = self.fetch_u16((pc + 2).into())?;
+            let insn_word = lo as u32 | (hi as u32) << 16;
             Ok((self.decoder.disas_32bit(insn_word), 4))
         } else {
             // 16-bit
-            let insn_word = self.get_u16(pc.into())?;
-            Ok((self.decoder.disas_16bit(insn_word), 2))
+            Ok((self.decoder.disas_16bit(lo), 2))
         }
     }
 
@@ -208,12 +500,13 @@ impl<'a> RvInterpreterExecutor<'a> {
 
         let res = self.interpret_one(&insn, len);
 
-        let new_pc = if let StopReason::ContinueAt(x) = res {
-            x
-        } else {
-            self.state.get_pc() + (len as u64)
-        };
-        self.state.set_pc(new_pc);
+        // faulting instructions leave pc alone, for the signal frame
+        match res {
+            StopReason::Next => self.state.set_pc(self.state.get_pc() + len as u64),
+            StopReason::ContinueAt(x) => self.state.set_pc(x),
+            _ => return res,
+        }
+        self.instret += 1;
 
         res
     }
@@ -235,12 +528,13 @@ impl<'a> RvInterpreterExecutor<'a> {
             RvInsn::Jal(a) => {
                 let pc = self.state.get_pc();
                 self.sx(a.rd, pc + insn_len as u64);
-                StopReason::ContinueAt((pc as i64 + a.imm as i64) as u64)
+                StopReason::ContinueAt(pc.wrapping_add(a.imm as i64 as u64))
             }
             RvInsn::Jalr(a) => {
-                let pc = self.state.get_pc();
-                self.sx(a.rd, pc + insn_len as u64);
-                StopReason::ContinueAt((self.gx(a.rs1) as i64 + a.imm as i64) as u64)
+                // rs1 may be rd
+                let target = self.ea(a.rs1, a.imm) & !1;
+                self.sx(a.rd, self.state.get_pc() + insn_len as u64);
+                StopReason::ContinueAt(target)
             }
             RvInsn::Beq(a) => {
                 let v1 = self.gx(a.rs1);
@@ -291,8 +585,8 @@ impl<'a> RvInterpreterExecutor<'a> {
             }
             RvInsn::Bgeu(a) => {
                 // TODO: dedup
-                let v1 = self.gx(a.rs1) as i64;
-                let v2 = self.gx(a.rs2) as i64;
+                let v1 = self.gx(a.rs1);
+                let v2 = self.gx(a.rs2);
                 if v1 >= v2 {
                     StopReason::ContinueAt(self.pcrel(a.imm as i64))
                 } else {
@@ -300,7 +594,7 @@ impl<'a> RvInterpreterExecutor<'a> {
                 }
             }
             RvInsn::Lb(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 match self.get_u8(addr.into()) {
                     Ok(v) => {
                         self.sx(a.rd, sext_u8(v));
@@ -310,7 +604,7 @@ impl<'a> RvInterpreterExecutor<'a> {
                 }
             }
             RvInsn::Lh(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 match self.get_u16(addr.into()) {
                     Ok(v) => {
                         self.sx(a.rd, sext_u16(v));
@@ -320,7 +614,7 @@ impl<'a> RvInterpreterExecutor<'a> {
                 }
             }
             RvInsn::Lw(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 match self.get_u32(addr.into()) {
                     Ok(v) => {
                         self.sx(a.rd, sext_u32(v));
@@ -330,7 +624,7 @@ impl<'a> RvInterpreterExecutor<'a> {
                 }
             }
             RvInsn::Lbu(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 match self.get_u8(addr.into()) {
                     Ok(v) => {
                         self.sx(a.rd, v as u64);
@@ -340,7 +634,7 @@ impl<'a> RvInterpreterExecutor<'a> {
                 }
             }
             RvInsn::Lhu(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 match self.get_u16(addr.into()) {
                     Ok(v) => {
                         self.sx(a.rd, v as u64);
@@ -350,26 +644,26 @@ impl<'a> RvInterpreterExecutor<'a> {
                 }
             }
             RvInsn::Sb(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 self.set_u8(addr.into(), self.gx(a.rs2) as u8)
                     .err()
                     .unwrap_or(StopReason::Next)
             }
             RvInsn::Sh(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 self.set_u16(addr.into(), self.gx(a.rs2) as u16)
                     .err()
                     .unwrap_or(StopReason::Next)
             }
             RvInsn::Sw(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 self.set_u32(addr.into(), self.gx(a.rs2) as u32)
                     .err()
                     .unwrap_or(StopReason::Next)
             }
             RvInsn::Addi(a) => {
-                let v = self.gx(a.rs1) as i64 + a.imm as i64;
-                self.sx(a.rd, v as u64);
+                let v = self.gx(a.rs1).wrapping_add(a.imm as i64 as u64);
+                self.sx(a.rd, v);
                 StopReason::Next
             }
             RvInsn::Slti(a) => {
@@ -421,13 +715,13 @@ impl<'a> RvInterpreterExecutor<'a> {
                 StopReason::Next
             }
             RvInsn::Add(a) => {
-                let v = self.gx(a.rs1) as i64 + self.gx(a.rs2) as i64;
-                self.sx(a.rd, v as u64);
+                let v = self.gx(a.rs1).wrapping_add(self.gx(a.rs2));
+                self.sx(a.rd, v);
                 StopReason::Next
             }
             RvInsn::Sub(a) => {
-                let v = self.gx(a.rs1) as i64 - self.gx(a.rs2) as i64;
-                self.sx(a.rd, v as u64);
+                let v = self.gx(a.rs1).wrapping_sub(self.gx(a.rs2));
+                self.sx(a.rd, v);
                 StopReason::Next
             }
             RvInsn::Sll(a) => {
@@ -483,9 +777,21 @@ impl<'a> RvInterpreterExecutor<'a> {
                 std::sync::atomic::fence(std::sync::atomic::Ordering::SeqCst);
                 StopReason::Next
             }
-            RvInsn::FenceI(_) => todo!(),
+            RvInsn::FenceI(_) => {
+                // stores by this thread already invalidate what they hit,
+                // but the guest may have written code by other means
+                self.icache.flush();
+                self.blocks.flush();
+                StopReason::Next
+            }
+            RvInsn::Csrrw(a) => self.do_csr_reg(a, CsrOp::Write),
+            RvInsn::Csrrs(a) => self.do_csr_reg(a, CsrOp::Set),
+            RvInsn::Csrrc(a) => self.do_csr_reg(a, CsrOp::Clear),
+            RvInsn::Csrrwi(a) => self.do_csr_imm(a, CsrOp::Write),
+            RvInsn::Csrrsi(a) => self.do_csr_imm(a, CsrOp::Set),
+            RvInsn::Csrrci(a) => self.do_csr_imm(a, CsrOp::Clear),
             RvInsn::Lwu(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 match self.get_u32(addr.into()) {
                     Ok(v) => {
                         self.sx(a.rd, v as u64);
@@ -495,7 +801,7 @@ impl<'a> RvInterpreterExecutor<'a> {
                 }
             }
             RvInsn::Ld(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 match self.get_u64(addr.into()) {
                     Ok(v) => {
                         self.sx(a.rd, v);
@@ -505,13 +811,13 @@ impl<'a> RvInterpreterExecutor<'a> {
                 }
             }
             RvInsn::Sd(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 self.set_u64(addr.into(), self.gx(a.rs2))
                     .err()
                     .unwrap_or(StopReason::Next)
             }
             RvInsn::Addiw(a) => {
-                let v = self.gx(a.rs1) as i32 + a.imm as i32;
+                let v = (self.gx(a.rs1) as i32).wrapping_add(a.imm);
                 self.sx(a.rd, v as i64 as u64);
                 StopReason::Next
             }
@@ -531,12 +837,12 @@ impl<'a> RvInterpreterExecutor<'a> {
                 StopReason::Next
             }
             RvInsn::Addw(a) => {
-                let v = self.gx(a.rs1) as i32 + self.gx(a.rs2) as i32;
+                let v = (self.gx(a.rs1) as i32).wrapping_add(self.gx(a.rs2) as i32);
                 self.sx(a.rd, v as i64 as u64);
                 StopReason::Next
             }
             RvInsn::Subw(a) => {
-                let v = self.gx(a.rs1) as i32 - self.gx(a.rs2) as i32;
+                let v = (self.gx(a.rs1) as i32).wrapping_sub(self.gx(a.rs2) as i32);
                 self.sx(a.rd, v as i64 as u64);
                 StopReason::Next
             }
@@ -555,212 +861,798 @@ impl<'a> RvInterpreterExecutor<'a> {
                 self.sx(a.rd, v as i64 as u64);
                 StopReason::Next
             }
-            RvInsn::Mul(a) => {
-                let v1 = self.gx(a.rs1) as i64;
-                let v2 = self.gx(a.rs2) as i64;
-                self.sx(a.rd, v1.wrapping_mul(v2) as u64);
-                StopReason::Next
-            }
-            RvInsn::Mulh(a) => {
-                let v1 = self.gx(a.rs1) as i64 as i128;
-                let v2 = self.gx(a.rs2) as i64 as i128;
-                let v = (v1 * v2) >> 64;
-                self.sx(a.rd, v as u64);
-                StopReason::Next
-            }
-            RvInsn::Mulhsu(a) => {
-                let v1 = self.gx(a.rs1) as i64 as i128;
-                let v2 = self.gx(a.rs1) as u64 as i128;
-                let v = (v1 * v2) >> 64;
-                self.sx(a.rd, v as u64);
-                StopReason::Next
-            }
-            RvInsn::Mulhu(a) => {
-                let v1 = self.gx(a.rs1);
-                let v2 = self.gx(a.rs2);
-                // let (_, v) = v1.widening_mul(v2);
-                let v = (v1 as u128 * v2 as u128) >> 64;
-                self.sx(a.rd, v as u64);
-                StopReason::Next
-            }
-            RvInsn::Div(a) => {
-                let v1 = self.gx(a.rs1) as i64;
-                let v2 = self.gx(a.rs2) as i64;
-                self.sx(a.rd, v1.wrapping_div(v2) as u64);
-                StopReason::Next
-            }
-            RvInsn::Divu(a) => {
-                let v1 = self.gx(a.rs1);
-                let v2 = self.gx(a.rs2);
-                self.sx(a.rd, v1.wrapping_div(v2));
-                StopReason::Next
-            }
-            RvInsn::Rem(a) => {
-                let v1 = self.gx(a.rs1) as i64;
-                let v2 = self.gx(a.rs2) as i64;
-                self.sx(a.rd, v1.wrapping_rem(v2) as u64);
-                StopReason::Next
-            }
-            RvInsn::Remu(a) => {
-                let v1 = self.gx(a.rs1);
-                let v2 = self.gx(a.rs2);
-                self.sx(a.rd, v1.wrapping_rem(v2));
-                StopReason::Next
-            }
-            RvInsn::Mulw(a) => {
-                let v1 = self.gx(a.rs1) as i32;
-                let v2 = self.gx(a.rs2) as i32;
-                self.sx(a.rd, v1.wrapping_mul(v2) as i64 as u64);
-                StopReason::Next
-            }
-            RvInsn::Divw(a) => {
-                let v1 = self.gx(a.rs1) as i32;
-                let v2 = self.gx(a.rs2) as i32;
-                self.sx(a.rd, v1.wrapping_div(v2) as i64 as u64);
-                StopReason::Next
-            }
-            RvInsn::Divuw(a) => {
-                let v1 = self.gx(a.rs1) as u32;
-                let v2 = self.gx(a.rs2) as u32;
-                self.sx(a.rd, v1.wrapping_div(v2) as u64);
-                StopReason::Next
-            }
-            RvInsn::Remw(a) => {
-                let v1 = self.gx(a.rs1) as i32;
-                let v2 = self.gx(a.rs2) as i32;
-                self.sx(a.rd, v1.wrapping_rem(v2) as i64 as u64);
-                StopReason::Next
-            }
-            RvInsn::Remuw(a) => {
-                let v1 = self.gx(a.rs1) as u32;
-                let v2 = self.gx(a.rs2) as u32;
-                self.sx(a.rd, v1.wrapping_rem(v2) as u64);
-                StopReason::Next
-            }
-            RvInsn::LrW(_) => todo!(),
-            RvInsn::ScW(_) => todo!(),
-            RvInsn::AmoSwapW(_) => todo!(),
-            RvInsn::AmoAddW(_) => todo!(),
-            RvInsn::AmoXorW(_) => todo!(),
-            RvInsn::AmoAndW(_) => todo!(),
-            RvInsn::AmoOrW(_) => todo!(),
-            RvInsn::AmoMinW(_) => todo!(),
-            RvInsn::AmoMaxW(_) => todo!(),
-            RvInsn::AmoMinuW(_) => todo!(),
-            RvInsn::AmoMaxuW(_) => todo!(),
-            RvInsn::LrD(_) => todo!(),
-            RvInsn::ScD(_) => todo!(),
-            RvInsn::AmoSwapD(_) => todo!(),
-            RvInsn::AmoAddD(_) => todo!(),
-            RvInsn::AmoXorD(_) => todo!(),
-            RvInsn::AmoAndD(_) => todo!(),
-            RvInsn::AmoOrD(_) => todo!(),
-            RvInsn::AmoMinD(_) => todo!(),
-            RvInsn::AmoMaxD(_) => todo!(),
-            RvInsn::AmoMinuD(_) => todo!(),
-            RvInsn::AmoMaxuD(_) => todo!(),
+            RvInsn::Mul(a) => self.do_muldiv(a, MulDivOp::Mul),
+            RvInsn::Mulh(a) => self.do_muldiv(a, MulDivOp::Mulh),
+            RvInsn::Mulhsu(a) => self.do_muldiv(a, MulDivOp::Mulhsu),
+            RvInsn::Mulhu(a) => self.do_muldiv(a, MulDivOp::Mulhu),
+            RvInsn::Div(a) => self.do_muldiv(a, MulDivOp::Div),
+            RvInsn::Divu(a) => self.do_muldiv(a, MulDivOp::Divu),
+            RvInsn::Rem(a) => self.do_muldiv(a, MulDivOp::Rem),
+            RvInsn::Remu(a) => self.do_muldiv(a, MulDivOp::Remu),
+            RvInsn::Mulw(a) => self.do_muldiv(a, MulDivOp::Mulw),
+            RvInsn::Divw(a) => self.do_muldiv(a, MulDivOp::Divw),
+            RvInsn::Divuw(a) => self.do_muldiv(a, MulDivOp::Divuw),
+            RvInsn::Remw(a) => self.do_muldiv(a, MulDivOp::Remw),
+            RvInsn::Remuw(a) => self.do_muldiv(a, MulDivOp::Remuw),
+            RvInsn::LrW(a) => self.do_lr_w(a),
+            RvInsn::ScW(a) => self.do_sc_w(a),
+            RvInsn::AmoSwapW(a) => self.do_amo_w(a, AmoOp::Swap),
+            RvInsn::AmoAddW(a) => self.do_amo_w(a, AmoOp::Add),
+            RvInsn::AmoXorW(a) => self.do_amo_w(a, AmoOp::Xor),
+            RvInsn::AmoAndW(a) => self.do_amo_w(a, AmoOp::And),
+            RvInsn::AmoOrW(a) => self.do_amo_w(a, AmoOp::Or),
+            RvInsn::AmoMinW(a) => self.do_amo_w(a, AmoOp::Min),
+            RvInsn::AmoMaxW(a) => self.do_amo_w(a, AmoOp::Max),
+            RvInsn::AmoMinuW(a) => self.do_amo_w(a, AmoOp::Minu),
+            RvInsn::AmoMaxuW(a) => self.do_amo_w(a, AmoOp::Maxu),
+            RvInsn::LrD(a) => self.do_lr_d(a),
+            RvInsn::ScD(a) => self.do_sc_d(a),
+            RvInsn::AmoSwapD(a) => self.do_amo_d(a, AmoOp::Swap),
+            RvInsn::AmoAddD(a) => self.do_amo_d(a, AmoOp::Add),
+            RvInsn::AmoXorD(a) => self.do_amo_d(a, AmoOp::Xor),
+            RvInsn::AmoAndD(a) => self.do_amo_d(a, AmoOp::And),
+            RvInsn::AmoOrD(a) => self.do_amo_d(a, AmoOp::Or),
+            RvInsn::AmoMinD(a) => self.do_amo_d(a, AmoOp::Min),
+            RvInsn::AmoMaxD(a) => self.do_amo_d(a, AmoOp::Max),
+            RvInsn::AmoMinuD(a) => self.do_amo_d(a, AmoOp::Minu),
+            RvInsn::AmoMaxuD(a) => self.do_amo_d(a, AmoOp::Maxu),
             RvInsn::Flw(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 match self.get_u32(addr.into()) {
                     Ok(v) => {
-                        self.sf32(a.rd, v as f32);
+                        self.state.set_f32_bits(a.rd, v);
                         StopReason::Next
                     }
                     Err(e) => e,
                 }
             }
             RvInsn::Fsw(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
-                self.set_u32(addr.into(), self.gf32(a.rs2) as u32)
+                let addr = self.ea(a.rs1, a.imm);
+                self.set_u32(addr.into(), self.state.get_f_bits(a.rs2) as u32)
                     .err()
                     .unwrap_or(StopReason::Next)
             }
-            RvInsn::FmaddS(_) => todo!(),
-            RvInsn::FmsubS(_) => todo!(),
-            RvInsn::FnmsubS(_) => todo!(),
-            RvInsn::FnmaddS(_) => todo!(),
-            RvInsn::FaddS(_) => todo!(),
-            RvInsn::FsubS(_) => todo!(),
-            RvInsn::FmulS(_) => todo!(),
-            RvInsn::FdivS(_) => todo!(),
-            RvInsn::FsqrtS(_) => todo!(),
-            RvInsn::FsgnjS(_) => todo!(),
-            RvInsn::FsgnjnS(_) => todo!(),
-            RvInsn::FsgnjxS(_) => todo!(),
-            RvInsn::FminS(_) => todo!(),
-            RvInsn::FmaxS(_) => todo!(),
-            RvInsn::FcvtWS(_) => todo!(),
-            RvInsn::FcvtWuS(_) => todo!(),
+            RvInsn::FmaddS(a) => self.fop3(&F32, a, softfp::fmadd),
+            RvInsn::FmsubS(a) => self.fop3(&F32, a, softfp::fmsub),
+            RvInsn::FnmsubS(a) => self.fop3(&F32, a, softfp::fnmsub),
+            RvInsn::FnmaddS(a) => self.fop3(&F32, a, softfp::fnmadd),
+            RvInsn::FaddS(a) => self.fop2(&F32, a, softfp::add),
+            RvInsn::FsubS(a) => self.fop2(&F32, a, softfp::sub),
+            RvInsn::FmulS(a) => self.fop2(&F32, a, softfp::mul),
+            RvInsn::FdivS(a) => self.fop2(&F32, a, softfp::div),
+            RvInsn::FsqrtS(a) => self.fsqrt(&F32, a),
+            RvInsn::FsgnjS(a) => self.fsop_s(a, fsgnj_f32),
+            RvInsn::FsgnjnS(a) => self.fsop_s(a, fsgnjn_f32),
+            RvInsn::FsgnjxS(a) => self.fsop_s(a, fsgnjx_f32),
+            RvInsn::FminS(a) => self.fminmax_s(a, fmin_f32),
+            RvInsn::FmaxS(a) => self.fminmax_s(a, fmax_f32),
+            RvInsn::FcvtWS(a) => self.fcvt_int(&F32, a, IntFmt::W),
+            RvInsn::FcvtWuS(a) => self.fcvt_int(&F32, a, IntFmt::Wu),
             RvInsn::FmvXW(a) => {
-                self.sx(a.rd, self.gf32(a.rs1) as i32 as i64 as u64);
+                self.sx(a.rd, sext_u32(self.state.get_f_bits(a.rs1) as u32));
                 StopReason::Next
             }
-            RvInsn::FeqS(_) => todo!(),
-            RvInsn::FltS(_) => todo!(),
-            RvInsn::FleS(_) => todo!(),
-            RvInsn::FclassS(_) => todo!(),
-            RvInsn::FcvtSW(_) => todo!(),
-            RvInsn::FcvtSWu(_) => todo!(),
+            RvInsn::FeqS(a) => self.fcmp_s(a, f32::eq, false),
+            RvInsn::FltS(a) => self.fcmp_s(a, f32::lt, true),
+            RvInsn::FleS(a) => self.fcmp_s(a, f32::le, true),
+            RvInsn::FclassS(a) => self.fclass_s(a),
+            RvInsn::FcvtSW(a) => self.fcvt_from_int(&F32, a, IntFmt::W),
+            RvInsn::FcvtSWu(a) => self.fcvt_from_int(&F32, a, IntFmt::Wu),
             RvInsn::FmvWX(a) => {
-                self.sf32(a.rd, self.gx(a.rs1) as u32 as f32);
+                self.state.set_f32_bits(a.rd, self.gx(a.rs1) as u32);
                 StopReason::Next
             }
-            RvInsn::FcvtLS(_) => todo!(),
-            RvInsn::FcvtLuS(_) => todo!(),
-            RvInsn::FcvtSL(_) => todo!(),
-            RvInsn::FcvtSLu(_) => todo!(),
+            RvInsn::FcvtLS(a) => self.fcvt_int(&F32, a, IntFmt::L),
+            RvInsn::FcvtLuS(a) => self.fcvt_int(&F32, a, IntFmt::Lu),
+            RvInsn::FcvtSL(a) => self.fcvt_from_int(&F32, a, IntFmt::L),
+            RvInsn::FcvtSLu(a) => self.fcvt_from_int(&F32, a, IntFmt::Lu),
             RvInsn::Fld(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
+                let addr = self.ea(a.rs1, a.imm);
                 match self.get_u64(addr.into()) {
                     Ok(v) => {
-                        self.sf64(a.rd, v as f64);
+                        self.state.set_f_bits(a.rd, v);
                         StopReason::Next
                     }
                     Err(e) => e,
                 }
             }
             RvInsn::Fsd(a) => {
-                let addr = (self.gx(a.rs1) as i64 + a.imm as i64) as u64;
-                self.set_u64(addr.into(), self.gf64(a.rs2) as u64)
+                let addr = self.ea(a.rs1, a.imm);
+                self.set_u64(addr.into(), self.state.get_f_bits(a.rs2))
                     .err()
                     .unwrap_or(StopReason::Next)
             }
-            RvInsn::FmaddD(_) => todo!(),
-            RvInsn::FmsubD(_) => todo!(),
-            RvInsn::FnmsubD(_) => todo!(),
-            RvInsn::FnmaddD(_) => todo!(),
-            RvInsn::FaddD(_) => todo!(),
-            RvInsn::FsubD(_) => todo!(),
-            RvInsn::FmulD(_) => todo!(),
-            RvInsn::FdivD(_) => todo!(),
-            RvInsn::FsqrtD(_) => todo!(),
-            RvInsn::FsgnjD(_) => todo!(),
-            RvInsn::FsgnjnD(_) => todo!(),
-            RvInsn::FsgnjxD(_) => todo!(),
-            RvInsn::FminD(_) => todo!(),
-            RvInsn::FmaxD(_) => todo!(),
-            RvInsn::FcvtSD(_) => todo!(),
-            RvInsn::FcvtDS(_) => todo!(),
-            RvInsn::FeqD(_) => todo!(),
-            RvInsn::FltD(_) => todo!(),
-            RvInsn::FleD(_) => todo!(),
-            RvInsn::FclassD(_) => todo!(),
-            RvInsn::FcvtWD(_) => todo!(),
-            RvInsn::FcvtWuD(_) => todo!(),
-            RvInsn::FcvtDW(_) => todo!(),
-            RvInsn::FcvtDWu(_) => todo!(),
-            RvInsn::FcvtLD(_) => todo!(),
-            RvInsn::FcvtLuD(_) => todo!(),
+            RvInsn::FmaddD(a) => self.fop3(&F64, a, softfp::fmadd),
+            RvInsn::FmsubD(a) => self.fop3(&F64, a, softfp::fmsub),
+            RvInsn::FnmsubD(a) => self.fop3(&F64, a, softfp::fnmsub),
+            RvInsn::FnmaddD(a) => self.fop3(&F64, a, softfp::fnmadd),
+            RvInsn::FaddD(a) => self.fop2(&F64, a, softfp::add),
+            RvInsn::FsubD(a) => self.fop2(&F64, a, softfp::sub),
+            RvInsn::FmulD(a) => self.fop2(&F64, a, softfp::mul),
+            RvInsn::FdivD(a) => self.fop2(&F64, a, softfp::div),
+            RvInsn::FsqrtD(a) => self.fsqrt(&F64, a),
+            RvInsn::FsgnjD(a) => self.fsop_d(a, fsgnj_f64),
+            RvInsn::FsgnjnD(a) => self.fsop_d(a, fsgnjn_f64),
+            RvInsn::FsgnjxD(a) => self.fsop_d(a, fsgnjx_f64),
+            RvInsn::FminD(a) => self.fminmax_d(a, fmin_f64),
+            RvInsn::FmaxD(a) => self.fminmax_d(a, fmax_f64),
+            RvInsn::FcvtSD(a) => self.fcvt_s_d(a),
+            RvInsn::FcvtDS(a) => self.fcvt_d_s(a),
+            RvInsn::FeqD(a) => self.fcmp_d(a, f64::eq, false),
+            RvInsn::FltD(a) => self.fcmp_d(a, f64::lt, true),
+            RvInsn::FleD(a) => self.fcmp_d(a, f64::le, true),
+            RvInsn::FclassD(a) => self.fclass_d(a),
+            RvInsn::FcvtWD(a) => self.fcvt_int(&F64, a, IntFmt::W),
+            RvInsn::FcvtWuD(a) => self.fcvt_int(&F64, a, IntFmt::Wu),
+            RvInsn::FcvtDW(a) => self.fcvt_from_int(&F64, a, IntFmt::W),
+            RvInsn::FcvtDWu(a) => self.fcvt_from_int(&F64, a, IntFmt::Wu),
+            RvInsn::FcvtLD(a) => self.fcvt_int(&F64, a, IntFmt::L),
+            RvInsn::FcvtLuD(a) => self.fcvt_int(&F64, a, IntFmt::Lu),
             RvInsn::FmvXD(a) => {
-                self.sx(a.rd, self.gf64(a.rs1) as u64);
+                self.sx(a.rd, self.state.get_f_bits(a.rs1));
                 StopReason::Next
             }
-            RvInsn::FcvtDL(_) => todo!(),
-            RvInsn::FcvtDLu(_) => todo!(),
+            RvInsn::FcvtDL(a) => self.fcvt_from_int(&F64, a, IntFmt::L),
+            RvInsn::FcvtDLu(a) => self.fcvt_from_int(&F64, a, IntFmt::Lu),
             RvInsn::FmvDX(a) => {
-                self.sf64(a.rd, self.gx(a.rs1) as f64);
+                self.state.set_f_bits(a.rd, self.gx(a.rs1));
                 StopReason::Next
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::mem::{AddressSpace, Placement, Prot};
+    use crate::rv::{CsrArgs, CsrImmArgs};
+
+    // runs a sequence of 32-bit instructions, returning how it stopped
+    fn run(code: &[u32], state: &mut RvIsaState) -> StopReason {
+        let mmu = GuestMmu::new(4096);
+        let gaddr = mmu
+            .consume_host(code.as_ptr() as *const u8, code.len() * 4)
+            .unwrap();
+        let mut executor = RvInterpreterExecutor::new(64, state.clone(), Arc::new(mmu));
+        let reason = executor.exec(gaddr.as_u64());
+        *state = executor.state().clone();
+        reason
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut state = RvIsaState::default();
+        // li a0, 42; li a7, 94; ecall
+        let code = [0x02a0_0513, 0x05e0_0893, 0x0000_0073];
+        assert!(matches!(run(&code, &mut state), StopReason::Exit(42)));
+        assert_eq!(state.get_x(10), 42);
+
+        // li a0, 3; li a7, 93; ecall
+        let code = [0x0030_0513, 0x05d0_0893, 0x0000_0073];
+        assert!(matches!(run(&code, &mut state), StopReason::ExitThread(3)));
+    }
+
+    #[test]
+    fn test_syscall_efault() {
+        let mut state = RvIsaState::default();
+        // write(1, 8, 4), then exit_group with its result
+        let code = [
+            0x0010_0513,
+            0x0080_0593,
+            0x0040_0613,
+            0x0400_0893,
+            0x0000_0073,
+            0x05e0_0893,
+            0x0000_0073,
+        ];
+        assert!(matches!(
+            run(&code, &mut state),
+            StopReason::Exit(x) if x == -libc::EFAULT
+        ));
+    }
+
+    // the guest sees a thread gone as soon as its TID is cleared, but the
+    // host thread still has to account for its exit
+    fn wait_for_threads(executor: &RvInterpreterExecutor) {
+        while executor.threads.live.load(Ordering::SeqCst) > 1 {
+            std::thread::yield_now();
+        }
+    }
+
+    // a thread is cloned and exits at once, while the parent waits for
+    // its TID at s0 to be cleared like pthread_join does
+    const CLONE_JOIN: [u32; 23] = [
+        0x0031_1537, // lui a0, 0x311 (VM|FS|FILES|SIGHAND|THREAD|SETTID|CLEARTID)
+        0xf005_0513, // addi a0, a0, -256
+        0x0000_0593, // li a1, 0
+        0x0004_0613, // mv a2, s0
+        0x0000_0693, // li a3, 0
+        0x0004_0713, // mv a4, s0
+        0x0dc0_0893, // li a7, 220
+        0x0000_0073, // ecall
+        0x0005_1863, // bnez a0, 1f
+        0x0000_0513, // li a0, 0
+        0x05d0_0893, // li a7, 93
+        0x0000_0073, // ecall
+        0x0004_2603, // 1: lw a2, 0(s0)
+        0x0006_0e63, // beqz a2, 2f
+        0x0004_0513, // mv a0, s0
+        0x0000_0593, // li a1, 0
+        0x0000_0693, // li a3, 0
+        0x0620_0893, // li a7, 98
+        0x0000_0073, // ecall
+        0xfe5f_f06f, // j 1b
+        0x02a0_0513, // 2: li a0, 42
+        0x05e0_0893, // li a7, 94
+        0x0000_0073, // ecall
+    ];
+
+    #[test]
+    fn test_bgeu_unsigned() {
+        let code = [
+            0x0010_0513, // li a0, 1
+            0x0094_7463, // bgeu s0, s1, 1f
+            0x0020_0513, // li a0, 2
+            0x05e0_0893, // 1: li a7, 94
+            0x0000_0073, // ecall
+        ];
+        // taken exactly when s0 >= s1 as unsigned numbers
+        for (s0, s1, taken) in [(u64::MAX, 1, true), (1, u64::MAX, false), (5, 5, true)] {
+            let mut state = RvIsaState::default();
+            state.set_x(8, s0);
+            state.set_x(9, s1);
+            let want = if taken { 1 } else { 2 };
+            assert!(matches!(run(&code, &mut state), StopReason::Exit(x) if x == want));
+        }
+    }
+
+    #[test]
+    fn test_wrapping_arith() {
+        let code = [
+            0x0094_0533, // add a0, s0, s1
+            0x0014_0593, // addi a1, s0, 1
+            0x4095_0633, // sub a2, a0, s1
+            0x0099_06bb, // addw a3, s2, s1
+            0x0019_071b, // addiw a4, s2, 1
+            0x4097_07bb, // subw a5, a4, s1
+            0x0014_0803, // lb a6, 1(s0)
+        ];
+        let mut state = RvIsaState::default();
+        state.set_x(8, i64::MAX as u64);
+        state.set_x(9, 1);
+        state.set_x(18, i32::MAX as u64);
+        // overflow wraps around instead of panicking, and so does the
+        // address of the load, which then faults
+        assert!(matches!(run(&code, &mut state), StopReason::Segv { .. }));
+        assert_eq!(state.get_x(10), i64::MIN as u64);
+        assert_eq!(state.get_x(11), i64::MIN as u64);
+        assert_eq!(state.get_x(12), i64::MAX as u64);
+        assert_eq!(state.get_x(13), i32::MIN as u64);
+        assert_eq!(state.get_x(14), i32::MIN as u64);
+        assert_eq!(state.get_x(15), i32::MAX as u64);
+    }
+
+    #[test]
+    fn test_jalr() {
+        let code = [
+            0x0000_0517, // auipc a0, 0
+            0x00d5_0567, // jalr a0, 13(a0)
+            0x0000_0513, // li a0, 0
+            0x0000_0597, // 1: auipc a1, 0
+            0x40a5_8533, // sub a0, a1, a0
+            0x05e0_0893, // li a7, 94
+            0x0000_0073, // ecall
+        ];
+        // the target is computed from rs1 before rd is written, and its
+        // low bit is cleared, so this lands at 1b with a0 = 1b - 4
+        let mut state = RvIsaState::default();
+        assert!(matches!(run(&code, &mut state), StopReason::Exit(4)));
+    }
+
+    #[test]
+    fn test_csr() {
+        let mmu = Arc::new(GuestMmu::new(4096));
+        let mut executor = RvInterpreterExecutor::new(64, RvIsaState::default(), mmu);
+        let reg = |csr: u16, rd, rs1| CsrArgs {
+            csr: csr.into(),
+            rd,
+            rs1,
+        };
+        let imm = |csr: u16, rd, uimm| CsrImmArgs {
+            csr: csr.into(),
+            rd,
+            uimm,
+        };
+
+        // fcsr is frm and fflags together, and each can be reached alone
+        executor.state.set_x(5, 0xff);
+        executor.state.set_x(6, 0b110);
+        let mut exec = |insn| executor.interpret_one(&insn, 4);
+        let r = [
+            exec(RvInsn::Csrrw(reg(0x003, 10, 5))),
+            exec(RvInsn::Csrrc(reg(0x002, 11, 6))),
+            exec(RvInsn::Csrrs(reg(0x001, 12, 0))),
+            exec(RvInsn::Csrrci(imm(0x001, 13, 0x1f))),
+            exec(RvInsn::Csrrsi(imm(0x002, 14, 0b010))),
+            exec(RvInsn::Csrrwi(imm(0x003, 15, 0x05))),
+        ];
+        assert!(r.iter().all(|r| matches!(r, StopReason::Next)));
+        let x: Vec<u64> = (10..16).map(|i| executor.state.get_x(i)).collect();
+        assert_eq!(x, [0, 0b111, 0x1f, 0x1f, 0b001, 0b011 << 5]);
+        assert_eq!(executor.state.get_fcsr(), 0x05);
+
+        // the counters can only be read
+        executor.instret = 7;
+        assert!(matches!(
+            executor.interpret_one(&RvInsn::Csrrs(reg(0xc02, 10, 0)), 4),
+            StopReason::Next
+        ));
+        assert_eq!(executor.state.get_x(10), 7);
+        assert!(matches!(
+            executor.interpret_one(&RvInsn::Csrrsi(imm(0xc00, 10, 0)), 4),
+            StopReason::Next
+        ));
+        for insn in [
+            RvInsn::Csrrw(reg(0xc00, 10, 0)),
+            RvInsn::Csrrs(reg(0xc01, 10, 5)),
+            RvInsn::Csrrci(imm(0xc02, 10, 1)),
+        ] {
+            assert!(matches!(
+                executor.interpret_one(&insn, 4),
+                StopReason::ReservedInsn
+            ));
+        }
+
+        // and the rest are illegal instructions, even to read
+        assert!(matches!(
+            executor.interpret_one(&RvInsn::Csrrs(reg(0x300, 10, 0)), 4),
+            StopReason::ReservedInsn
+        ));
+        let mut state = RvIsaState::default();
+        // csrr a0, mstatus
+        assert!(matches!(
+            run(&[0x3000_2573], &mut state),
+            StopReason::ReservedInsn
+        ));
+    }
+
+    #[test]
+    fn test_clone_join() {
+        let code = CLONE_JOIN;
+        let mut tid = [0u32; 1];
+
+        let mmu = GuestMmu::new(4096);
+        let gaddr = mmu
+            .consume_host(code.as_ptr() as *const u8, code.len() * 4)
+            .unwrap();
+        let tid_addr = mmu
+            .consume_host_mut(tid.as_mut_ptr() as *mut u8, 4)
+            .unwrap();
+        let mut state = RvIsaState::default();
+        state.set_x(8, tid_addr.as_u64());
+
+        let mut executor = RvInterpreterExecutor::new(64, state, Arc::new(mmu));
+        assert!(matches!(
+            executor.exec(gaddr.as_u64()),
+            StopReason::Exit(42)
+        ));
+        assert_eq!(tid[0], 0);
+        wait_for_threads(&executor);
+        assert!(executor.thread_exited());
+    }
+
+    #[test]
+    fn test_exit_group_kills_threads() {
+        // the cloned thread spins until exit_group in the parent kills it
+        let code: [u32; 10] = [
+            0x0001_1537, // lui a0, 0x11 (VM|FS|FILES|SIGHAND|THREAD)
+            0xf005_0513, // addi a0, a0, -256
+            0x0000_0593, // li a1, 0
+            0x0dc0_0893, // li a7, 220
+            0x0000_0073, // ecall
+            0x0005_1463, // bnez a0, 1f
+            0x0000_006f, // j .
+            0x02a0_0513, // 1: li a0, 42
+            0x05e0_0893, // li a7, 94
+            0x0000_0073, // ecall
+        ];
+        let mmu = GuestMmu::new(4096);
+        let gaddr = mmu
+            .consume_host(code.as_ptr() as *const u8, code.len() * 4)
+            .unwrap();
+        let mut executor = RvInterpreterExecutor::new(64, RvIsaState::default(), Arc::new(mmu));
+        assert!(matches!(executor.run(gaddr.as_u64()), StopReason::Exit(42)));
+        while executor.threads.live.load(Ordering::SeqCst) > 0 {
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn test_clone_join_guest_base() {
+        // the same, with the TID word at a guest address that is not the
+        // host one, so that the futexes must be translated to meet
+        let mmu =
+            GuestMmu::with_address_space(4096, AddressSpace::GuestBase { size: 1 << 32 }).unwrap();
+        let rwx = Prot {
+            read: true,
+            write: true,
+            exec: true,
+        };
+        let g = mmu
+            .mmap_at(0.into(), 4096, rwx, Placement::Hint, 0, None)
+            .unwrap();
+        let bytes: Vec<u8> = CLONE_JOIN.iter().flat_map(|x| x.to_le_bytes()).collect();
+        mmu.write_bytes(g, &bytes).unwrap();
+        let tid_addr = mmu.mmap(4096, false).unwrap();
+        let mmu = Arc::new(mmu);
+        let mut state = RvIsaState::default();
+        state.set_x(8, tid_addr.as_u64());
+
+        let mut executor = RvInterpreterExecutor::new(64, state, mmu.clone());
+        assert!(matches!(executor.exec(g.as_u64()), StopReason::Exit(42)));
+        let mut tid = [0xffu8; 4];
+        mmu.read_bytes(tid_addr, &mut tid).unwrap();
+        assert_eq!(tid, [0; 4]);
+        wait_for_threads(&executor);
+        assert!(executor.thread_exited());
+    }
+
+    #[test]
+    fn test_signal_handler() {
+        // ebreak raises SIGTRAP, whose handler skips it and makes the
+        // interrupted code see the signal number in a0
+        let code: [u32; 15] = [
+            0x0050_0513, // li a0, 5 (SIGTRAP)
+            0x0004_8593, // mv a1, s1
+            0x0000_0613, // li a2, 0
+            0x0080_0693, // li a3, 8
+            0x0860_0893, // li a7, 134
+            0x0000_0073, // ecall
+            0x0000_0513, // li a0, 0
+            0x0010_0073, // ebreak
+            0x05e0_0893, // li a7, 94
+            0x0000_0073, // ecall
+            0x0b06_3283, // handler: ld t0, 176(a2)
+            0x0042_8293, // addi t0, t0, 4
+            0x0a56_3823, // sd t0, 176(a2)
+            0x10a6_3023, // sd a0, 256(a2)
+            0x0000_8067, // ret
+        ];
+        let mmu = GuestMmu::new(4096);
+        let gaddr = mmu
+            .consume_host(code.as_ptr() as *const u8, code.len() * 4)
+            .unwrap();
+        // struct sigaction
+        let act = [gaddr.as_u64() + 40, 0, 0];
+        let act_addr = mmu
+            .consume_host(act.as_ptr() as *const u8, act.len() * 8)
+            .unwrap();
+        let mut state = RvIsaState::default();
+        state.set_x(9, act_addr.as_u64());
+
+        let mut executor = RvInterpreterExecutor::new(64, state, Arc::new(mmu));
+        executor.stack(16384).unwrap();
+        assert!(matches!(
+            executor.exec(gaddr.as_u64()),
+            StopReason::Exit(x) if x == libc::SIGTRAP
+        ));
+
+        // without a handler, the fault stops the guest at the ebreak
+        let mut state = RvIsaState::default();
+        assert!(matches!(run(&code[6..8], &mut state), StopReason::Break));
+        assert_eq!(state.get_pc(), code[7..].as_ptr() as u64);
+    }
+
+    #[test]
+    fn test_sa_restart() {
+        // as if the ecall at `ecall` had just failed with EINTR, because of
+        // a SIGUSR1 queued for this thread
+        let ecall = 0x1000;
+        let interrupted = |handler: u64, flags: u64| {
+            let mut executor = RvInterpreterExecutor::new(
+                64,
+                RvIsaState::default(),
+                Arc::new(GuestMmu::new(4096)),
+            );
+            executor.stack(16384).unwrap();
+            executor.signals.actions.lock().unwrap()[libc::SIGUSR1 as usize - 1] =
+                signal::SigAction {
+                    handler,
+                    flags,
+                    mask: 0,
+                };
+            executor
+                .pending
+                .push(signal::SigInfo::user(libc::SIGUSR1, signal::SI_USER));
+            executor.state.set_pc(ecall + 4);
+            executor.state.set_x(10, -libc::EINTR as u64);
+            executor.restart_a0 = Some(3);
+            assert!(executor.deliver_pending().is_none());
+            executor
+        };
+        // the pc and a0 the handler returns to
+        let saved = |executor: &RvInterpreterExecutor| {
+            let mut b = [0u8; 8];
+            let uc = executor.state.get_x(12);
+            executor.mmu.read_bytes((uc + 176).into(), &mut b).unwrap();
+            let pc = u64::from_le_bytes(b);
+            executor.mmu.read_bytes((uc + 256).into(), &mut b).unwrap();
+            (pc, u64::from_le_bytes(b))
+        };
+
+        let executor = interrupted(0x2000, 0x1000_0000);
+        assert_eq!(executor.state.get_pc(), 0x2000);
+        assert_eq!(saved(&executor), (ecall, 3));
+
+        let executor = interrupted(0x2000, 0);
+        assert_eq!(saved(&executor), (ecall + 4, -libc::EINTR as u64));
+
+        // an ignored signal restarts it at once
+        let executor = interrupted(1, 0);
+        assert_eq!(executor.state.get_pc(), ecall);
+        assert_eq!(executor.state.get_x(10), 3);
+    }
+
+    #[test]
+    fn test_permissions() {
+        // sd zero, 0(s0) into the code itself, which is read-only
+        let code = [0x0004_3023];
+        let mut state = RvIsaState::default();
+        state.set_x(8, code.as_ptr() as u64);
+        assert!(matches!(
+            run(&code, &mut state),
+            StopReason::Segv {
+                access: Access::Write,
+                ..
+            }
+        ));
+
+        // jr s0 into data that is not executable
+        let code = [0x0004_0067];
+        let mut data = [0u32; 1];
+        let mut state = RvIsaState::default();
+        state.set_x(8, data.as_mut_ptr() as u64);
+        let mmu = GuestMmu::new(4096);
+        let gaddr = mmu
+            .consume_host(code.as_ptr() as *const u8, code.len() * 4)
+            .unwrap();
+        mmu.consume_host_mut(data.as_mut_ptr() as *mut u8, 4)
+            .unwrap();
+        let mut executor = RvInterpreterExecutor::new(64, state, Arc::new(mmu));
+        assert!(matches!(
+            executor.exec(gaddr.as_u64()),
+            StopReason::Segv {
+                access: Access::Exec,
+                gaddr,
+            } if gaddr == data.as_ptr() as u64
+        ));
+    }
+
+    // runs the single instruction at `pc`
+    fn step(executor: &mut RvInterpreterExecutor, pc: u64) -> StopReason {
+        executor.state.set_pc(pc);
+        executor.exec_one()
+    }
+
+    #[test]
+    fn test_misaligned() {
+        // ld a0, 0(s0); sd zero, 0(s0); lw a0, 1(s0); amoadd.w a0, zero, (s0)
+        let code = [0x0004_3503, 0x0004_3023, 0x0014_2503, 0x0004_252f];
+        let mmu = Arc::new(GuestMmu::new(4096));
+        let gaddr = mmu
+            .consume_host(code.as_ptr() as *const u8, code.len() * 4)
+            .unwrap();
+        let data = mmu.mmap(2 * 4096, false).unwrap();
+        let bytes = [1, 2, 3, 4, 5, 6, 7, 8];
+        mmu.write_bytes(data + 4092, &bytes).unwrap();
+        let mut state = RvIsaState::default();
+        state.set_x(8, (data + 4092).as_u64());
+        let mut executor = RvInterpreterExecutor::new(64, state, mmu.clone());
+
+        // loads crossing a page are put together from both
+        let pc = gaddr.as_u64();
+        assert!(matches!(step(&mut executor, pc), StopReason::Next));
+        assert_eq!(executor.state().get_x(10), u64::from_le_bytes(bytes));
+
+        // stores crossing into an unmapped page write nothing at all
+        mmu.munmap(data + 4096, 4096);
+        assert!(matches!(
+            step(&mut executor, pc + 4),
+            StopReason::Segv {
+                access: Access::Write,
+                gaddr,
+            } if gaddr == (data + 4096).as_u64()
+        ));
+        let mut buf = [0u8; 4];
+        mmu.read_bytes(data + 4092, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        // misaligned accesses may trap, atomic ones always do
+        executor.state.set_x(8, data.as_u64() + 2);
+        assert!(matches!(step(&mut executor, pc + 8), StopReason::Next));
+        assert!(matches!(
+            step(&mut executor, pc + 12),
+            StopReason::Misaligned { .. }
+        ));
+        executor.misaligned(MisalignedPolicy::Trap);
+        assert!(matches!(
+            step(&mut executor, pc + 8),
+            StopReason::Misaligned { read: true, gaddr } if gaddr == data.as_u64() + 3
+        ));
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // li a0, 1; sw s1, 0(s0); fence.i
+        let code = [0x0010_0513u32, 0x0094_2023, 0x0000_100f];
+        let mmu = Arc::new(GuestMmu::new(4096));
+        let rwx = Prot {
+            read: true,
+            write: true,
+            exec: true,
+        };
+        let g = mmu
+            .mmap_at(0.into(), 4096, rwx, Placement::Hint, 0, None)
+            .unwrap();
+        let bytes: Vec<u8> = code.iter().flat_map(|x| x.to_le_bytes()).collect();
+        mmu.write_bytes(g, &bytes).unwrap();
+
+        let mut state = RvIsaState::default();
+        state.set_x(8, g.as_u64());
+        // li a0, 2
+        state.set_x(9, 0x0020_0513);
+        let mut executor = RvInterpreterExecutor::new(64, state, mmu.clone());
+        let pc = g.as_u64();
+        step(&mut executor, pc);
+        assert_eq!(executor.state().get_x(10), 1);
+
+        // stores by the guest itself take effect at once
+        step(&mut executor, pc + 4);
+        step(&mut executor, pc);
+        assert_eq!(executor.state().get_x(10), 2);
+
+        // other writes need a fence.i
+        mmu.write_bytes(g, &0x0030_0513u32.to_le_bytes()).unwrap();
+        step(&mut executor, pc);
+        assert_eq!(executor.state().get_x(10), 2);
+        step(&mut executor, pc + 8);
+        step(&mut executor, pc);
+        assert_eq!(executor.state().get_x(10), 3);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let code: [u32; 14] = [
+            0x0640_0293, // li t0, 100
+            0x0000_0513, // li a0, 0
+            0x0055_0533, // 1: add a0, a0, t0
+            0xfff2_8293, // addi t0, t0, -1
+            0x00a4_3023, // sd a0, 0(s0)
+            0xfe02_9ae3, // bnez t0, 1b
+            0x00c0_00ef, // jal ra, 2f
+            0x0004_3583, // ld a1, 0(s0)
+            0x0100_006f, // j 3f
+            0x0075_061b, // 2: addiw a2, a0, 7
+            0x0036_1613, // slli a2, a2, 3
+            0x0000_8067, // ret
+            0x05e0_0893, // 3: li a7, 94
+            0x0000_0073, // ecall
+        ];
+        let mut data = [0u64; 1];
+        let mmu = Arc::new(GuestMmu::new(4096));
+        let gaddr = mmu
+            .consume_host(code.as_ptr() as *const u8, code.len() * 4)
+            .unwrap();
+        let daddr = mmu
+            .consume_host_mut(data.as_mut_ptr() as *mut u8, 8)
+            .unwrap();
+
+        // both ways of running the guest end up in the same state
+        let mut states = Vec::new();
+        for dispatch in [Dispatch::Step, Dispatch::Blocks] {
+            let mut state = RvIsaState::default();
+            state.set_x(8, daddr.as_u64());
+            let mut executor = RvInterpreterExecutor::new(64, state, mmu.clone());
+            executor.dispatch(dispatch);
+            assert!(matches!(
+                executor.exec(gaddr.as_u64()),
+                StopReason::Exit(5050)
+            ));
+            assert_eq!(executor.instret, 409);
+            states.push(executor.state().clone());
+        }
+        assert_eq!(states[0], states[1]);
+        assert_eq!(states[1].get_x(11), 5050);
+        assert_eq!(states[1].get_x(12), 5057 << 3);
+
+        // a store to the rest of the running block takes effect
+        let code = [0x0094_2223u32, 0x0010_0513, 0x05e0_0893, 0x0000_0073];
+        let rwx = Prot {
+            read: true,
+            write: true,
+            exec: true,
+        };
+        let g = mmu
+            .mmap_at(0.into(), 4096, rwx, Placement::Hint, 0, None)
+            .unwrap();
+        let bytes: Vec<u8> = code.iter().flat_map(|x| x.to_le_bytes()).collect();
+        mmu.write_bytes(g, &bytes).unwrap();
+        let mut state = RvIsaState::default();
+        state.set_x(8, g.as_u64());
+        // li a0, 2
+        state.set_x(9, 0x0020_0513);
+        let mut executor = RvInterpreterExecutor::new(64, state, mmu.clone());
+        executor.dispatch(Dispatch::Blocks);
+        assert!(matches!(executor.exec(g.as_u64()), StopReason::Exit(2)));
+    }
+
+    // Times both dispatch modes on a sieve of Eratosthenes, which is mostly
+    // short loops of loads, stores and branches. Run it with
+    // `cargo test --release -- --ignored --nocapture bench_dispatch`.
+    #[test]
+    #[ignore]
+    fn bench_dispatch() {
+        const N: u64 = 1 << 20;
+        const REPS: u64 = 5;
+        // counts the primes below s1, a1 times over, in a flag array at s0
+        let code: [u32; 29] = [
+            0x0010_0613, // li a2, 1
+            0x0004_0293, // 1: mv t0, s0
+            0x0094_0333, // add t1, s0, s1
+            0x0002_8023, // 2: sb zero, 0(t0)
+            0x0012_8293, // addi t0, t0, 1
+            0xfe62_ece3, // bltu t0, t1, 2b
+            0x0020_0393, // li t2, 2
+            0x0273_8e33, // 3: mul t3, t2, t2
+            0x029e_7463, // bgeu t3, s1, 6f
+            0x0074_0eb3, // add t4, s0, t2
+            0x000e_cf03, // lbu t5, 0(t4)
+            0x000f_1a63, // bnez t5, 5f
+            0x01c4_0e33, // add t3, s0, t3
+            0x00ce_0023, // 4: sb a2, 0(t3)
+            0x007e_0e33, // add t3, t3, t2
+            0xfe6e_6ce3, // bltu t3, t1, 4b
+            0x0013_8393, // 5: addi t2, t2, 1
+            0xfd9f_f06f, // j 3b
+            0x0000_0513, // 6: li a0, 0
+            0x0024_0293, // addi t0, s0, 2
+            0x0002_cf03, // 7: lbu t5, 0(t0)
+            0x001f_3f13, // seqz t5, t5
+            0x01e5_0533, // add a0, a0, t5
+            0x0012_8293, // addi t0, t0, 1
+            0xfe62_e8e3, // bltu t0, t1, 7b
+            0xfff5_8593, // addi a1, a1, -1
+            0xf805_9ee3, // bnez a1, 1b
+            0x05e0_0893, // li a7, 94
+            0x0000_0073, // ecall
+        ];
+        let mmu = Arc::new(GuestMmu::new(4096));
+        let gaddr = mmu
+            .consume_host(code.as_ptr() as *const u8, code.len() * 4)
+            .unwrap();
+        let data = mmu.mmap(N as usize, false).unwrap();
+
+        for dispatch in [Dispatch::Step, Dispatch::Blocks] {
+            let mut state = RvIsaState::default();
+            state.set_x(8, data.as_u64());
+            state.set_x(9, N);
+            state.set_x(11, REPS);
+            let mut executor = RvInterpreterExecutor::new(64, state, mmu.clone());
+            executor.dispatch(dispatch);
+            let t = std::time::Instant::now();
+            assert!(matches!(
+                executor.exec(gaddr.as_u64()),
+                StopReason::Exit(82025)
+            ));
+            let elapsed = t.elapsed();
+            println!(
+                "{:?}: {} instructions in {:.3}s, {:.1} ns each",
+                dispatch,
+                executor.instret,
+                elapsed.as_secs_f64(),
+                elapsed.as_nanos() as f64 / executor.instret as f64
+            );
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use super::{sext_u32, RvInterpreterExecutor, StopReason};
+use crate::rv::RTypeArgs;
+
+/// Operations of the M extension. None of them trap: division by zero and
+/// signed overflow have results defined by the spec instead.
+#[derive(Clone, Copy, Debug)]
+pub(super) enum MulDivOp {
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+    Mulw,
+    Divw,
+    Divuw,
+    Remw,
+    Remuw,
+}
+
+impl MulDivOp {
+    pub(super) fn eval(self, a: u64, b: u64) -> u64 {
+        let (sa, sb) = (a as i64, b as i64);
+        // the .w variants take the low 32 bits and sign-extend the result
+        let (wa, wb) = (a as i32, b as i32);
+        let (ua, ub) = (a as u32, b as u32);
+        match self {
+            MulDivOp::Mul => a.wrapping_mul(b),
+            MulDivOp::Mulh => ((sa as i128 * sb as i128) >> 64) as u64,
+            MulDivOp::Mulhsu => ((sa as i128 * b as i128) >> 64) as u64,
+            MulDivOp::Mulhu => ((a as u128 * b as u128) >> 64) as u64,
+            MulDivOp::Div if b == 0 => u64::MAX,
+            MulDivOp::Div => sa.wrapping_div(sb) as u64,
+            MulDivOp::Divu => a.checked_div(b).unwrap_or(u64::MAX),
+            MulDivOp::Rem if b == 0 => a,
+            MulDivOp::Rem => sa.wrapping_rem(sb) as u64,
+            MulDivOp::Remu => a.checked_rem(b).unwrap_or(a),
+            MulDivOp::Mulw => sext_u32(ua.wrapping_mul(ub)),
+            MulDivOp::Divw if wb == 0 => u64::MAX,
+            MulDivOp::Divw => sext_u32(wa.wrapping_div(wb) as u32),
+            MulDivOp::Divuw => sext_u32(ua.checked_div(ub).unwrap_or(u32::MAX)),
+            MulDivOp::Remw if wb == 0 => sext_u32(ua),
+            MulDivOp::Remw => sext_u32(wa.wrapping_rem(wb) as u32),
+            MulDivOp::Remuw => sext_u32(ua.checked_rem(ub).unwrap_or(ua)),
+        }
+    }
+}
+
+impl RvInterpreterExecutor {
+    pub(super) fn do_muldiv(&mut self, a: &RTypeArgs, op: MulDivOp) -> StopReason {
+        let v = op.eval(self.gx(a.rs1), self.gx(a.rs2));
+        self.sx(a.rd, v);
+        StopReason::Next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::exec::mem::GuestMmu;
+    use crate::exec::RvIsaState;
+
+    const MIN: u64 = i64::MIN as u64;
+    const W_MIN: u64 = i32::MIN as i64 as u64;
+    const NEG1: u64 = u64::MAX;
+
+    // op, funct3, whether it is a .w variant, rs1, rs2, rd
+    #[rustfmt::skip]
+    const CASES: &[(MulDivOp, u32, bool, u64, u64, u64)] = &[
+        (MulDivOp::Mul, 0, false, 7, NEG1, -7i64 as u64),
+        (MulDivOp::Mul, 0, false, MIN, NEG1, MIN),
+        (MulDivOp::Mulh, 1, false, MIN, MIN, 1 << 62),
+        (MulDivOp::Mulh, 1, false, NEG1, 5, NEG1),
+        (MulDivOp::Mulhsu, 2, false, NEG1, NEG1, NEG1),
+        (MulDivOp::Mulhsu, 2, false, 2, NEG1, 1),
+        (MulDivOp::Mulhsu, 2, false, MIN, 2, NEG1),
+        (MulDivOp::Mulhu, 3, false, NEG1, NEG1, NEG1 - 1),
+        (MulDivOp::Div, 4, false, 20, -6i64 as u64, -3i64 as u64),
+        (MulDivOp::Div, 4, false, 20, 0, NEG1),
+        (MulDivOp::Div, 4, false, MIN, NEG1, MIN),
+        (MulDivOp::Divu, 5, false, 20, 0, NEG1),
+        (MulDivOp::Divu, 5, false, NEG1, 2, NEG1 >> 1),
+        (MulDivOp::Rem, 6, false, -20i64 as u64, 6, -2i64 as u64),
+        (MulDivOp::Rem, 6, false, 20, 0, 20),
+        (MulDivOp::Rem, 6, false, MIN, NEG1, 0),
+        (MulDivOp::Remu, 7, false, 20, 0, 20),
+        (MulDivOp::Remu, 7, false, NEG1, 10, 5),
+        (MulDivOp::Mulw, 0, true, 0x8000_0000, 1, W_MIN),
+        (MulDivOp::Mulw, 0, true, 0x1_0000_0003, 0x7fff_ffff, 0x7fff_fffd),
+        (MulDivOp::Divw, 4, true, 0x1_0000_0014, 0xffff_fffa, -3i64 as u64),
+        (MulDivOp::Divw, 4, true, 20, 0x1_0000_0000, NEG1),
+        (MulDivOp::Divw, 4, true, W_MIN, NEG1, W_MIN),
+        (MulDivOp::Divuw, 5, true, 0xffff_fffe, 1, 0xffff_ffff_ffff_fffe),
+        (MulDivOp::Divuw, 5, true, 20, 0, NEG1),
+        (MulDivOp::Remw, 6, true, 0x8000_0014, 0, 0xffff_ffff_8000_0014),
+        (MulDivOp::Remw, 6, true, W_MIN, NEG1, 0),
+        (MulDivOp::Remw, 6, true, -20i64 as u64, 6, -2i64 as u64),
+        (MulDivOp::Remuw, 7, true, 0x1_8000_0000, 0, W_MIN),
+        (MulDivOp::Remuw, 7, true, 0xffff_fff5, 0x1_0000_0010, 5),
+    ];
+
+    #[test]
+    fn test_muldiv() {
+        for &(op, funct3, w, rs1, rs2, rd) in CASES {
+            assert_eq!(op.eval(rs1, rs2), rd, "{:?} {:#x} {:#x}", op, rs1, rs2);
+
+            // and decoded from `op a0, a1, a2`, to get the operands right too
+            let opcode = if w { 0b0111011 } else { 0b0110011 };
+            let code = [1 << 25 | 12 << 20 | 11 << 15 | funct3 << 12 | 10 << 7 | opcode];
+            let mmu = GuestMmu::new(4096);
+            let gaddr = mmu.consume_host(code.as_ptr() as *const u8, 4).unwrap();
+            let mut state = RvIsaState::default();
+            state.set_x(11, rs1);
+            state.set_x(12, rs2);
+            state.set_pc(gaddr.as_u64());
+            let mut executor = RvInterpreterExecutor::new(64, state, Arc::new(mmu));
+            assert!(matches!(executor.exec_one(), StopReason::Next));
+            assert_eq!(
+                executor.state().get_x(10),
+                rd,
+                "{:?} {:#x} {:#x}",
+                op,
+                rs1,
+                rs2
+            );
+        }
+    }
+}
@@ -0,0 +1,472 @@
+//! Guest signals: dispositions, pending sets, and delivery on the guest
+//! stack following the riscv64 Linux ABI.
+//!
+//! Every signal is queued at most once, real-time ones included.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once};
+
+use super::{RvInterpreterExecutor, StopReason};
+use crate::exec::mem::Prot;
+
+pub(super) const NSIG: u64 = 64;
+
+pub(super) const SIG_DFL: u64 = 0;
+pub(super) const SIG_IGN: u64 = 1;
+
+// sa_flags
+const SA_ONSTACK: u64 = 0x0800_0000;
+const SA_RESTART: u64 = 0x1000_0000;
+const SA_NODEFER: u64 = 0x4000_0000;
+const SA_RESETHAND: u64 = 0x8000_0000;
+
+// si_code values
+pub(super) const SI_USER: i32 = 0;
+pub(super) const SI_TKILL: i32 = -6;
+const ILL_ILLOPC: i32 = 1;
+const SEGV_MAPERR: i32 = 1;
+const SEGV_ACCERR: i32 = 2;
+const BUS_ADRALN: i32 = 1;
+const TRAP_BRKPT: i32 = 1;
+
+// ss_flags
+pub(super) const SS_ONSTACK: i32 = 1;
+pub(super) const SS_DISABLE: i32 = 2;
+
+// struct rt_sigframe is a siginfo followed by a ucontext, whose
+// uc_mcontext holds sc_regs (pc, then x1-x31) and the F/D state
+pub(super) const SIGINFO_SIZE: usize = 128;
+const UC_STACK: usize = 16;
+const UC_SIGMASK: usize = 40;
+const UC_MCONTEXT: usize = 176;
+const SC_FPREGS: usize = UC_MCONTEXT + 256;
+const SC_FCSR: usize = SC_FPREGS + 256;
+const UCONTEXT_SIZE: usize = SC_FPREGS + 528;
+const FRAME_SIZE: usize = SIGINFO_SIZE + UCONTEXT_SIZE;
+
+// li a7, 139 (rt_sigreturn); ecall
+const SIGRETURN_CODE: [u32; 2] = [0x08b0_0893, 0x0000_0073];
+
+// host signal that interrupts the blocking syscalls of a thread that has
+// just been sent a guest signal
+fn kick_signal() -> libc::c_int {
+    libc::SIGRTMAX()
+}
+
+extern "C" fn kick_handler(_: libc::c_int) {}
+
+pub(super) fn sigbit(signo: i32) -> u64 {
+    1 << (signo - 1)
+}
+
+// SIGKILL and SIGSTOP can be neither caught nor blocked
+pub(super) const UNBLOCKABLE: u64 = 1 << (libc::SIGKILL - 1) | 1 << (libc::SIGSTOP - 1);
+
+// signals whose default action is not to terminate; stopping is not
+// supported, so the stop signals are ignored as well
+fn ignored_by_default(signo: i32) -> bool {
+    matches!(
+        signo,
+        libc::SIGCHLD
+            | libc::SIGCONT
+            | libc::SIGURG
+            | libc::SIGWINCH
+            | libc::SIGSTOP
+            | libc::SIGTSTP
+            | libc::SIGTTIN
+            | libc::SIGTTOU
+    )
+}
+
+/// struct sigaction of asm-generic, without sa_restorer.
+#[derive(Clone, Copy, Default, Debug)]
+pub(super) struct SigAction {
+    pub(super) handler: u64,
+    pub(super) flags: u64,
+    pub(super) mask: u64,
+}
+
+/// The part of a siginfo that is filled in.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct SigInfo {
+    signo: i32,
+    code: i32,
+    pid: u32,
+    uid: u32,
+    addr: u64,
+}
+
+impl SigInfo {
+    /// Signal sent by kill(2) and friends from within the guest.
+    pub(super) fn user(signo: i32, code: i32) -> Self {
+        Self {
+            signo,
+            code,
+            pid: unsafe { libc::getpid() } as u32,
+            uid: unsafe { libc::getuid() },
+            addr: 0,
+        }
+    }
+
+    fn fault(signo: i32, code: i32, addr: u64) -> Self {
+        Self {
+            signo,
+            code,
+            pid: 0,
+            uid: 0,
+            addr,
+        }
+    }
+
+    fn to_guest(self) -> [u8; SIGINFO_SIZE] {
+        let mut b = [0u8; SIGINFO_SIZE];
+        b[0..4].copy_from_slice(&self.signo.to_le_bytes());
+        b[8..12].copy_from_slice(&self.code.to_le_bytes());
+        if self.addr != 0 {
+            b[16..24].copy_from_slice(&self.addr.to_le_bytes());
+        } else {
+            b[16..20].copy_from_slice(&self.pid.to_le_bytes());
+            b[20..24].copy_from_slice(&self.uid.to_le_bytes());
+        }
+        b
+    }
+}
+
+/// Pending signals of a thread or of the whole process. The bits can be
+/// checked without taking the lock.
+#[derive(Default)]
+pub(super) struct SigQueue {
+    bits: AtomicU64,
+    infos: Mutex<Vec<SigInfo>>,
+}
+
+impl SigQueue {
+    pub(super) fn bits(&self) -> u64 {
+        self.bits.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn push(&self, info: SigInfo) {
+        let mut infos = self.infos.lock().unwrap();
+        if self.bits() & sigbit(info.signo) == 0 {
+            infos.push(info);
+            self.bits.fetch_or(sigbit(info.signo), Ordering::SeqCst);
+        }
+    }
+
+    // the lowest-numbered pending signal among `allowed`
+    fn take(&self, allowed: u64) -> Option<SigInfo> {
+        if self.bits() & allowed == 0 {
+            return None;
+        }
+        let mut infos = self.infos.lock().unwrap();
+        let i = infos
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| allowed & sigbit(x.signo) != 0)
+            .min_by_key(|(_, x)| x.signo)
+            .map(|(i, _)| i)?;
+        let info = infos.remove(i);
+        self.bits.fetch_and(!sigbit(info.signo), Ordering::SeqCst);
+        Some(info)
+    }
+
+    pub(super) fn discard(&self, signo: i32) {
+        let mut infos = self.infos.lock().unwrap();
+        infos.retain(|x| x.signo != signo);
+        self.bits.fetch_and(!sigbit(signo), Ordering::SeqCst);
+    }
+}
+
+/// Signal state shared by all threads of a guest process.
+pub(super) struct SigProcess {
+    pub(super) actions: Mutex<[SigAction; NSIG as usize]>,
+    pub(super) pending: SigQueue,
+    // pending queues of the live threads, by TID
+    threads: Mutex<HashMap<i64, Arc<SigQueue>>>,
+    // guest address of the code handlers return to
+    trampoline: Mutex<Option<u64>>,
+}
+
+impl SigProcess {
+    pub(super) fn new() -> Self {
+        static KICK: Once = Once::new();
+        KICK.call_once(|| unsafe {
+            // no SA_RESTART, so that blocking syscalls fail with EINTR
+            let mut sa: libc::sigaction = std::mem::zeroed();
+            sa.sa_sigaction = kick_handler as *const () as usize;
+            libc::sigemptyset(&mut sa.sa_mask);
+            libc::sigaction(kick_signal(), &sa, std::ptr::null_mut());
+        });
+
+        Self {
+            actions: Mutex::new([SigAction::default(); NSIG as usize]),
+            pending: SigQueue::default(),
+            threads: Mutex::new(HashMap::new()),
+            trampoline: Mutex::new(None),
+        }
+    }
+
+    pub(super) fn action(&self, signo: i32) -> SigAction {
+        self.actions.lock().unwrap()[signo as usize - 1]
+    }
+
+    pub(super) fn register(&self, tid: i64, queue: Arc<SigQueue>) {
+        self.threads.lock().unwrap().insert(tid, queue);
+    }
+
+    pub(super) fn unregister(&self, tid: i64) {
+        self.threads.lock().unwrap().remove(&tid);
+    }
+
+    pub(super) fn has_thread(&self, tid: i64) -> bool {
+        self.threads.lock().unwrap().contains_key(&tid)
+    }
+
+    /// Queues a signal for thread `tid`, returning false if there is no
+    /// such thread.
+    pub(super) fn send_to_thread(&self, tid: i64, info: SigInfo) -> bool {
+        let threads = self.threads.lock().unwrap();
+        let Some(queue) = threads.get(&tid) else {
+            return false;
+        };
+        queue.push(info);
+        unsafe {
+            libc::syscall(libc::SYS_tgkill, libc::getpid(), tid, kick_signal());
+        }
+        true
+    }
+
+    /// Kills every thread but `tid`, the way exit_group(2) does.
+    pub(super) fn kill_others(&self, tid: i64) {
+        let others: Vec<i64> = self
+            .threads
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .filter(|&x| x != tid)
+            .collect();
+        for x in others {
+            self.send_to_thread(x, SigInfo::user(libc::SIGKILL, SI_USER));
+        }
+    }
+}
+
+/// stack_t set by sigaltstack(2).
+#[derive(Clone, Copy, Debug)]
+pub(super) struct AltStack {
+    pub(super) sp: u64,
+    pub(super) size: u64,
+    pub(super) flags: i32,
+}
+
+impl Default for AltStack {
+    fn default() -> Self {
+        Self {
+            sp: 0,
+            size: 0,
+            flags: SS_DISABLE,
+        }
+    }
+}
+
+impl AltStack {
+    pub(super) fn contains(&self, sp: u64) -> bool {
+        self.flags & SS_DISABLE == 0 && sp > self.sp && sp - self.sp <= self.size
+    }
+
+    // ss_flags as reported to the guest for the current `sp`
+    pub(super) fn flags_at(&self, sp: u64) -> i32 {
+        if self.contains(sp) {
+            SS_ONSTACK
+        } else {
+            self.flags
+        }
+    }
+}
+
+impl RvInterpreterExecutor {
+    // checked before every instruction
+    pub(super) fn signal_pending(&self) -> bool {
+        (self.pending.bits() | self.signals.pending.bits()) & !self.blocked != 0
+    }
+
+    /// Delivers the next pending signal that is not blocked, returning how
+    /// the guest stops if it is killed by it.
+    pub(super) fn deliver_pending(&mut self) -> Option<StopReason> {
+        let allowed = !self.blocked;
+        let info = self
+            .pending
+            .take(allowed)
+            .or_else(|| self.signals.pending.take(allowed))?;
+        self.deliver(info)
+    }
+
+    /// Turns a guest fault into the signal Linux would raise. Faults cannot
+    /// be blocked or ignored, so the guest stops with `reason` unless a
+    /// handler is ready to take it.
+    pub(super) fn deliver_fault(&mut self, reason: StopReason) -> Option<StopReason> {
+        let pc = self.state.get_pc();
+        let info = match reason {
+            StopReason::Segv { gaddr, .. } => {
+                let code = if self.mmu.g2h(gaddr.into()).is_some() {
+                    SEGV_ACCERR
+                } else {
+                    SEGV_MAPERR
+                };
+                SigInfo::fault(libc::SIGSEGV, code, gaddr)
+            }
+            StopReason::Misaligned { gaddr, .. } => SigInfo::fault(libc::SIGBUS, BUS_ADRALN, gaddr),
+            StopReason::ReservedInsn => SigInfo::fault(libc::SIGILL, ILL_ILLOPC, pc),
+            StopReason::Break => SigInfo::fault(libc::SIGTRAP, TRAP_BRKPT, pc),
+            _ => return Some(reason),
+        };
+
+        let act = self.signals.action(info.signo);
+        if act.handler == SIG_DFL
+            || act.handler == SIG_IGN
+            || self.blocked & sigbit(info.signo) != 0
+        {
+            return Some(reason);
+        }
+        self.deliver(info)
+    }
+
+    fn deliver(&mut self, info: SigInfo) -> Option<StopReason> {
+        let act = {
+            let mut actions = self.signals.actions.lock().unwrap();
+            let act = actions[info.signo as usize - 1];
+            if act.flags & SA_RESETHAND != 0 && act.handler != SIG_IGN {
+                actions[info.signo as usize - 1] = SigAction::default();
+            }
+            act
+        };
+
+        // like Linux, the interrupted syscall runs again unless a handler
+        // without SA_RESTART sees it fail with EINTR; ecall is 4 bytes
+        let ignored =
+            act.handler == SIG_IGN || act.handler == SIG_DFL && ignored_by_default(info.signo);
+        let handled = act.handler != SIG_DFL && act.handler != SIG_IGN;
+        if let Some(a0) = self.restart_a0.take() {
+            if ignored || handled && act.flags & SA_RESTART != 0 {
+                self.state.set_pc(self.state.get_pc() - 4);
+                self.state.set_x(10, a0);
+            }
+        }
+
+        match act.handler {
+            SIG_IGN => None,
+            SIG_DFL if ignored_by_default(info.signo) => None,
+            SIG_DFL => Some(StopReason::Signaled(info.signo)),
+            _ => match self.setup_frame(info, &act) {
+                Ok(()) => None,
+                // like Linux, a handler that cannot be run is fatal
+                Err(_) => Some(StopReason::Signaled(libc::SIGSEGV)),
+            },
+        }
+    }
+
+    // a page holding the code that handlers return to, as Linux has no
+    // sa_restorer on riscv64 and points ra into the vDSO instead
+    fn sigreturn_trampoline(&self) -> Result<u64, i64> {
+        let mut tramp = self.signals.trampoline.lock().unwrap();
+        if let Some(x) = *tramp {
+            return Ok(x);
+        }
+
+        let page = self.mmu.page_size();
+        let g = self
+            .mmu
+            .mmap(page, false)
+            .map_err(|_| libc::ENOMEM as i64)?;
+        let code: Vec<u8> = SIGRETURN_CODE
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        self.copy_to_guest(g.as_u64(), &code)?;
+        self.mmu
+            .mprotect(g, page, Prot::RX)
+            .map_err(|_| libc::ENOMEM as i64)?;
+
+        *tramp = Some(g.as_u64());
+        Ok(g.as_u64())
+    }
+
+    fn setup_frame(&mut self, info: SigInfo, act: &SigAction) -> Result<(), i64> {
+        let tramp = self.sigreturn_trampoline()?;
+
+        let mut sp = self.state.get_x(2);
+        if act.flags & SA_ONSTACK != 0
+            && self.altstack.flags & SS_DISABLE == 0
+            && !self.altstack.contains(sp)
+        {
+            sp = self.altstack.sp + self.altstack.size;
+        }
+        let frame = (sp - FRAME_SIZE as u64) & !0xf;
+
+        let mut uc = vec![0u8; UCONTEXT_SIZE];
+        let mut put = |off: usize, bytes: &[u8]| uc[off..off + bytes.len()].copy_from_slice(bytes);
+        let old_sp = self.state.get_x(2);
+        put(UC_STACK, &self.altstack.sp.to_le_bytes());
+        put(UC_STACK + 8, &self.altstack.flags_at(old_sp).to_le_bytes());
+        put(UC_STACK + 16, &self.altstack.size.to_le_bytes());
+        put(UC_SIGMASK, &self.blocked.to_le_bytes());
+        put(UC_MCONTEXT, &self.state.get_pc().to_le_bytes());
+        for i in 1..32 {
+            put(
+                UC_MCONTEXT + 8 * i,
+                &self.state.get_x(i as u8).to_le_bytes(),
+            );
+        }
+        for i in 0..32 {
+            put(
+                SC_FPREGS + 8 * i,
+                &self.state.get_f_bits(i as u8).to_le_bytes(),
+            );
+        }
+        put(SC_FCSR, &self.state.get_fcsr().to_le_bytes());
+
+        self.copy_to_guest(frame, &info.to_guest())?;
+        self.copy_to_guest(frame + SIGINFO_SIZE as u64, &uc)?;
+
+        self.state.set_x(10, info.signo as u64);
+        self.state.set_x(11, frame);
+        self.state.set_x(12, frame + SIGINFO_SIZE as u64);
+        self.state.set_x(2, frame);
+        self.state.set_x(1, tramp);
+        self.state.set_pc(act.handler);
+
+        self.blocked |= act.mask;
+        if act.flags & SA_NODEFER == 0 {
+            self.blocked |= sigbit(info.signo);
+        }
+        self.blocked &= !UNBLOCKABLE;
+        self.reservation = None;
+        Ok(())
+    }
+
+    /// rt_sigreturn(2): restores the context saved by `setup_frame`, which
+    /// `sp` still points to.
+    pub(super) fn sigreturn(&mut self) -> StopReason {
+        let uc_addr = self.state.get_x(2) + SIGINFO_SIZE as u64;
+        let mut uc = vec![0u8; UCONTEXT_SIZE];
+        if self.copy_from_guest(uc_addr, &mut uc).is_err() {
+            return StopReason::Signaled(libc::SIGSEGV);
+        }
+        let u64_at = |off: usize| u64::from_le_bytes(uc[off..off + 8].try_into().unwrap());
+
+        self.blocked = u64_at(UC_SIGMASK) & !UNBLOCKABLE;
+        for i in 1..32 {
+            self.state.set_x(i as u8, u64_at(UC_MCONTEXT + 8 * i));
+        }
+        for i in 0..32 {
+            self.state.set_f_bits(i as u8, u64_at(SC_FPREGS + 8 * i));
+        }
+        self.state.set_fcsr(u32::from_le_bytes(
+            uc[SC_FCSR..SC_FCSR + 4].try_into().unwrap(),
+        ));
+        self.reservation = None;
+        StopReason::ContinueAt(u64_at(UC_MCONTEXT))
+    }
+}
@@ -0,0 +1,776 @@
+//! Software IEEE-754 arithmetic.
+//!
+//! The host FPU only knows its own rounding mode and exception state, so the
+//! arithmetic instructions are carried out here instead, with every rounding
+//! mode RISC-V can ask for and the accrued exception flags it expects. Values
+//! are passed around as raw bit patterns in the low bits of a `u64`.
+
+use crate::rv::RoundingMode;
+
+// fflags bits
+pub(super) const NV: u8 = 1 << 4;
+pub(super) const DZ: u8 = 1 << 3;
+pub(super) const OF: u8 = 1 << 2;
+pub(super) const UF: u8 = 1 << 1;
+pub(super) const NX: u8 = 1 << 0;
+
+/// A resolved rounding mode, i.e. neither `Dyn` nor reserved.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Rm {
+    Rne,
+    Rtz,
+    Rdn,
+    Rup,
+    Rmm,
+}
+
+impl Rm {
+    pub(super) fn from_static(rm: &RoundingMode) -> Option<Self> {
+        match rm {
+            RoundingMode::Rne => Some(Self::Rne),
+            RoundingMode::Rtz => Some(Self::Rtz),
+            RoundingMode::Rdn => Some(Self::Rdn),
+            RoundingMode::Rup => Some(Self::Rup),
+            RoundingMode::Rmm => Some(Self::Rmm),
+            RoundingMode::Dyn | RoundingMode::Reserved(_) => None,
+        }
+    }
+}
+
+pub(super) struct Fmt {
+    exp_bits: u32,
+    frac_bits: u32,
+}
+
+pub(super) const F32: Fmt = Fmt {
+    exp_bits: 8,
+    frac_bits: 23,
+};
+
+pub(super) const F64: Fmt = Fmt {
+    exp_bits: 11,
+    frac_bits: 52,
+};
+
+impl Fmt {
+    pub(super) fn is_single(&self) -> bool {
+        self.frac_bits == F32.frac_bits
+    }
+
+    pub(super) fn sign_bit(&self) -> u64 {
+        1 << (self.exp_bits + self.frac_bits)
+    }
+
+    fn bias(&self) -> i32 {
+        (1 << (self.exp_bits - 1)) - 1
+    }
+
+    fn prec(&self) -> i32 {
+        self.frac_bits as i32 + 1
+    }
+
+    fn exp_mask(&self) -> u64 {
+        (1 << self.exp_bits) - 1
+    }
+
+    fn frac_mask(&self) -> u64 {
+        (1 << self.frac_bits) - 1
+    }
+
+    // weight of the lsb of subnormal numbers
+    fn min_q(&self) -> i32 {
+        1 - self.bias() - self.frac_bits as i32
+    }
+
+    fn sign(&self, sign: bool) -> u64 {
+        if sign {
+            self.sign_bit()
+        } else {
+            0
+        }
+    }
+
+    pub(super) fn canonical_nan(&self) -> u64 {
+        (self.exp_mask() << self.frac_bits) | (1 << (self.frac_bits - 1))
+    }
+
+    fn zero(&self, sign: bool) -> u64 {
+        self.sign(sign)
+    }
+
+    fn inf(&self, sign: bool) -> u64 {
+        self.sign(sign) | (self.exp_mask() << self.frac_bits)
+    }
+
+    fn max_finite(&self, sign: bool) -> u64 {
+        self.sign(sign) | ((self.exp_mask() - 1) << self.frac_bits) | self.frac_mask()
+    }
+}
+
+// a finite non-zero value, sig * 2^exp
+#[derive(Clone, Copy)]
+struct Num {
+    sign: bool,
+    sig: u128,
+    exp: i32,
+}
+
+enum Val {
+    Nan { snan: bool },
+    Inf(bool),
+    Zero(bool),
+    Finite(Num),
+}
+
+impl Val {
+    fn is_nan(&self) -> bool {
+        matches!(self, Val::Nan { .. })
+    }
+
+    fn is_snan(&self) -> bool {
+        matches!(self, Val::Nan { snan: true })
+    }
+}
+
+fn sign_of(v: &Val) -> bool {
+    match v {
+        Val::Inf(s) | Val::Zero(s) => *s,
+        Val::Finite(x) => x.sign,
+        Val::Nan { .. } => false,
+    }
+}
+
+fn unpack(f: &Fmt, x: u64) -> Val {
+    let sign = x & f.sign_bit() != 0;
+    let e = (x >> f.frac_bits) & f.exp_mask();
+    let frac = x & f.frac_mask();
+
+    if e == f.exp_mask() {
+        if frac == 0 {
+            Val::Inf(sign)
+        } else {
+            Val::Nan {
+                snan: frac >> (f.frac_bits - 1) == 0,
+            }
+        }
+    } else if e == 0 {
+        if frac == 0 {
+            Val::Zero(sign)
+        } else {
+            Val::Finite(Num {
+                sign,
+                sig: frac as u128,
+                exp: f.min_q(),
+            })
+        }
+    } else {
+        Val::Finite(Num {
+            sign,
+            sig: (frac | (1 << f.frac_bits)) as u128,
+            exp: e as i32 - f.bias() - f.frac_bits as i32,
+        })
+    }
+}
+
+fn msb(x: u128) -> i32 {
+    127 - x.leading_zeros() as i32
+}
+
+// Rounds sig * 2^exp, plus a non-zero fraction of its lsb if `sticky`, to a
+// multiple of 2^q. Returns the rounded multiple and whether it is inexact.
+//
+// Callers must make sure the value has enough bits below 2^q if `sticky` is
+// set, and that the result fits if it has none.
+fn round_at(sig: u128, exp: i32, sticky: bool, q: i32, sign: bool, rm: Rm) -> (u128, bool) {
+    if exp >= q {
+        debug_assert!(!sticky);
+        return (sig << (exp - q), false);
+    }
+
+    let s = (q - exp) as u32;
+    if s > 128 {
+        // everything is below half of 2^q
+        let inexact = sig != 0 || sticky;
+        let inc = match rm {
+            Rm::Rdn => inexact && sign,
+            Rm::Rup => inexact && !sign,
+            _ => false,
+        };
+        return (inc as u128, inexact);
+    }
+
+    let (m, rem, half) = if s == 128 {
+        (0, sig, 1 << 127)
+    } else {
+        (sig >> s, sig & ((1 << s) - 1), 1 << (s - 1))
+    };
+    let above_half = rem > half || (rem == half && sticky);
+    let tie = rem == half && !sticky;
+    let inexact = rem != 0 || sticky;
+
+    let inc = match rm {
+        Rm::Rne => above_half || (tie && m & 1 == 1),
+        Rm::Rmm => above_half || tie,
+        Rm::Rtz => false,
+        Rm::Rdn => inexact && sign,
+        Rm::Rup => inexact && !sign,
+    };
+    (m + inc as u128, inexact)
+}
+
+fn round_pack(f: &Fmt, sign: bool, sig: u128, exp: i32, sticky: bool, rm: Rm) -> (u64, u8) {
+    if sig == 0 {
+        return (f.zero(sign), 0);
+    }
+
+    let p = f.prec();
+    let e = msb(sig) + exp;
+    let emin = 1 - f.bias();
+
+    let q = (e - (p - 1)).max(f.min_q());
+    let (mut m, inexact) = round_at(sig, exp, sticky, q, sign, rm);
+    let mut q = q;
+    if m >> p != 0 {
+        // rounded up into the next binade
+        m >>= 1;
+        q += 1;
+    }
+
+    let mut flags = if inexact { NX } else { 0 };
+
+    // tininess is detected after rounding, that is, as if the exponent range
+    // were unbounded
+    if e < emin && inexact {
+        let (m2, _) = round_at(sig, exp, sticky, e - (p - 1), sign, rm);
+        let tiny = !(m2 >> p != 0 && e == emin - 1);
+        if tiny {
+            flags |= UF;
+        }
+    }
+
+    if m >> (p - 1) == 0 {
+        // subnormal
+        return (f.sign(sign) | m as u64, flags);
+    }
+
+    let biased_exp = q + (p - 1) + f.bias();
+    if biased_exp >= f.exp_mask() as i32 {
+        let to_inf = match rm {
+            Rm::Rne | Rm::Rmm => true,
+            Rm::Rtz => false,
+            Rm::Rdn => sign,
+            Rm::Rup => !sign,
+        };
+        let v = if to_inf {
+            f.inf(sign)
+        } else {
+            f.max_finite(sign)
+        };
+        return (v, OF | NX);
+    }
+
+    let v = f.sign(sign) | ((biased_exp as u64) << f.frac_bits) | (m as u64 & f.frac_mask());
+    (v, flags)
+}
+
+fn nan_result(f: &Fmt, invalid: bool) -> (u64, u8) {
+    (f.canonical_nan(), if invalid { NV } else { 0 })
+}
+
+// shifts sig * 2^from to a multiple of 2^to, collecting the lost bits
+fn align(sig: u128, from: i32, to: i32) -> (u128, bool) {
+    if from >= to {
+        (sig << (from - to), false)
+    } else {
+        let s = (to - from) as u32;
+        if s >= 128 {
+            (0, sig != 0)
+        } else {
+            (sig >> s, sig & ((1 << s) - 1) != 0)
+        }
+    }
+}
+
+// Exact sum of two finite values, returned as (sign, sig, exp, sticky).
+// Operands may have up to 106 significant bits (fused products).
+fn add_nums(x: Num, y: Num) -> (bool, u128, i32, bool) {
+    let (big, small) = if msb(x.sig) + x.exp >= msb(y.sig) + y.exp {
+        (x, y)
+    } else {
+        (y, x)
+    };
+
+    // leave room for the carry
+    let sh = 125 - msb(big.sig);
+    let bsig = big.sig << sh;
+    let exp = big.exp - sh;
+    let (ssig, sticky) = align(small.sig, small.exp, exp);
+
+    if big.sign == small.sign {
+        (big.sign, bsig + ssig, exp, sticky)
+    } else if bsig >= ssig {
+        // the lost bits of the smaller operand borrow from the lsb:
+        // a - (b + frac) = (a - b - 1) + (1 - frac)
+        (big.sign, bsig - ssig - sticky as u128, exp, sticky)
+    } else {
+        // only possible if the exponents are close, hence nothing was lost
+        (small.sign, ssig - bsig, exp, false)
+    }
+}
+
+fn sum(f: &Fmt, x: Num, y: Num, rm: Rm) -> (u64, u8) {
+    let (sign, sig, exp, sticky) = add_nums(x, y);
+    if sig == 0 && !sticky {
+        // exact cancellation
+        (f.zero(rm == Rm::Rdn), 0)
+    } else {
+        round_pack(f, sign, sig, exp, sticky, rm)
+    }
+}
+
+fn zero_sum_sign(a: bool, b: bool, rm: Rm) -> bool {
+    if a == b {
+        a
+    } else {
+        rm == Rm::Rdn
+    }
+}
+
+pub(super) fn add(f: &Fmt, a: u64, b: u64, rm: Rm) -> (u64, u8) {
+    let (a, b) = (unpack(f, a), unpack(f, b));
+    match (a, b) {
+        (a, b) if a.is_nan() || b.is_nan() => nan_result(f, a.is_snan() || b.is_snan()),
+        (Val::Inf(sa), Val::Inf(sb)) if sa != sb => nan_result(f, true),
+        (Val::Inf(s), _) | (_, Val::Inf(s)) => (f.inf(s), 0),
+        (Val::Zero(sa), Val::Zero(sb)) => (f.zero(zero_sum_sign(sa, sb, rm)), 0),
+        (Val::Zero(_), Val::Finite(x)) | (Val::Finite(x), Val::Zero(_)) => {
+            round_pack(f, x.sign, x.sig, x.exp, false, rm)
+        }
+        (Val::Finite(x), Val::Finite(y)) => sum(f, x, y, rm),
+        _ => unreachable!(),
+    }
+}
+
+pub(super) fn sub(f: &Fmt, a: u64, b: u64, rm: Rm) -> (u64, u8) {
+    add(f, a, b ^ f.sign_bit(), rm)
+}
+
+pub(super) fn mul(f: &Fmt, a: u64, b: u64, rm: Rm) -> (u64, u8) {
+    let (a, b) = (unpack(f, a), unpack(f, b));
+    match (a, b) {
+        (a, b) if a.is_nan() || b.is_nan() => nan_result(f, a.is_snan() || b.is_snan()),
+        (Val::Inf(_), Val::Zero(_)) | (Val::Zero(_), Val::Inf(_)) => nan_result(f, true),
+        (Val::Inf(sa), Val::Inf(sb)) => (f.inf(sa ^ sb), 0),
+        (Val::Inf(sa), Val::Finite(y)) => (f.inf(sa ^ y.sign), 0),
+        (Val::Finite(x), Val::Inf(sb)) => (f.inf(x.sign ^ sb), 0),
+        (Val::Zero(sa), Val::Zero(sb)) => (f.zero(sa ^ sb), 0),
+        (Val::Zero(sa), Val::Finite(y)) => (f.zero(sa ^ y.sign), 0),
+        (Val::Finite(x), Val::Zero(sb)) => (f.zero(x.sign ^ sb), 0),
+        (Val::Finite(x), Val::Finite(y)) => {
+            round_pack(f, x.sign ^ y.sign, x.sig * y.sig, x.exp + y.exp, false, rm)
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub(super) fn div(f: &Fmt, a: u64, b: u64, rm: Rm) -> (u64, u8) {
+    let (a, b) = (unpack(f, a), unpack(f, b));
+    match (a, b) {
+        (a, b) if a.is_nan() || b.is_nan() => nan_result(f, a.is_snan() || b.is_snan()),
+        (Val::Inf(_), Val::Inf(_)) | (Val::Zero(_), Val::Zero(_)) => nan_result(f, true),
+        (Val::Inf(sa), Val::Zero(sb)) => (f.inf(sa ^ sb), 0),
+        (Val::Inf(sa), Val::Finite(y)) => (f.inf(sa ^ y.sign), 0),
+        (Val::Zero(sa), Val::Inf(sb)) => (f.zero(sa ^ sb), 0),
+        (Val::Finite(x), Val::Inf(sb)) => (f.zero(x.sign ^ sb), 0),
+        (Val::Finite(x), Val::Zero(sb)) => (f.inf(x.sign ^ sb), DZ),
+        (Val::Zero(sa), Val::Finite(y)) => (f.zero(sa ^ y.sign), 0),
+        (Val::Finite(x), Val::Finite(y)) => {
+            // numerator msb at bit 127 and denominator msb at bit 63 give a
+            // quotient of at least 64 bits
+            let sha = 127 - msb(x.sig);
+            let shb = 63 - msb(y.sig);
+            let n = x.sig << sha;
+            let d = y.sig << shb;
+            let exp = (x.exp - sha) - (y.exp - shb);
+            round_pack(f, x.sign ^ y.sign, n / d, exp, !n.is_multiple_of(d), rm)
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn isqrt(x: u128) -> u128 {
+    let mut rem = x;
+    let mut res = 0u128;
+    let mut bit = 1u128 << 126;
+    while bit > x {
+        bit >>= 2;
+    }
+
+    while bit != 0 {
+        if rem >= res + bit {
+            rem -= res + bit;
+            res = (res >> 1) + bit;
+        } else {
+            res >>= 1;
+        }
+        bit >>= 2;
+    }
+    res
+}
+
+pub(super) fn sqrt(f: &Fmt, a: u64, rm: Rm) -> (u64, u8) {
+    match unpack(f, a) {
+        Val::Nan { snan } => nan_result(f, snan),
+        Val::Zero(s) => (f.zero(s), 0),
+        Val::Inf(false) => (f.inf(false), 0),
+        Val::Inf(true) => nan_result(f, true),
+        Val::Finite(x) if x.sign => nan_result(f, true),
+        Val::Finite(x) => {
+            // normalize to an even exponent with the msb at bit 125 or 126,
+            // so the root has at least 63 bits
+            let mut sh = 126 - msb(x.sig);
+            if (x.exp - sh) & 1 != 0 {
+                sh -= 1;
+            }
+            let sig = x.sig << sh;
+            let exp = x.exp - sh;
+
+            let r = isqrt(sig);
+            round_pack(f, false, r, exp / 2, r * r != sig, rm)
+        }
+    }
+}
+
+// computes a * b + c with a single rounding
+fn fma(f: &Fmt, a: u64, b: u64, c: u64, rm: Rm) -> (u64, u8) {
+    let (a, b, c) = (unpack(f, a), unpack(f, b), unpack(f, c));
+
+    // inf * 0 is invalid even if the addend is a quiet NaN
+    let inf_times_zero = matches!(
+        (&a, &b),
+        (Val::Inf(_), Val::Zero(_)) | (Val::Zero(_), Val::Inf(_))
+    );
+    if a.is_nan() || b.is_nan() || c.is_nan() {
+        return nan_result(
+            f,
+            a.is_snan() || b.is_snan() || c.is_snan() || inf_times_zero,
+        );
+    }
+    if inf_times_zero {
+        return nan_result(f, true);
+    }
+
+    let prod_sign = sign_of(&a) ^ sign_of(&b);
+    let prod_inf = matches!(a, Val::Inf(_)) || matches!(b, Val::Inf(_));
+    let prod_zero = matches!(a, Val::Zero(_)) || matches!(b, Val::Zero(_));
+
+    match (a, b, c) {
+        (_, _, Val::Inf(sc)) => {
+            if prod_inf && prod_sign != sc {
+                nan_result(f, true)
+            } else {
+                (f.inf(sc), 0)
+            }
+        }
+        _ if prod_inf => (f.inf(prod_sign), 0),
+        (_, _, Val::Zero(sc)) if prod_zero => (f.zero(zero_sum_sign(prod_sign, sc, rm)), 0),
+        (_, _, Val::Finite(z)) if prod_zero => round_pack(f, z.sign, z.sig, z.exp, false, rm),
+        (Val::Finite(x), Val::Finite(y), c) => {
+            let prod = Num {
+                sign: prod_sign,
+                sig: x.sig * y.sig,
+                exp: x.exp + y.exp,
+            };
+            match c {
+                Val::Zero(_) => round_pack(f, prod.sign, prod.sig, prod.exp, false, rm),
+                Val::Finite(z) => sum(f, prod, z, rm),
+                _ => unreachable!(),
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub(super) fn fmadd(f: &Fmt, a: u64, b: u64, c: u64, rm: Rm) -> (u64, u8) {
+    fma(f, a, b, c, rm)
+}
+
+pub(super) fn fmsub(f: &Fmt, a: u64, b: u64, c: u64, rm: Rm) -> (u64, u8) {
+    fma(f, a, b, c ^ f.sign_bit(), rm)
+}
+
+pub(super) fn fnmsub(f: &Fmt, a: u64, b: u64, c: u64, rm: Rm) -> (u64, u8) {
+    fma(f, a ^ f.sign_bit(), b, c, rm)
+}
+
+pub(super) fn fnmadd(f: &Fmt, a: u64, b: u64, c: u64, rm: Rm) -> (u64, u8) {
+    fma(f, a ^ f.sign_bit(), b, c ^ f.sign_bit(), rm)
+}
+
+/// Converts between floating-point formats.
+pub(super) fn convert(from: &Fmt, to: &Fmt, a: u64, rm: Rm) -> (u64, u8) {
+    match unpack(from, a) {
+        Val::Nan { snan } => nan_result(to, snan),
+        Val::Inf(s) => (to.inf(s), 0),
+        Val::Zero(s) => (to.zero(s), 0),
+        Val::Finite(x) => round_pack(to, x.sign, x.sig, x.exp, false, rm),
+    }
+}
+
+/// Converts an integer to floating-point.
+pub(super) fn from_int(f: &Fmt, x: i128, rm: Rm) -> (u64, u8) {
+    round_pack(f, x < 0, x.unsigned_abs(), 0, false, rm)
+}
+
+/// Converts to an integer in `[min, max]`, saturating and raising NV for
+/// out-of-range inputs. NaNs convert to `max`.
+pub(super) fn to_int(f: &Fmt, a: u64, rm: Rm, min: i128, max: i128) -> (i128, u8) {
+    match unpack(f, a) {
+        Val::Nan { .. } => (max, NV),
+        Val::Inf(s) => (if s { min } else { max }, NV),
+        Val::Zero(_) => (0, 0),
+        Val::Finite(x) => {
+            // anything this large is out of range of every integer type
+            if msb(x.sig) + x.exp > 64 {
+                return (if x.sign { min } else { max }, NV);
+            }
+
+            let (m, inexact) = round_at(x.sig, x.exp, false, 0, x.sign, rm);
+            let v = if x.sign { -(m as i128) } else { m as i128 };
+            if v < min {
+                (min, NV)
+            } else if v > max {
+                (max, NV)
+            } else {
+                (v, if inexact { NX } else { 0 })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RMS: [Rm; 5] = [Rm::Rne, Rm::Rtz, Rm::Rdn, Rm::Rup, Rm::Rmm];
+
+    fn s(x: f32) -> u64 {
+        x.to_bits() as u64
+    }
+
+    fn d(x: f64) -> u64 {
+        x.to_bits()
+    }
+
+    // expected results from the host, which may produce other NaNs
+    fn hs(x: f32) -> u64 {
+        if x.is_nan() {
+            F32.canonical_nan()
+        } else {
+            s(x)
+        }
+    }
+
+    fn hd(x: f64) -> u64 {
+        if x.is_nan() {
+            F64.canonical_nan()
+        } else {
+            d(x)
+        }
+    }
+
+    // a spread of interesting values, including subnormals and values near
+    // the overflow threshold
+    fn samples_f64() -> Vec<f64> {
+        let mut v = vec![
+            1.0,
+            -1.0,
+            3.0,
+            0.1,
+            -0.7,
+            1.0 / 3.0,
+            2.0f64.sqrt(),
+            1e300,
+            -1e-300,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            f64::from_bits(1),
+            f64::from_bits(0x000f_ffff_ffff_ffff),
+            123456789.0,
+            -9.87654321e-5,
+            1.0 + f64::EPSILON,
+            1.0 - f64::EPSILON / 2.0,
+        ];
+        let mut x: u64 = 0x1234_5678_9abc_def1;
+        for _ in 0..64 {
+            // xorshift
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            let f = f64::from_bits(x);
+            if f.is_finite() {
+                v.push(f);
+                v.push(f64::from_bits(
+                    (x & 0x800f_ffff_ffff_ffff) | 0x3ff0_0000_0000_0000,
+                ));
+            }
+        }
+        v
+    }
+
+    #[test]
+    fn test_rne_matches_host_f64() {
+        let v = samples_f64();
+        for &a in &v {
+            assert_eq!(
+                sqrt(&F64, d(a.abs()), Rm::Rne).0,
+                hd(a.abs().sqrt()),
+                "sqrt {a}"
+            );
+            for &b in &v {
+                assert_eq!(add(&F64, d(a), d(b), Rm::Rne).0, hd(a + b), "{a} + {b}");
+                assert_eq!(sub(&F64, d(a), d(b), Rm::Rne).0, hd(a - b), "{a} - {b}");
+                assert_eq!(mul(&F64, d(a), d(b), Rm::Rne).0, hd(a * b), "{a} * {b}");
+                assert_eq!(div(&F64, d(a), d(b), Rm::Rne).0, hd(a / b), "{a} / {b}");
+                let c = a - b * 0.5;
+                assert_eq!(
+                    fmadd(&F64, d(a), d(b), d(c), Rm::Rne).0,
+                    hd(a.mul_add(b, c)),
+                    "{a} * {b} + {c}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_rne_matches_host_f32() {
+        let v: Vec<f32> = samples_f64().iter().map(|&x| x as f32).collect();
+        for &a in &v {
+            assert_eq!(
+                sqrt(&F32, s(a.abs()), Rm::Rne).0,
+                hs(a.abs().sqrt()),
+                "sqrt {a}"
+            );
+            assert_eq!(convert(&F32, &F64, s(a), Rm::Rne).0, hd(a as f64));
+            for &b in &v {
+                assert_eq!(add(&F32, s(a), s(b), Rm::Rne).0, hs(a + b), "{a} + {b}");
+                assert_eq!(mul(&F32, s(a), s(b), Rm::Rne).0, hs(a * b), "{a} * {b}");
+                assert_eq!(div(&F32, s(a), s(b), Rm::Rne).0, hs(a / b), "{a} / {b}");
+            }
+        }
+        for &a in &samples_f64() {
+            assert_eq!(convert(&F64, &F32, d(a), Rm::Rne).0, hs(a as f32), "{a}");
+        }
+    }
+
+    #[test]
+    fn test_directed_rounding() {
+        let third_dn = div(&F64, d(1.0), d(3.0), Rm::Rdn).0;
+        let third_up = div(&F64, d(1.0), d(3.0), Rm::Rup).0;
+        assert_eq!(third_dn + 1, third_up);
+        assert_eq!(div(&F64, d(1.0), d(3.0), Rm::Rtz).0, third_dn);
+        assert_eq!(
+            div(&F64, d(-1.0), d(3.0), Rm::Rtz).0,
+            d(-f64::from_bits(third_dn))
+        );
+        assert_eq!(
+            div(&F64, d(-1.0), d(3.0), Rm::Rdn).0,
+            d(-f64::from_bits(third_up))
+        );
+
+        // ties: 1 + 2^-53 lies halfway between 1 and the next value
+        let half_ulp = f64::EPSILON / 2.0;
+        assert_eq!(add(&F64, d(1.0), d(half_ulp), Rm::Rne).0, d(1.0));
+        assert_eq!(
+            add(&F64, d(1.0), d(half_ulp), Rm::Rmm).0,
+            d(1.0 + f64::EPSILON)
+        );
+
+        // exact cancellation is -0 only when rounding down
+        for rm in RMS {
+            let z = sub(&F64, d(1.5), d(1.5), rm).0;
+            assert_eq!(z, d(if rm == Rm::Rdn { -0.0 } else { 0.0 }));
+        }
+    }
+
+    #[test]
+    fn test_flags() {
+        assert_eq!(add(&F64, d(1.0), d(2.0), Rm::Rne), (d(3.0), 0));
+        assert_eq!(add(&F64, d(1.0), d(0.1), Rm::Rne).1, NX);
+        assert_eq!(div(&F64, d(1.0), d(0.0), Rm::Rne), (d(f64::INFINITY), DZ));
+        assert_eq!(
+            div(&F64, d(0.0), d(0.0), Rm::Rne),
+            (F64.canonical_nan(), NV)
+        );
+        assert_eq!(sqrt(&F32, s(-1.0), Rm::Rne), (F32.canonical_nan(), NV));
+        assert_eq!(
+            add(&F64, d(f64::INFINITY), d(f64::NEG_INFINITY), Rm::Rne),
+            (F64.canonical_nan(), NV)
+        );
+        assert_eq!(
+            mul(&F64, d(f64::MAX), d(2.0), Rm::Rne),
+            (d(f64::INFINITY), OF | NX)
+        );
+        assert_eq!(
+            mul(&F64, d(f64::MAX), d(2.0), Rm::Rtz),
+            (d(f64::MAX), OF | NX)
+        );
+        assert_eq!(mul(&F64, d(f64::MIN_POSITIVE), d(0.3), Rm::Rne).1, UF | NX);
+        // exact subnormal results do not underflow
+        assert_eq!(
+            mul(&F64, d(f64::MIN_POSITIVE), d(0.5), Rm::Rne),
+            (d(f64::MIN_POSITIVE / 2.0), 0)
+        );
+
+        // quiet NaNs only raise NV when the operation is invalid anyway
+        let qnan = F32.canonical_nan();
+        let snan = 0x7f80_0001;
+        assert_eq!(add(&F32, qnan, s(1.0), Rm::Rne), (qnan, 0));
+        assert_eq!(add(&F32, snan, s(1.0), Rm::Rne), (qnan, NV));
+        assert_eq!(
+            fmadd(&F32, s(f32::INFINITY), s(0.0), qnan, Rm::Rne),
+            (qnan, NV)
+        );
+    }
+
+    #[test]
+    fn test_int_conversions() {
+        let (i32min, i32max) = (i32::MIN as i128, i32::MAX as i128);
+
+        assert_eq!(to_int(&F64, d(2.5), Rm::Rne, i32min, i32max), (2, NX));
+        assert_eq!(to_int(&F64, d(2.5), Rm::Rmm, i32min, i32max), (3, NX));
+        assert_eq!(to_int(&F64, d(-2.5), Rm::Rdn, i32min, i32max), (-3, NX));
+        assert_eq!(to_int(&F64, d(-2.5), Rm::Rtz, i32min, i32max), (-2, NX));
+        assert_eq!(to_int(&F64, d(7.0), Rm::Rne, i32min, i32max), (7, 0));
+        assert_eq!(to_int(&F64, d(3e9), Rm::Rne, i32min, i32max), (i32max, NV));
+        assert_eq!(
+            to_int(&F64, d(-1e300), Rm::Rne, i32min, i32max),
+            (i32min, NV)
+        );
+        assert_eq!(
+            to_int(&F64, F64.canonical_nan(), Rm::Rne, i32min, i32max),
+            (i32max, NV)
+        );
+        assert_eq!(to_int(&F32, s(-0.3), Rm::Rtz, 0, u32::MAX as i128), (0, NX));
+        assert_eq!(to_int(&F32, s(-1.0), Rm::Rtz, 0, u32::MAX as i128), (0, NV));
+        assert_eq!(
+            to_int(
+                &F64,
+                d(18446744073709549568.0),
+                Rm::Rne,
+                0,
+                u64::MAX as i128
+            ),
+            (18446744073709549568, 0)
+        );
+
+        assert_eq!(from_int(&F64, 0, Rm::Rne), (d(0.0), 0));
+        assert_eq!(from_int(&F64, -5, Rm::Rne), (d(-5.0), 0));
+        assert_eq!(from_int(&F32, 16777217, Rm::Rne), (s(16777216.0), NX));
+        assert_eq!(from_int(&F32, 16777217, Rm::Rup), (s(16777218.0), NX));
+        assert_eq!(
+            from_int(&F64, u64::MAX as i128, Rm::Rtz),
+            (d(18446744073709549568.0), NX)
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! File and descriptor syscalls.
+//!
+//! Apart from `struct stat`, the structures involved have the same layout on
+//! riscv64 and the 64-bit hosts we run on, so most calls are passed through
+//! once their guest pointers are translated.
+
+use libc;
+
+use super::{host_ret, RvInterpreterExecutor, SysResult, EINVAL};
+
+const IOV_MAX: u64 = 1024;
+const IOVEC_SIZE: usize = 16;
+
+// bounce buffer limit for getdents64, short reads are fine there
+const GETDENTS_MAX: u64 = 64 << 10;
+
+// asm-generic struct stat, used by riscv64
+const STAT_SIZE: usize = 128;
+
+// TTY ioctls, and the size of what their argument points to
+const TCGETS: u64 = 0x5401;
+const TCSETS: u64 = 0x5402;
+const TCSETSW: u64 = 0x5403;
+const TCSETSF: u64 = 0x5404;
+const TIOCGPGRP: u64 = 0x540f;
+const TIOCSPGRP: u64 = 0x5410;
+const TIOCGWINSZ: u64 = 0x5413;
+const TIOCSWINSZ: u64 = 0x5414;
+const FIONREAD: u64 = 0x541b;
+const TERMIOS_SIZE: u64 = 36;
+const WINSIZE_SIZE: u64 = 8;
+
+fn stat_to_guest(st: &libc::stat) -> [u8; STAT_SIZE] {
+    let mut b = [0u8; STAT_SIZE];
+    let mut put = |off: usize, bytes: &[u8]| b[off..off + bytes.len()].copy_from_slice(bytes);
+    put(0, &st.st_dev.to_le_bytes());
+    put(8, &st.st_ino.to_le_bytes());
+    put(16, &st.st_mode.to_le_bytes());
+    put(20, &(st.st_nlink as u32).to_le_bytes());
+    put(24, &st.st_uid.to_le_bytes());
+    put(28, &st.st_gid.to_le_bytes());
+    put(32, &st.st_rdev.to_le_bytes());
+    put(48, &st.st_size.to_le_bytes());
+    put(56, &(st.st_blksize as i32).to_le_bytes());
+    put(64, &st.st_blocks.to_le_bytes());
+    put(72, &st.st_atime.to_le_bytes());
+    put(80, &st.st_atime_nsec.to_le_bytes());
+    put(88, &st.st_mtime.to_le_bytes());
+    put(96, &st.st_mtime_nsec.to_le_bytes());
+    put(104, &st.st_ctime.to_le_bytes());
+    put(112, &st.st_ctime_nsec.to_le_bytes());
+    b
+}
+
+impl RvInterpreterExecutor {
+    pub(super) fn sys_openat(&mut self, dirfd: u64, path: u64, flags: u64, mode: u64) -> SysResult {
+        let path = self.guest_cstr(path)?;
+        host_ret(unsafe { libc::syscall(libc::SYS_openat, dirfd, path.as_ptr(), flags, mode) })
+    }
+
+    pub(super) fn sys_close(&mut self, fd: u64) -> SysResult {
+        host_ret(unsafe { libc::syscall(libc::SYS_close, fd) })
+    }
+
+    pub(super) fn sys_read(&mut self, fd: u64, buf: u64, count: u64) -> SysResult {
+        let iov = self.guest_iovecs(buf, count)?;
+        host_ret(unsafe { libc::syscall(libc::SYS_readv, fd, iov.as_ptr(), iov.len()) })
+    }
+
+    pub(super) fn sys_write(&mut self, fd: u64, buf: u64, count: u64) -> SysResult {
+        let iov = self.guest_iovecs(buf, count)?;
+        host_ret(unsafe { libc::syscall(libc::SYS_writev, fd, iov.as_ptr(), iov.len()) })
+    }
+
+    pub(super) fn sys_pread64(&mut self, fd: u64, buf: u64, count: u64, off: u64) -> SysResult {
+        let iov = self.guest_iovecs(buf, count)?;
+        host_ret(unsafe { libc::syscall(libc::SYS_preadv, fd, iov.as_ptr(), iov.len(), off) })
+    }
+
+    pub(super) fn sys_pwrite64(&mut self, fd: u64, buf: u64, count: u64, off: u64) -> SysResult {
+        let iov = self.guest_iovecs(buf, count)?;
+        host_ret(unsafe { libc::syscall(libc::SYS_pwritev, fd, iov.as_ptr(), iov.len(), off) })
+    }
+
+    // readv and writev: every buffer in the vector is translated, and split
+    // where the host memory behind it is not contiguous
+    pub(super) fn sys_iov(&mut self, nr: i64, fd: u64, iov: u64, iovcnt: u64) -> SysResult {
+        if iovcnt > IOV_MAX {
+            return Err(EINVAL);
+        }
+        let mut raw = vec![0u8; iovcnt as usize * IOVEC_SIZE];
+        self.copy_from_guest(iov, &mut raw)?;
+
+        let mut host_iov = Vec::with_capacity(iovcnt as usize);
+        for e in raw.chunks_exact(IOVEC_SIZE) {
+            let base = u64::from_le_bytes(e[0..8].try_into().unwrap());
+            let len = u64::from_le_bytes(e[8..16].try_into().unwrap());
+            host_iov.extend(self.guest_iovecs(base, len)?);
+        }
+        if host_iov.len() > IOV_MAX as usize {
+            return Err(EINVAL);
+        }
+        host_ret(unsafe { libc::syscall(nr, fd, host_iov.as_ptr(), host_iov.len()) })
+    }
+
+    pub(super) fn sys_lseek(&mut self, fd: u64, off: u64, whence: u64) -> SysResult {
+        host_ret(unsafe { libc::syscall(libc::SYS_lseek, fd, off, whence) })
+    }
+
+    pub(super) fn sys_fstat(&mut self, fd: u64, statbuf: u64) -> SysResult {
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        host_ret(unsafe { libc::syscall(libc::SYS_fstat, fd, &mut st as *mut libc::stat) })?;
+        self.copy_to_guest(statbuf, &stat_to_guest(&st))?;
+        Ok(0)
+    }
+
+    pub(super) fn sys_newfstatat(
+        &mut self,
+        dirfd: u64,
+        path: u64,
+        statbuf: u64,
+        flags: u64,
+    ) -> SysResult {
+        let path = self.guest_cstr(path)?;
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        host_ret(unsafe {
+            libc::syscall(
+                libc::SYS_newfstatat,
+                dirfd,
+                path.as_ptr(),
+                &mut st as *mut libc::stat,
+                flags,
+            )
+        })?;
+        self.copy_to_guest(statbuf, &stat_to_guest(&st))?;
+        Ok(0)
+    }
+
+    // struct linux_dirent64 is the same everywhere
+    pub(super) fn sys_getdents64(&mut self, fd: u64, dirp: u64, count: u64) -> SysResult {
+        let mut buf = vec![0u8; count.min(GETDENTS_MAX) as usize];
+        let n = host_ret(unsafe {
+            libc::syscall(libc::SYS_getdents64, fd, buf.as_mut_ptr(), buf.len())
+        })?;
+        self.copy_to_guest(dirp, &buf[..n as usize])?;
+        Ok(n)
+    }
+
+    pub(super) fn sys_readlinkat(
+        &mut self,
+        dirfd: u64,
+        path: u64,
+        buf: u64,
+        bufsiz: u64,
+    ) -> SysResult {
+        let path = self.guest_cstr(path)?;
+        let mut host_buf = vec![0u8; bufsiz.min(libc::PATH_MAX as u64) as usize];
+        let n = host_ret(unsafe {
+            libc::syscall(
+                libc::SYS_readlinkat,
+                dirfd,
+                path.as_ptr(),
+                host_buf.as_mut_ptr(),
+                host_buf.len(),
+            )
+        })?;
+        self.copy_to_guest(buf, &host_buf[..n as usize])?;
+        Ok(n)
+    }
+
+    pub(super) fn sys_faccessat(&mut self, dirfd: u64, path: u64, mode: u64) -> SysResult {
+        let path = self.guest_cstr(path)?;
+        host_ret(unsafe { libc::syscall(libc::SYS_faccessat, dirfd, path.as_ptr(), mode) })
+    }
+
+    pub(super) fn sys_fcntl(&mut self, fd: u64, cmd: u64, arg: u64) -> SysResult {
+        let arg = match cmd as i32 {
+            libc::F_DUPFD
+            | libc::F_DUPFD_CLOEXEC
+            | libc::F_GETFD
+            | libc::F_SETFD
+            | libc::F_GETFL
+            | libc::F_SETFL => arg,
+            libc::F_GETLK
+            | libc::F_SETLK
+            | libc::F_SETLKW
+            | libc::F_OFD_GETLK
+            | libc::F_OFD_SETLK
+            | libc::F_OFD_SETLKW => {
+                self.guest_buf(arg, std::mem::size_of::<libc::flock>() as u64)?
+            }
+            _ => return Err(EINVAL),
+        };
+        host_ret(unsafe { libc::syscall(libc::SYS_fcntl, fd, cmd, arg) })
+    }
+
+    pub(super) fn sys_dup3(&mut self, oldfd: u64, newfd: u64, flags: u64) -> SysResult {
+        host_ret(unsafe { libc::syscall(libc::SYS_dup3, oldfd, newfd, flags) })
+    }
+
+    pub(super) fn sys_pipe2(&mut self, fds: u64, flags: u64) -> SysResult {
+        let fds = self.guest_buf(fds, 8)?;
+        host_ret(unsafe { libc::syscall(libc::SYS_pipe2, fds, flags) })
+    }
+
+    // only the TTY queries programs commonly make at startup are supported
+    pub(super) fn sys_ioctl(&mut self, fd: u64, req: u64, arg: u64) -> SysResult {
+        let size = match req {
+            TCGETS | TCSETS | TCSETSW | TCSETSF => TERMIOS_SIZE,
+            TIOCGWINSZ | TIOCSWINSZ => WINSIZE_SIZE,
+            TIOCGPGRP | TIOCSPGRP | FIONREAD => 4,
+            _ => return Err(libc::ENOTTY as i64),
+        };
+        let arg = self.guest_buf(arg, size)?;
+        host_ret(unsafe { libc::syscall(libc::SYS_ioctl, fd, req, arg) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::EFAULT;
+    use super::*;
+    use crate::exec::mem::{GuestMmu, Placement, Prot};
+    use crate::exec::RvIsaState;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_guest_cstr_prot() {
+        let mmu = GuestMmu::new(4096);
+        let g = mmu
+            .mmap_at(0.into(), 4096, Prot::RW, Placement::Hint, 0, None)
+            .unwrap();
+        mmu.write_bytes(g, b"/tmp\0").unwrap();
+        let mmu = Arc::new(mmu);
+        let executor = RvInterpreterExecutor::new(64, RvIsaState::default(), mmu.clone());
+        assert_eq!(executor.guest_cstr(g.as_u64()).unwrap().as_bytes(), b"/tmp");
+
+        // still mapped, but the guest could not read it itself
+        let none = Prot {
+            read: false,
+            write: false,
+            exec: false,
+        };
+        mmu.mprotect(g, 4096, none).unwrap();
+        assert_eq!(executor.guest_cstr(g.as_u64()), Err(EFAULT));
+    }
+
+    #[test]
+    fn test_stat_layout() {
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        st.st_ino = 0x1122;
+        st.st_mode = 0o100644;
+        st.st_nlink = 3;
+        st.st_size = 0x1234_5678;
+        st.st_blksize = 4096;
+        st.st_mtime_nsec = 999;
+
+        let b = stat_to_guest(&st);
+        let u32_at = |off: usize| u32::from_le_bytes(b[off..off + 4].try_into().unwrap());
+        let u64_at = |off: usize| u64::from_le_bytes(b[off..off + 8].try_into().unwrap());
+        assert_eq!(u64_at(8), 0x1122);
+        assert_eq!(u32_at(16), 0o100644);
+        assert_eq!(u32_at(20), 3);
+        assert_eq!(u64_at(48), 0x1234_5678);
+        assert_eq!(u32_at(56), 4096);
+        assert_eq!(u64_at(96), 999);
+    }
+}
@@ -0,0 +1,142 @@
+//! Memory-management syscalls, backed by `GuestMmu`.
+
+use libc;
+
+use super::{io_errno, RvInterpreterExecutor, SysResult, EINVAL};
+use crate::exec::mem::{Placement, Prot};
+
+// mmap(2) flags the host gets to see as they are
+const MAP_PASSTHROUGH: i32 = libc::MAP_NORESERVE | libc::MAP_POPULATE | libc::MAP_STACK;
+
+// riscv_flush_icache(2) flag limiting the flush to the calling thread
+const FLUSH_ICACHE_LOCAL: u64 = 1;
+
+fn prot_from_guest(prot: u64) -> Result<Prot, i64> {
+    if prot & !((libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC) as u64) != 0 {
+        return Err(EINVAL);
+    }
+    Ok(Prot {
+        read: prot & libc::PROT_READ as u64 != 0,
+        write: prot & libc::PROT_WRITE as u64 != 0,
+        exec: prot & libc::PROT_EXEC as u64 != 0,
+    })
+}
+
+impl RvInterpreterExecutor {
+    pub(super) fn sys_brk(&mut self, addr: u64) -> SysResult {
+        Ok(self.mmu.brk(addr) as i64)
+    }
+
+    pub(super) fn sys_mmap(
+        &mut self,
+        addr: u64,
+        len: u64,
+        prot: u64,
+        flags: u64,
+        fd: u64,
+        off: u64,
+    ) -> SysResult {
+        let prot = prot_from_guest(prot)?;
+        let flags = flags as i32;
+        let mut host_flags = flags & MAP_PASSTHROUGH;
+        match flags & libc::MAP_TYPE {
+            libc::MAP_SHARED | libc::MAP_SHARED_VALIDATE => host_flags |= libc::MAP_SHARED,
+            libc::MAP_PRIVATE => {}
+            _ => return Err(EINVAL),
+        }
+
+        let placement = if flags & libc::MAP_FIXED_NOREPLACE != 0 {
+            Placement::FixedNoReplace
+        } else if flags & libc::MAP_FIXED != 0 {
+            Placement::Fixed
+        } else {
+            Placement::Hint
+        };
+        let file = if flags & libc::MAP_ANONYMOUS != 0 {
+            None
+        } else {
+            if !off.is_multiple_of(self.mmu.page_size() as u64) {
+                return Err(EINVAL);
+            }
+            Some((fd as i32, off))
+        };
+
+        self.mmu
+            .mmap_at(addr.into(), len as usize, prot, placement, host_flags, file)
+            .map(|g| g.as_u64() as i64)
+            .map_err(io_errno)
+    }
+
+    pub(super) fn sys_munmap(&mut self, addr: u64, len: u64) -> SysResult {
+        if len == 0 || !addr.is_multiple_of(self.mmu.page_size() as u64) {
+            return Err(EINVAL);
+        }
+        self.mmu.munmap(addr.into(), len as usize);
+        Ok(0)
+    }
+
+    pub(super) fn sys_mprotect(&mut self, addr: u64, len: u64, prot: u64) -> SysResult {
+        let prot = prot_from_guest(prot)?;
+        if !addr.is_multiple_of(self.mmu.page_size() as u64) {
+            return Err(EINVAL);
+        }
+        self.mmu
+            .mprotect(addr.into(), len as usize, prot)
+            .map(|_| 0)
+            .map_err(io_errno)
+    }
+
+    pub(super) fn sys_mremap(
+        &mut self,
+        old: u64,
+        old_len: u64,
+        new_len: u64,
+        flags: u64,
+        new_addr: u64,
+    ) -> SysResult {
+        let flags = flags as i32;
+        let may_move = flags & libc::MREMAP_MAYMOVE != 0;
+        let fixed = flags & libc::MREMAP_FIXED != 0;
+        if flags & !(libc::MREMAP_MAYMOVE | libc::MREMAP_FIXED) != 0 || (fixed && !may_move) {
+            return Err(EINVAL);
+        }
+
+        let new_addr = if fixed { Some(new_addr.into()) } else { None };
+        self.mmu
+            .mremap(
+                old.into(),
+                old_len as usize,
+                new_len as usize,
+                may_move,
+                new_addr,
+            )
+            .map(|g| g.as_u64() as i64)
+            .map_err(io_errno)
+    }
+
+    pub(super) fn sys_madvise(&mut self, addr: u64, len: u64, advice: u64) -> SysResult {
+        self.mmu
+            .madvise(addr.into(), len as usize, advice as i32)
+            .map(|_| 0)
+            .map_err(io_errno)
+    }
+
+    // The whole cache is dropped whatever the range, for this thread only
+    // with SYS_RISCV_FLUSH_ICACHE_LOCAL.
+    pub(super) fn sys_riscv_flush_icache(
+        &mut self,
+        _start: u64,
+        _end: u64,
+        flags: u64,
+    ) -> SysResult {
+        match flags {
+            0 => self.mmu.flush_caches(),
+            FLUSH_ICACHE_LOCAL => {
+                self.icache.flush();
+                self.blocks.flush();
+            }
+            _ => return Err(EINVAL),
+        }
+        Ok(0)
+    }
+}
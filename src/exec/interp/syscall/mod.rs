@@ -0,0 +1,221 @@
+use std::ffi::CString;
+
+use libc;
+
+use super::{RvInterpreterExecutor, StopReason};
+use crate::exec::mem::Access;
+
+mod fs;
+mod mm;
+mod signal;
+mod thread;
+
+// riscv64 uses the asm-generic syscall numbers
+const SYS_DUP3: u64 = 24;
+const SYS_FCNTL: u64 = 25;
+const SYS_IOCTL: u64 = 29;
+const SYS_FACCESSAT: u64 = 48;
+const SYS_OPENAT: u64 = 56;
+const SYS_CLOSE: u64 = 57;
+const SYS_PIPE2: u64 = 59;
+const SYS_GETDENTS64: u64 = 61;
+const SYS_LSEEK: u64 = 62;
+const SYS_READ: u64 = 63;
+const SYS_WRITE: u64 = 64;
+const SYS_READV: u64 = 65;
+const SYS_WRITEV: u64 = 66;
+const SYS_PREAD64: u64 = 67;
+const SYS_PWRITE64: u64 = 68;
+const SYS_READLINKAT: u64 = 78;
+const SYS_NEWFSTATAT: u64 = 79;
+const SYS_FSTAT: u64 = 80;
+const SYS_EXIT: u64 = 93;
+const SYS_EXIT_GROUP: u64 = 94;
+const SYS_SET_TID_ADDRESS: u64 = 96;
+const SYS_FUTEX: u64 = 98;
+const SYS_SET_ROBUST_LIST: u64 = 99;
+const SYS_KILL: u64 = 129;
+const SYS_TGKILL: u64 = 131;
+const SYS_SIGALTSTACK: u64 = 132;
+const SYS_RT_SIGACTION: u64 = 134;
+const SYS_RT_SIGPROCMASK: u64 = 135;
+const SYS_RT_SIGRETURN: u64 = 139;
+const SYS_GETPID: u64 = 172;
+const SYS_GETTID: u64 = 178;
+const SYS_BRK: u64 = 214;
+const SYS_MUNMAP: u64 = 215;
+const SYS_MREMAP: u64 = 216;
+const SYS_CLONE: u64 = 220;
+const SYS_MMAP: u64 = 222;
+const SYS_MPROTECT: u64 = 226;
+const SYS_MADVISE: u64 = 233;
+const SYS_RISCV_FLUSH_ICACHE: u64 = 259;
+
+const EFAULT: i64 = libc::EFAULT as i64;
+const EINTR: i64 = libc::EINTR as i64;
+const EINVAL: i64 = libc::EINVAL as i64;
+const ENOSYS: i64 = libc::ENOSYS as i64;
+
+/// Result of a syscall as seen by the guest: the return value, or a positive
+/// errno that is handed back negated.
+type SysResult = Result<i64, i64>;
+
+// host syscalls report errors through errno
+fn host_ret(ret: libc::c_long) -> SysResult {
+    if ret == -1 {
+        Err(std::io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EIO) as i64)
+    } else {
+        Ok(ret)
+    }
+}
+
+fn io_errno(e: std::io::Error) -> i64 {
+    e.raw_os_error().unwrap_or(libc::EINVAL) as i64
+}
+
+impl RvInterpreterExecutor {
+    pub(super) fn do_syscall(&mut self) -> StopReason {
+        let nr = self.state.get_x(17); // a7
+        let arg0 = self.state.get_x(10); // a0
+        let arg1 = self.state.get_x(11); // a1
+        let arg2 = self.state.get_x(12); // a2
+        let arg3 = self.state.get_x(13); // a3
+        let arg4 = self.state.get_x(14); // a4
+        let arg5 = self.state.get_x(15); // a5
+
+        if self.debug {
+            println!(
+                "syscall: {} ({:#x}, {:#x}, {:#x}, {:#x}, {:#x}, {:#x})",
+                nr, arg0, arg1, arg2, arg3, arg4, arg5
+            );
+        }
+        let ret = match nr {
+            SYS_DUP3 => self.sys_dup3(arg0, arg1, arg2),
+            SYS_FCNTL => self.sys_fcntl(arg0, arg1, arg2),
+            SYS_IOCTL => self.sys_ioctl(arg0, arg1, arg2),
+            SYS_FACCESSAT => self.sys_faccessat(arg0, arg1, arg2),
+            SYS_OPENAT => self.sys_openat(arg0, arg1, arg2, arg3),
+            SYS_CLOSE => self.sys_close(arg0),
+            SYS_PIPE2 => self.sys_pipe2(arg0, arg1),
+            SYS_GETDENTS64 => self.sys_getdents64(arg0, arg1, arg2),
+            SYS_LSEEK => self.sys_lseek(arg0, arg1, arg2),
+            SYS_READ => self.sys_read(arg0, arg1, arg2),
+            SYS_WRITE => self.sys_write(arg0, arg1, arg2),
+            SYS_READV => self.sys_iov(libc::SYS_readv, arg0, arg1, arg2),
+            SYS_WRITEV => self.sys_iov(libc::SYS_writev, arg0, arg1, arg2),
+            SYS_PREAD64 => self.sys_pread64(arg0, arg1, arg2, arg3),
+            SYS_PWRITE64 => self.sys_pwrite64(arg0, arg1, arg2, arg3),
+            SYS_READLINKAT => self.sys_readlinkat(arg0, arg1, arg2, arg3),
+            SYS_NEWFSTATAT => self.sys_newfstatat(arg0, arg1, arg2, arg3),
+            SYS_FSTAT => self.sys_fstat(arg0, arg1),
+            SYS_EXIT => return self.sys_exit(arg0),
+            SYS_EXIT_GROUP => return StopReason::Exit(arg0 as i32),
+            SYS_SET_TID_ADDRESS => self.sys_set_tid_address(arg0),
+            SYS_FUTEX => self.sys_futex(arg0, arg1, arg2, arg3, arg4, arg5),
+            SYS_SET_ROBUST_LIST => self.sys_set_robust_list(arg0, arg1),
+            SYS_KILL => self.sys_kill(arg0, arg1),
+            SYS_TGKILL => self.sys_tgkill(arg0, arg1, arg2),
+            SYS_SIGALTSTACK => self.sys_sigaltstack(arg0, arg1),
+            SYS_RT_SIGACTION => self.sys_rt_sigaction(arg0, arg1, arg2, arg3),
+            SYS_RT_SIGPROCMASK => self.sys_rt_sigprocmask(arg0, arg1, arg2, arg3),
+            // all registers come from the signal frame, a0 included
+            SYS_RT_SIGRETURN => return self.sys_rt_sigreturn(),
+            SYS_GETPID => self.sys_getpid(),
+            SYS_GETTID => self.sys_gettid(),
+            SYS_BRK => self.sys_brk(arg0),
+            SYS_MUNMAP => self.sys_munmap(arg0, arg1),
+            SYS_MREMAP => self.sys_mremap(arg0, arg1, arg2, arg3, arg4),
+            SYS_CLONE => self.sys_clone(arg0, arg1, arg2, arg3, arg4),
+            SYS_MMAP => self.sys_mmap(arg0, arg1, arg2, arg3, arg4, arg5),
+            SYS_MPROTECT => self.sys_mprotect(arg0, arg1, arg2),
+            SYS_MADVISE => self.sys_madvise(arg0, arg1, arg2),
+            SYS_RISCV_FLUSH_ICACHE => self.sys_riscv_flush_icache(arg0, arg1, arg2),
+
+            _ => {
+                // guests probe for optional syscalls, so this is only noise
+                // unless debugging
+                if self.debug {
+                    println!(
+                        "syscall: unimplemented: {} ({:#x}, {:#x}, {:#x}, {:#x}, {:#x}, {:#x})",
+                        nr, arg0, arg1, arg2, arg3, arg4, arg5
+                    );
+                }
+                Err(ENOSYS)
+            }
+        };
+
+        // the signal that interrupted a blocking syscall is delivered before
+        // the next instruction, which may restart the syscall
+        self.restart_a0 = match ret {
+            Err(EINTR) if self.signal_pending() => Some(arg0),
+            _ => None,
+        };
+        let ret = ret.unwrap_or_else(|errno| -errno);
+        if self.debug {
+            println!("syscall: {} = {:#x}", nr, ret);
+        }
+        self.sx(10, ret as u64);
+        StopReason::Next
+    }
+
+    /// Translates a small guest buffer that the host kernel is going to
+    /// access in place, failing with EFAULT unless all of it is mapped and
+    /// contiguous on the host.
+    fn guest_buf(&self, gaddr: u64, len: u64) -> Result<u64, i64> {
+        if len == 0 {
+            return Ok(gaddr);
+        }
+        match self.mmu.host_chunks(gaddr.into(), len as usize).as_deref() {
+            Some([(h, _)]) => Ok(h.as_u64()),
+            _ => Err(EFAULT),
+        }
+    }
+
+    /// Translates a guest buffer of any size into host iovecs, one per run
+    /// of host-contiguous memory.
+    fn guest_iovecs(&self, gaddr: u64, len: u64) -> Result<Vec<libc::iovec>, i64> {
+        let chunks = self
+            .mmu
+            .host_chunks(gaddr.into(), len as usize)
+            .ok_or(EFAULT)?;
+        Ok(chunks
+            .into_iter()
+            .map(|(h, n)| libc::iovec {
+                iov_base: h.as_u64() as *mut libc::c_void,
+                iov_len: n,
+            })
+            .collect())
+    }
+
+    /// Copies a NUL-terminated guest string of at most PATH_MAX bytes.
+    fn guest_cstr(&self, gaddr: u64) -> Result<CString, i64> {
+        let page = self.mmu.page_size() as u64;
+        let end = gaddr.saturating_add(libc::PATH_MAX as u64);
+
+        // mappings are checked once per page, like a load would
+        let mut s = Vec::new();
+        let mut p = gaddr;
+        while p < end {
+            let h = self.translate_byte(p, Access::Read).map_err(|_| EFAULT)? as *const u8;
+            let n = (((p & !(page - 1)) + page).min(end) - p) as usize;
+            let bytes = unsafe { std::slice::from_raw_parts(h, n) };
+            if let Some(i) = bytes.iter().position(|&b| b == 0) {
+                s.extend_from_slice(&bytes[..i]);
+                return Ok(CString::new(s).unwrap());
+            }
+            s.extend_from_slice(bytes);
+            p += n as u64;
+        }
+        Err(libc::ENAMETOOLONG as i64)
+    }
+
+    pub(super) fn copy_to_guest(&self, gaddr: u64, data: &[u8]) -> Result<(), i64> {
+        self.mmu.write_bytes(gaddr.into(), data).map_err(|_| EFAULT)
+    }
+
+    pub(super) fn copy_from_guest(&self, gaddr: u64, data: &mut [u8]) -> Result<(), i64> {
+        self.mmu.read_bytes(gaddr.into(), data).map_err(|_| EFAULT)
+    }
+}
@@ -0,0 +1,200 @@
+//! Signal syscalls. Guest signals are emulated entirely; only signals for
+//! other processes reach the host.
+
+use libc;
+
+use super::{host_ret, RvInterpreterExecutor, SysResult, EINVAL};
+use crate::exec::interp::signal::{
+    sigbit, SigAction, SigInfo, NSIG, SIG_IGN, SI_TKILL, SI_USER, SS_DISABLE, UNBLOCKABLE,
+};
+use crate::exec::StopReason;
+
+const SIGSET_SIZE: u64 = 8;
+const SIGACTION_SIZE: usize = 24;
+const STACK_T_SIZE: usize = 24;
+const MINSIGSTKSZ: u64 = 2048;
+
+const SIG_BLOCK: u64 = 0;
+const SIG_UNBLOCK: u64 = 1;
+const SIG_SETMASK: u64 = 2;
+
+fn valid_signo(sig: u64) -> Result<i32, i64> {
+    if sig == 0 || sig > NSIG {
+        return Err(EINVAL);
+    }
+    Ok(sig as i32)
+}
+
+impl RvInterpreterExecutor {
+    pub(super) fn sys_rt_sigaction(
+        &mut self,
+        sig: u64,
+        act: u64,
+        oact: u64,
+        sigsetsize: u64,
+    ) -> SysResult {
+        let signo = valid_signo(sig)?;
+        if sigsetsize != SIGSET_SIZE {
+            return Err(EINVAL);
+        }
+
+        let new = if act != 0 {
+            if UNBLOCKABLE & sigbit(signo) != 0 {
+                return Err(EINVAL);
+            }
+            let mut b = [0u8; SIGACTION_SIZE];
+            self.copy_from_guest(act, &mut b)?;
+            let word = |i: usize| u64::from_le_bytes(b[i * 8..i * 8 + 8].try_into().unwrap());
+            Some(SigAction {
+                handler: word(0),
+                flags: word(1),
+                mask: word(2) & !UNBLOCKABLE,
+            })
+        } else {
+            None
+        };
+
+        let old = {
+            let mut actions = self.signals.actions.lock().unwrap();
+            let old = actions[signo as usize - 1];
+            if let Some(x) = new {
+                actions[signo as usize - 1] = x;
+            }
+            old
+        };
+        // ignoring a signal discards it if it is pending
+        if new.is_some_and(|x| x.handler == SIG_IGN) {
+            self.signals.pending.discard(signo);
+            self.pending.discard(signo);
+        }
+
+        if oact != 0 {
+            let mut b = [0u8; SIGACTION_SIZE];
+            b[0..8].copy_from_slice(&old.handler.to_le_bytes());
+            b[8..16].copy_from_slice(&old.flags.to_le_bytes());
+            b[16..24].copy_from_slice(&old.mask.to_le_bytes());
+            self.copy_to_guest(oact, &b)?;
+        }
+        Ok(0)
+    }
+
+    pub(super) fn sys_rt_sigprocmask(
+        &mut self,
+        how: u64,
+        set: u64,
+        oset: u64,
+        sigsetsize: u64,
+    ) -> SysResult {
+        if sigsetsize != SIGSET_SIZE {
+            return Err(EINVAL);
+        }
+        let old = self.blocked;
+
+        if set != 0 {
+            let mut b = [0u8; 8];
+            self.copy_from_guest(set, &mut b)?;
+            let set = u64::from_le_bytes(b);
+            let blocked = match how {
+                SIG_BLOCK => old | set,
+                SIG_UNBLOCK => old & !set,
+                SIG_SETMASK => set,
+                _ => return Err(EINVAL),
+            };
+            self.blocked = blocked & !UNBLOCKABLE;
+        }
+
+        if oset != 0 {
+            self.copy_to_guest(oset, &old.to_le_bytes())?;
+        }
+        Ok(0)
+    }
+
+    pub(super) fn sys_sigaltstack(&mut self, ss: u64, old_ss: u64) -> SysResult {
+        let sp = self.state.get_x(2);
+        let old = self.altstack;
+
+        if ss != 0 {
+            let mut b = [0u8; STACK_T_SIZE];
+            self.copy_from_guest(ss, &mut b)?;
+            // the stack cannot be changed while it is in use
+            if old.contains(sp) {
+                return Err(libc::EPERM as i64);
+            }
+            let flags = i32::from_le_bytes(b[8..12].try_into().unwrap());
+            let mut new = old;
+            match flags {
+                SS_DISABLE => new.flags = SS_DISABLE,
+                0 => {
+                    let size = u64::from_le_bytes(b[16..24].try_into().unwrap());
+                    if size < MINSIGSTKSZ {
+                        return Err(libc::ENOMEM as i64);
+                    }
+                    new.sp = u64::from_le_bytes(b[0..8].try_into().unwrap());
+                    new.size = size;
+                    new.flags = 0;
+                }
+                _ => return Err(EINVAL),
+            }
+            self.altstack = new;
+        }
+
+        if old_ss != 0 {
+            let mut b = [0u8; STACK_T_SIZE];
+            b[0..8].copy_from_slice(&old.sp.to_le_bytes());
+            b[8..12].copy_from_slice(&old.flags_at(sp).to_le_bytes());
+            b[16..24].copy_from_slice(&old.size.to_le_bytes());
+            self.copy_to_guest(old_ss, &b)?;
+        }
+        Ok(0)
+    }
+
+    pub(super) fn sys_kill(&mut self, pid: u64, sig: u64) -> SysResult {
+        let own = unsafe { libc::getpid() } as i64;
+        let pid = pid as i32 as i64;
+        if pid != own && pid != 0 {
+            return host_ret(unsafe { libc::syscall(libc::SYS_kill, pid, sig) });
+        }
+        if sig == 0 {
+            return Ok(0);
+        }
+        let signo = valid_signo(sig)?;
+        self.signals.pending.push(SigInfo::user(signo, SI_USER));
+        Ok(0)
+    }
+
+    pub(super) fn sys_tgkill(&mut self, tgid: u64, tid: u64, sig: u64) -> SysResult {
+        let own = unsafe { libc::getpid() } as i64;
+        let (tgid, tid) = (tgid as i32 as i64, tid as i32 as i64);
+        if tgid <= 0 || tid <= 0 {
+            return Err(EINVAL);
+        }
+        if tgid != own {
+            return host_ret(unsafe { libc::syscall(libc::SYS_tgkill, tgid, tid, sig) });
+        }
+        if sig == 0 {
+            return if self.signals.has_thread(tid) {
+                Ok(0)
+            } else {
+                Err(libc::ESRCH as i64)
+            };
+        }
+
+        let info = SigInfo::user(valid_signo(sig)?, SI_TKILL);
+        if tid == unsafe { libc::gettid() } as i64 {
+            self.pending.push(info);
+            Ok(0)
+        } else if self.signals.send_to_thread(tid, info) {
+            Ok(0)
+        } else {
+            Err(libc::ESRCH as i64)
+        }
+    }
+
+    pub(super) fn sys_rt_sigreturn(&mut self) -> StopReason {
+        self.sigreturn()
+    }
+
+    pub(super) fn sys_getpid(&mut self) -> SysResult {
+        Ok(unsafe { libc::getpid() } as i64)
+    }
+}
@@ -0,0 +1,165 @@
+//! Threads and futexes.
+//!
+//! Every guest thread runs on a host thread of its own. Guest memory is
+//! plain host memory, so futexes are the host's, waited on and woken at
+//! the host address a guest futex word translates to.
+
+use std::sync::mpsc;
+
+use libc;
+
+use super::{host_ret, RvInterpreterExecutor, SysResult, EINVAL, ENOSYS};
+use crate::exec::StopReason;
+
+// what host threads already share with each other
+const CLONE_THREAD_FLAGS: u64 = (libc::CLONE_VM
+    | libc::CLONE_FS
+    | libc::CLONE_FILES
+    | libc::CLONE_SIGHAND
+    | libc::CLONE_THREAD) as u64;
+const CLONE_OPTIONAL_FLAGS: u64 = (libc::CLONE_SYSVSEM
+    | libc::CLONE_SETTLS
+    | libc::CLONE_PARENT_SETTID
+    | libc::CLONE_CHILD_SETTID
+    | libc::CLONE_CHILD_CLEARTID
+    | libc::CLONE_DETACHED) as u64;
+// the low byte is the exit signal, which threads do not have
+const CSIGNAL: u64 = 0xff;
+
+// struct robust_list_head
+const ROBUST_LIST_HEAD_SIZE: u64 = 24;
+const TIMESPEC_SIZE: u64 = 16;
+
+fn host_gettid() -> i64 {
+    unsafe { libc::syscall(libc::SYS_gettid) }
+}
+
+// runs a thread made by clone(2) until it is done, leaving the end of the
+// process, if it comes to that, to the owner of the main thread
+fn run_thread(mut ex: RvInterpreterExecutor, pc: u64) {
+    let reason = ex.exec(pc);
+    ex.thread_stopped(reason);
+}
+
+impl RvInterpreterExecutor {
+    // riscv64 has the CLONE_BACKWARDS argument order
+    pub(super) fn sys_clone(
+        &mut self,
+        flags: u64,
+        stack: u64,
+        ptid: u64,
+        tls: u64,
+        ctid: u64,
+    ) -> SysResult {
+        // only threads are supported, not new processes
+        let flags = flags & !CSIGNAL;
+        if flags & CLONE_THREAD_FLAGS != CLONE_THREAD_FLAGS
+            || flags & !(CLONE_THREAD_FLAGS | CLONE_OPTIONAL_FLAGS) != 0
+        {
+            return Err(EINVAL);
+        }
+        let has = |f: libc::c_int| flags & f as u64 != 0;
+
+        let mut child = self.new_thread();
+        child.state.set_x(10, 0);
+        if stack != 0 {
+            child.state.set_x(2, stack);
+        }
+        if has(libc::CLONE_SETTLS) {
+            child.state.set_x(4, tls);
+        }
+        if has(libc::CLONE_CHILD_CLEARTID) {
+            child.clear_child_tid = ctid;
+        }
+        // resume right after the ecall
+        let pc = self.state.get_pc() + 4;
+
+        // the TIDs are stored before either thread returns, like Linux does
+        let parent_settid = has(libc::CLONE_PARENT_SETTID);
+        let child_settid = has(libc::CLONE_CHILD_SETTID);
+        let (tx, rx) = mpsc::channel();
+        let spawned = std::thread::Builder::new().spawn(move || {
+            let tid = host_gettid();
+            child.signals.register(tid, child.pending.clone());
+            if parent_settid {
+                let _ = child.copy_to_guest(ptid, &(tid as u32).to_le_bytes());
+            }
+            if child_settid {
+                let _ = child.copy_to_guest(ctid, &(tid as u32).to_le_bytes());
+            }
+            tx.send(tid).unwrap();
+            run_thread(child, pc);
+        });
+        match spawned {
+            Ok(_) => Ok(rx.recv().unwrap()),
+            Err(e) => {
+                self.threads
+                    .live
+                    .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                Err(e.raw_os_error().unwrap_or(libc::EAGAIN) as i64)
+            }
+        }
+    }
+
+    // exit(2): the thread's exit is announced through clear_child_tid
+    pub(super) fn sys_exit(&mut self, code: u64) -> StopReason {
+        self.signals.unregister(host_gettid());
+        if self.clear_child_tid != 0 && self.copy_to_guest(self.clear_child_tid, &[0; 4]).is_ok() {
+            if let Ok(uaddr) = self.guest_buf(self.clear_child_tid, 4) {
+                unsafe {
+                    libc::syscall(libc::SYS_futex, uaddr, libc::FUTEX_WAKE, 1);
+                }
+            }
+        }
+        StopReason::ExitThread(code as i32)
+    }
+
+    pub(super) fn sys_set_tid_address(&mut self, tidptr: u64) -> SysResult {
+        self.clear_child_tid = tidptr;
+        Ok(host_gettid())
+    }
+
+    // the list is recorded but not walked when the thread dies, so waiters
+    // on a dead thread's robust mutexes are not told about it
+    pub(super) fn sys_set_robust_list(&mut self, head: u64, len: u64) -> SysResult {
+        if len != ROBUST_LIST_HEAD_SIZE {
+            return Err(EINVAL);
+        }
+        self.robust_list = head;
+        Ok(0)
+    }
+
+    pub(super) fn sys_gettid(&mut self) -> SysResult {
+        Ok(host_gettid())
+    }
+
+    pub(super) fn sys_futex(
+        &mut self,
+        uaddr: u64,
+        op: u64,
+        val: u64,
+        timeout: u64,
+        uaddr2: u64,
+        val3: u64,
+    ) -> SysResult {
+        let cmd = op as i32 & !(libc::FUTEX_PRIVATE_FLAG | libc::FUTEX_CLOCK_REALTIME);
+        let uaddr = self.guest_buf(uaddr, 4)?;
+        // the fourth argument is either a timeout or a second count
+        let (timeout, uaddr2) = match cmd {
+            libc::FUTEX_WAIT | libc::FUTEX_WAIT_BITSET => {
+                let timeout = if timeout != 0 {
+                    self.guest_buf(timeout, TIMESPEC_SIZE)?
+                } else {
+                    0
+                };
+                (timeout, uaddr2)
+            }
+            libc::FUTEX_WAKE | libc::FUTEX_WAKE_BITSET => (timeout, uaddr2),
+            libc::FUTEX_REQUEUE | libc::FUTEX_CMP_REQUEUE | libc::FUTEX_WAKE_OP => {
+                (timeout, self.guest_buf(uaddr2, 4)?)
+            }
+            _ => return Err(ENOSYS),
+        };
+        host_ret(unsafe { libc::syscall(libc::SYS_futex, uaddr, op, val, timeout, uaddr2, val3) })
+    }
+}
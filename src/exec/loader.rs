@@ -0,0 +1,514 @@
+//! ELF64 loader for RISC-V executables.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::mem::{GuestMmu, Prot};
+use super::stack::{AT_BASE, AT_ENTRY, AT_PHDR, AT_PHENT, AT_PHNUM};
+
+const ELFMAG: &[u8; 4] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const EM_RISCV: u16 = 243;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+const PF_R: u32 = 1 << 2;
+
+const EF_RISCV_RVC: u32 = 0x1;
+const EF_RISCV_FLOAT_ABI: u32 = 0x6;
+const EF_RISCV_FLOAT_ABI_QUAD: u32 = 0x6;
+const EF_RISCV_RVE: u32 = 0x8;
+const EF_RISCV_TSO: u32 = 0x10;
+
+/// Where position-independent executables are loaded, 2/3 of the Sv39 user
+/// address space like Linux does.
+pub const ET_DYN_BASE: u64 = 0x2a_aaaa_a000;
+
+/// Where the dynamic linker is loaded, out of the way of the executable and
+/// its heap.
+pub const INTERP_BASE: u64 = 0x3f_0000_0000;
+
+/// Result of loading an ELF image into guest memory.
+#[derive(Debug)]
+pub struct LoadedElf {
+    /// Entry point, already relocated.
+    pub entry: u64,
+    /// Guest address of the program headers.
+    pub phdr: u64,
+    pub phnum: usize,
+    /// Difference between the runtime and link-time addresses.
+    pub load_bias: u64,
+    /// Page-aligned end of the highest segment, where the heap starts.
+    pub brk: u64,
+    /// The program interpreter requested with `PT_INTERP`, if any.
+    pub interp: Option<PathBuf>,
+}
+
+/// An executable together with its dynamic linker.
+#[derive(Debug)]
+pub struct LoadedProgram {
+    pub exe: LoadedElf,
+    pub interp: Option<LoadedElf>,
+}
+
+impl LoadedProgram {
+    /// Where execution starts: the dynamic linker's entry if there is one,
+    /// the executable's otherwise.
+    pub fn entry(&self) -> u64 {
+        match &self.interp {
+            Some(i) => i.entry,
+            None => self.exe.entry,
+        }
+    }
+
+    /// The auxiliary vector entries describing the loaded images.
+    pub fn auxv(&self) -> Vec<(u64, u64)> {
+        vec![
+            (AT_PHDR, self.exe.phdr),
+            (AT_PHENT, PHDR_SIZE as u64),
+            (AT_PHNUM, self.exe.phnum as u64),
+            (AT_BASE, self.interp.as_ref().map_or(0, |i| i.load_bias)),
+            (AT_ENTRY, self.exe.entry),
+        ]
+    }
+}
+
+struct Ehdr {
+    e_type: u16,
+    e_machine: u16,
+    e_entry: u64,
+    e_phoff: u64,
+    e_flags: u32,
+    e_phentsize: u16,
+    e_phnum: u16,
+}
+
+struct Phdr {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u16(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(b[off..off + 2].try_into().unwrap())
+}
+
+fn read_u32(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(b[off..off + 4].try_into().unwrap())
+}
+
+fn read_u64(b: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(b[off..off + 8].try_into().unwrap())
+}
+
+fn parse_ehdr(image: &[u8]) -> io::Result<Ehdr> {
+    if image.len() < EHDR_SIZE || &image[0..4] != ELFMAG {
+        return Err(invalid("not an ELF file"));
+    }
+    if image[4] != ELFCLASS64 || image[5] != ELFDATA2LSB || image[6] != EV_CURRENT {
+        return Err(invalid("not a little-endian ELF64 file"));
+    }
+
+    let ehdr = Ehdr {
+        e_type: read_u16(image, 16),
+        e_machine: read_u16(image, 18),
+        e_entry: read_u64(image, 24),
+        e_phoff: read_u64(image, 32),
+        e_flags: read_u32(image, 48),
+        e_phentsize: read_u16(image, 54),
+        e_phnum: read_u16(image, 56),
+    };
+
+    if ehdr.e_machine != EM_RISCV {
+        return Err(invalid("not a RISC-V executable"));
+    }
+    if ehdr.e_type != ET_EXEC && ehdr.e_type != ET_DYN {
+        return Err(invalid("not an executable or shared object"));
+    }
+    if ehdr.e_phentsize as usize != PHDR_SIZE {
+        return Err(invalid("bad program header size"));
+    }
+    check_flags(ehdr.e_flags)?;
+
+    Ok(ehdr)
+}
+
+// We implement RV64GC, so both compressed code and the soft, single and
+// double float ABIs are fine; RVE and the quad float ABI are not.
+fn check_flags(flags: u32) -> io::Result<()> {
+    let known = EF_RISCV_RVC | EF_RISCV_FLOAT_ABI | EF_RISCV_RVE | EF_RISCV_TSO;
+    if flags & !known != 0 {
+        return Err(invalid("unknown ELF flags"));
+    }
+    if flags & EF_RISCV_RVE != 0 {
+        return Err(invalid("RV64E executables are not supported"));
+    }
+    if flags & EF_RISCV_FLOAT_ABI == EF_RISCV_FLOAT_ABI_QUAD {
+        return Err(invalid("the quad-float ABI is not supported"));
+    }
+    Ok(())
+}
+
+fn parse_phdrs(image: &[u8], ehdr: &Ehdr) -> io::Result<Vec<Phdr>> {
+    let start = ehdr.e_phoff as usize;
+    let end = (ehdr.e_phnum as usize)
+        .checked_mul(PHDR_SIZE)
+        .and_then(|len| len.checked_add(start))
+        .filter(|&end| end <= image.len())
+        .ok_or_else(|| invalid("program headers out of bounds"))?;
+
+    let mut phdrs = Vec::with_capacity(ehdr.e_phnum as usize);
+    for off in (start..end).step_by(PHDR_SIZE) {
+        let ph = Phdr {
+            p_type: read_u32(image, off),
+            p_flags: read_u32(image, off + 4),
+            p_offset: read_u64(image, off + 8),
+            p_vaddr: read_u64(image, off + 16),
+            p_filesz: read_u64(image, off + 32),
+            p_memsz: read_u64(image, off + 40),
+        };
+        if ph
+            .p_offset
+            .checked_add(ph.p_filesz)
+            .is_none_or(|x| x > image.len() as u64)
+        {
+            return Err(invalid("segment out of bounds"));
+        }
+        if ph.p_type == PT_LOAD && ph.p_filesz > ph.p_memsz {
+            return Err(invalid("segment file size exceeds memory size"));
+        }
+        if ph.p_vaddr.checked_add(ph.p_memsz).is_none() {
+            return Err(invalid("segment wraps around the address space"));
+        }
+        phdrs.push(ph);
+    }
+    Ok(phdrs)
+}
+
+fn prot_of(p_flags: u32) -> Prot {
+    Prot {
+        read: p_flags & PF_R != 0,
+        write: p_flags & PF_W != 0,
+        exec: p_flags & PF_X != 0,
+    }
+}
+
+fn align_down(x: u64, page: u64) -> u64 {
+    x & !(page - 1)
+}
+
+fn align_up(x: u64, page: u64) -> Option<u64> {
+    x.checked_add(page - 1).map(|x| align_down(x, page))
+}
+
+// Maps the PT_LOAD segments. ET_EXEC images go at their link addresses,
+// ET_DYN ones are shifted so the lowest segment starts at `base`.
+fn map_segments(
+    mmu: &GuestMmu,
+    image: &[u8],
+    ehdr: &Ehdr,
+    phdrs: &[Phdr],
+    base: u64,
+) -> io::Result<LoadedElf> {
+    let page = mmu.page_size() as u64;
+    let loads: Vec<&Phdr> = phdrs.iter().filter(|ph| ph.p_type == PT_LOAD).collect();
+    let first = match loads.first() {
+        Some(ph) => ph,
+        None => return Err(invalid("no loadable segments")),
+    };
+    if loads.windows(2).any(|w| w[0].p_vaddr > w[1].p_vaddr) {
+        return Err(invalid("loadable segments are not sorted"));
+    }
+
+    let (link_base, load_base) = if ehdr.e_type == ET_DYN {
+        (align_down(first.p_vaddr, page), base)
+    } else {
+        (0, 0)
+    };
+    let load_bias = load_base.wrapping_sub(link_base);
+
+    // Adjacent segments may share a page, so only the part that is not yet
+    // mapped is mapped for each. Everything is writable until the contents
+    // are in place; fresh anonymous memory takes care of zeroing the BSS.
+    let mut mapped_end = 0;
+    let mut brk = 0;
+    for ph in loads.iter() {
+        // the bias may push a segment past the end of the address space
+        let (vaddr, end) = (ph.p_vaddr - link_base)
+            .checked_add(load_base)
+            .and_then(|vaddr| Some((vaddr, align_up(vaddr.checked_add(ph.p_memsz)?, page)?)))
+            .ok_or_else(|| invalid("segment wraps around the address space"))?;
+        let start = align_down(vaddr, page).max(mapped_end);
+        if start < end {
+            mmu.mmap_fixed(start.into(), (end - start) as usize)?;
+            mapped_end = end;
+        }
+        brk = brk.max(end);
+
+        if ph.p_filesz > 0 {
+            let src = &image[ph.p_offset as usize..(ph.p_offset + ph.p_filesz) as usize];
+            mmu.write_bytes(vaddr.into(), src)?;
+        }
+    }
+
+    for ph in loads.iter() {
+        let vaddr = ph.p_vaddr.wrapping_add(load_bias);
+        if ph.p_memsz > 0 {
+            mmu.mprotect(vaddr.into(), ph.p_memsz as usize, prot_of(ph.p_flags))?;
+        }
+    }
+
+    let interp = match phdrs.iter().find(|ph| ph.p_type == PT_INTERP) {
+        Some(ph) => Some(interp_path(image, ph)?),
+        None => None,
+    };
+
+    // the program headers are found through the first segment, as Linux does
+    let phdr = first
+        .p_vaddr
+        .wrapping_sub(first.p_offset)
+        .wrapping_add(ehdr.e_phoff)
+        .wrapping_add(load_bias);
+
+    Ok(LoadedElf {
+        entry: ehdr.e_entry.wrapping_add(load_bias),
+        phdr,
+        phnum: ehdr.e_phnum as usize,
+        load_bias,
+        brk,
+        interp,
+    })
+}
+
+fn interp_path(image: &[u8], ph: &Phdr) -> io::Result<PathBuf> {
+    let raw = &image[ph.p_offset as usize..(ph.p_offset + ph.p_filesz) as usize];
+    let path = match raw.split_last() {
+        Some((0, path)) if !path.is_empty() && !path.contains(&0) => path,
+        _ => return Err(invalid("malformed PT_INTERP")),
+    };
+    let path = std::str::from_utf8(path).map_err(|_| invalid("malformed PT_INTERP"))?;
+    Ok(PathBuf::from(path))
+}
+
+// Interpreter paths are looked up under the sysroot first, if one is
+// given, and then on the host, like qemu's -L.
+fn interp_candidates(path: &Path, sysroot: Option<&Path>) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(root) = sysroot {
+        paths.push(root.join(path.strip_prefix("/").unwrap_or(path)));
+    }
+    paths.push(path.to_path_buf());
+    paths
+}
+
+// Reads the first candidate that exists. Errors name the paths tried.
+fn read_interp(path: &Path, sysroot: Option<&Path>) -> io::Result<Vec<u8>> {
+    let candidates = interp_candidates(path, sysroot);
+    for p in candidates.iter() {
+        match std::fs::read(p) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                let msg = format!("interpreter {}: {}", p.display(), e);
+                return Err(io::Error::new(e.kind(), msg));
+            }
+            image => return image,
+        }
+    }
+    let tried: Vec<String> = candidates.iter().map(|p| p.display().to_string()).collect();
+    let msg = format!("interpreter not found, tried {}", tried.join(", "));
+    Err(io::Error::new(io::ErrorKind::NotFound, msg))
+}
+
+/// Loads an ELF image into guest memory. Position-independent images are
+/// placed at `base`, others at their link addresses.
+pub fn load_elf_at(mmu: &GuestMmu, image: &[u8], base: u64) -> io::Result<LoadedElf> {
+    let ehdr = parse_ehdr(image)?;
+    let phdrs = parse_phdrs(image, &ehdr)?;
+    map_segments(mmu, image, &ehdr, &phdrs, base)
+}
+
+/// Loads an executable image into guest memory.
+pub fn load_elf(mmu: &GuestMmu, image: &[u8]) -> io::Result<LoadedElf> {
+    load_elf_at(mmu, image, ET_DYN_BASE)
+}
+
+/// Reads and loads an executable file into guest memory.
+pub fn load_elf_file(mmu: &GuestMmu, path: &Path) -> io::Result<LoadedElf> {
+    let image = std::fs::read(path)?;
+    load_elf(mmu, &image)
+}
+
+/// Loads an executable file and, if it is dynamically linked, its program
+/// interpreter, looked up under `sysroot` and then on the host.
+pub fn load_program(
+    mmu: &GuestMmu,
+    path: &Path,
+    sysroot: Option<&Path>,
+) -> io::Result<LoadedProgram> {
+    let exe = load_elf_file(mmu, path)?;
+    let interp = match &exe.interp {
+        Some(p) => {
+            let image = read_interp(p, sysroot)?;
+            Some(load_elf_at(mmu, &image, INTERP_BASE)?)
+        }
+        None => None,
+    };
+    Ok(LoadedProgram { exe, interp })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a two-segment executable: text, then data followed by BSS
+    fn build_image(machine: u16, flags: u32, base: u64) -> Vec<u8> {
+        let mut b = vec![0u8; 0x2000];
+        b[0..4].copy_from_slice(ELFMAG);
+        b[4] = ELFCLASS64;
+        b[5] = ELFDATA2LSB;
+        b[6] = EV_CURRENT;
+        b[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+        b[18..20].copy_from_slice(&machine.to_le_bytes());
+        b[24..32].copy_from_slice(&(base + 0x100).to_le_bytes());
+        b[32..40].copy_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+        b[48..52].copy_from_slice(&flags.to_le_bytes());
+        b[54..56].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        b[56..58].copy_from_slice(&2u16.to_le_bytes());
+
+        let segs = [
+            (PF_R | PF_X, 0u64, base, 0x200u64, 0x200u64),
+            (PF_R | PF_W, 0x1000, base + 0x1000, 0x10, 0x3000),
+        ];
+        for (i, (fl, off, va, filesz, memsz)) in segs.iter().enumerate() {
+            let p = EHDR_SIZE + i * PHDR_SIZE;
+            b[p..p + 4].copy_from_slice(&PT_LOAD.to_le_bytes());
+            b[p + 4..p + 8].copy_from_slice(&fl.to_le_bytes());
+            b[p + 8..p + 16].copy_from_slice(&off.to_le_bytes());
+            b[p + 16..p + 24].copy_from_slice(&va.to_le_bytes());
+            b[p + 32..p + 40].copy_from_slice(&filesz.to_le_bytes());
+            b[p + 40..p + 48].copy_from_slice(&memsz.to_le_bytes());
+        }
+        b[0x100..0x104].copy_from_slice(&[0x13, 0x05, 0x00, 0x00]);
+        b[0x1000..0x1010].fill(0xaa);
+        b
+    }
+
+    #[test]
+    fn test_load_exec() {
+        let base = 0x10_0000_0000;
+        let image = build_image(EM_RISCV, EF_RISCV_RVC | 0x4, base);
+        let mmu = GuestMmu::new(4096);
+        let elf = load_elf(&mmu, &image).unwrap();
+
+        assert_eq!(elf.entry, base + 0x100);
+        assert_eq!(elf.phdr, base + EHDR_SIZE as u64);
+        assert_eq!(elf.phnum, 2);
+        assert_eq!(elf.brk, base + 0x4000);
+
+        let h = |g: u64| mmu.g2h(g.into()).unwrap().as_u64() as *const u8;
+        unsafe {
+            assert_eq!(*h(base + 0x100), 0x13);
+            assert_eq!(*h(base + 0x100f), 0xaa);
+            assert_eq!(*h(base + 0x1010), 0);
+            assert_eq!(*h(base + 0x3fff), 0);
+        }
+        assert!(mmu.g2h((base + 0x4000).into()).is_none());
+    }
+
+    #[test]
+    fn test_load_pie_with_interp() {
+        let mut image = build_image(EM_RISCV, 0x5, 0);
+        let interp = b"/lib/ld-linux-riscv64-lp64d.so.1\0";
+        image[16..18].copy_from_slice(&ET_DYN.to_le_bytes());
+        image[56..58].copy_from_slice(&3u16.to_le_bytes());
+        let p = EHDR_SIZE + 2 * PHDR_SIZE;
+        image[p..p + 4].copy_from_slice(&PT_INTERP.to_le_bytes());
+        image[p + 8..p + 16].copy_from_slice(&0x180u64.to_le_bytes());
+        image[p + 32..p + 40].copy_from_slice(&(interp.len() as u64).to_le_bytes());
+        image[0x180..0x180 + interp.len()].copy_from_slice(interp);
+
+        let base = 0x30_0000_0000;
+        let mmu = GuestMmu::new(4096);
+        let exe = load_elf_at(&mmu, &image, base).unwrap();
+        assert_eq!(exe.load_bias, base);
+        assert_eq!(exe.entry, base + 0x100);
+        assert_eq!(exe.brk, base + 0x4000);
+        let path = exe.interp.clone().unwrap();
+        assert_eq!(path, Path::new("/lib/ld-linux-riscv64-lp64d.so.1"));
+        assert_eq!(
+            interp_candidates(&path, Some(Path::new("/opt/sysroot"))),
+            [
+                Path::new("/opt/sysroot/lib/ld-linux-riscv64-lp64d.so.1"),
+                &path
+            ]
+        );
+
+        let prog = LoadedProgram { exe, interp: None };
+        assert_eq!(prog.entry(), base + 0x100);
+        assert!(prog.auxv().contains(&(AT_BASE, 0)));
+        assert!(prog.auxv().contains(&(AT_ENTRY, base + 0x100)));
+    }
+
+    #[test]
+    fn test_read_interp() {
+        // falls back to the host when the sysroot does not have it
+        let exe = std::env::current_exe().unwrap();
+        let image = read_interp(&exe, Some(Path::new("/nonexistent"))).unwrap();
+        assert_eq!(&image[..4], ELFMAG);
+
+        let err =
+            read_interp(Path::new("/nonexistent/ld.so"), Some(Path::new("/opt"))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(
+            err.to_string(),
+            "interpreter not found, tried /opt/nonexistent/ld.so, /nonexistent/ld.so"
+        );
+    }
+
+    #[test]
+    fn test_reject() {
+        let mmu = GuestMmu::new(4096);
+        let err = |img: &[u8], mmu: &GuestMmu| load_elf(mmu, img).unwrap_err().kind();
+
+        assert_eq!(err(b"#!/bin/sh\n", &mmu), io::ErrorKind::InvalidData);
+        let x86 = build_image(62, 0, 0x20_0000_0000);
+        assert_eq!(err(&x86, &mmu), io::ErrorKind::InvalidData);
+        let quad = build_image(EM_RISCV, EF_RISCV_FLOAT_ABI_QUAD, 0x20_0000_0000);
+        assert_eq!(err(&quad, &mmu), io::ErrorKind::InvalidData);
+        let rve = build_image(EM_RISCV, EF_RISCV_RVE, 0x20_0000_0000);
+        assert_eq!(err(&rve, &mmu), io::ErrorKind::InvalidData);
+
+        let mut phoff = build_image(EM_RISCV, 0, 0x20_0000_0000);
+        phoff[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(err(&phoff, &mmu), io::ErrorKind::InvalidData);
+
+        // the data segment ends at the very top, so its last page does not
+        let p = EHDR_SIZE + PHDR_SIZE;
+        let mut top = build_image(EM_RISCV, 0, 0x20_0000_0000);
+        top[p + 16..p + 24].copy_from_slice(&(u64::MAX - 0x3000).to_le_bytes());
+        assert_eq!(err(&top, &mmu), io::ErrorKind::InvalidData);
+        // and the load bias moves it past the top
+        let mut biased = build_image(EM_RISCV, 0, 0);
+        biased[16..18].copy_from_slice(&ET_DYN.to_le_bytes());
+        biased[p + 16..p + 24].copy_from_slice(&(u64::MAX - 0x4000).to_le_bytes());
+        assert_eq!(err(&biased, &mmu), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,10 +1,15 @@
 use std::{
-    collections::HashMap,
+    cell::Cell,
+    collections::BTreeMap,
+    io,
+    mem::ManuallyDrop,
     ops::{Add, Sub},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock, RwLockWriteGuard,
+    },
 };
 
-use memmap;
-
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct HostAddr(u64);
 
@@ -74,121 +79,1085 @@ fn get_page_shift(page_size: usize) -> usize {
 }
 
 fn align_to_page(len: usize, page_size: usize, page_shift: usize) -> usize {
-    if len % page_size == 0 {
+    if len.is_multiple_of(page_size) {
         len
     } else {
-        (len >> page_shift + 1) << page_shift
+        ((len >> page_shift) + 1) << page_shift
+    }
+}
+
+/// Kind of a guest memory access.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    /// Instruction fetch.
+    Exec,
+}
+
+/// Guest page protection.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Prot {
+    pub read: bool,
+    pub write: bool,
+    pub exec: bool,
+}
+
+impl Prot {
+    pub const RW: Prot = Prot {
+        read: true,
+        write: true,
+        exec: false,
+    };
+    pub const RX: Prot = Prot {
+        read: true,
+        write: false,
+        exec: true,
+    };
+
+    pub fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Exec => self.exec,
+        }
+    }
+
+    // the interpreter fetches instructions with ordinary loads, so executable
+    // guest pages must be readable on the host
+    fn host_prot(&self) -> libc::c_int {
+        let mut p = libc::PROT_NONE;
+        if self.read || self.exec {
+            p |= libc::PROT_READ;
+        }
+        if self.write {
+            p |= libc::PROT_WRITE;
+        }
+        p
+    }
+}
+
+/// How a new mapping is placed.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Placement {
+    /// Anywhere, the address is only a hint.
+    Hint,
+    /// Exactly at the address, replacing whatever the guest had there.
+    Fixed,
+    /// Exactly at the address, failing with EEXIST if anything is there.
+    FixedNoReplace,
+}
+
+/// Host mapping owned by the MMU. On drop, it is unmapped, or turned back
+/// into reserved address space if it lies in the guest_base region.
+struct RawMap {
+    p: *mut u8,
+    len: usize,
+    reserved: bool,
+}
+
+impl RawMap {
+    fn split_at(self, off: usize) -> (RawMap, RawMap) {
+        let me = ManuallyDrop::new(self);
+        let lo = RawMap {
+            p: me.p,
+            len: off,
+            reserved: me.reserved,
+        };
+        let hi = RawMap {
+            p: unsafe { me.p.add(off) },
+            len: me.len - off,
+            reserved: me.reserved,
+        };
+        (lo, hi)
+    }
+}
+
+// Reserves host address space without committing any memory, replacing
+// whatever is mapped there if `fixed`.
+fn reserve(p: u64, len: usize, fixed: bool) -> *mut libc::c_void {
+    let mut flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE;
+    if fixed {
+        flags |= libc::MAP_FIXED;
+    }
+    unsafe { libc::mmap(p as *mut libc::c_void, len, libc::PROT_NONE, flags, -1, 0) }
+}
+
+impl Drop for RawMap {
+    fn drop(&mut self) {
+        if self.reserved {
+            reserve(self.p as u64, self.len, true);
+        } else {
+            unsafe {
+                libc::munmap(self.p as *mut libc::c_void, self.len);
+            }
+        }
     }
 }
 
 enum MemBlock {
-    Map(memmap::MmapMut),
-    Injected { _p: *const u8, len: usize },
-    InjectedMut { _p: *mut u8, len: usize },
+    Owned(RawMap),
+    Injected { p: *const u8, len: usize },
+    InjectedMut { p: *mut u8, len: usize },
 }
 
 impl MemBlock {
     fn len(&self) -> usize {
         match self {
-            MemBlock::Map(x) => x.len(),
-            MemBlock::Injected { _p: _, len } => *len,
-            MemBlock::InjectedMut { _p: _, len } => *len,
+            MemBlock::Owned(x) => x.len,
+            MemBlock::Injected { p: _, len } => *len,
+            MemBlock::InjectedMut { p: _, len } => *len,
+        }
+    }
+
+    fn host(&self) -> u64 {
+        match self {
+            MemBlock::Owned(x) => x.p as u64,
+            MemBlock::Injected { p, len: _ } => *p as u64,
+            MemBlock::InjectedMut { p, len: _ } => *p as u64,
+        }
+    }
+
+    fn split_at(self, off: usize) -> (MemBlock, MemBlock) {
+        match self {
+            MemBlock::Owned(x) => {
+                let (lo, hi) = x.split_at(off);
+                (MemBlock::Owned(lo), MemBlock::Owned(hi))
+            }
+            MemBlock::Injected { p, len } => (
+                MemBlock::Injected { p, len: off },
+                MemBlock::Injected {
+                    p: p.wrapping_add(off),
+                    len: len - off,
+                },
+            ),
+            MemBlock::InjectedMut { p, len } => (
+                MemBlock::InjectedMut { p, len: off },
+                MemBlock::InjectedMut {
+                    p: p.wrapping_add(off),
+                    len: len - off,
+                },
+            ),
         }
     }
 }
 
+// The blocks are plain memory shared by all guest threads, who synchronize
+// among themselves like on real hardware.
+unsafe impl Send for MemBlock {}
+unsafe impl Sync for MemBlock {}
+
+struct Mapping {
+    block: MemBlock,
+    prot: Prot,
+}
+
+// keyed by start address, mappings never overlap
+type Maps = BTreeMap<GuestAddr, Mapping>;
+
+// the mapping containing `g`
+fn find(maps: &Maps, g: u64) -> Option<(GuestAddr, &Mapping)> {
+    maps.range(..=GuestAddr(g))
+        .next_back()
+        .filter(|(k, m)| g - k.0 < m.block.len() as u64)
+        .map(|(k, m)| (*k, m))
+}
+
+// Splits the mappings straddling `lo` or `hi`, so that every mapping is
+// either entirely inside `[lo, hi)` or entirely outside.
+fn split_range(maps: &mut Maps, lo: u64, hi: u64) {
+    for at in [lo, hi] {
+        let key = find(maps, at).map(|(g, _)| g).filter(|g| g.0 < at);
+        if let Some(g) = key {
+            let m = maps.remove(&g).unwrap();
+            let (a, b) = m.block.split_at((at - g.0) as usize);
+            maps.insert(
+                g,
+                Mapping {
+                    block: a,
+                    prot: m.prot,
+                },
+            );
+            maps.insert(
+                at.into(),
+                Mapping {
+                    block: b,
+                    prot: m.prot,
+                },
+            );
+        }
+    }
+}
+
+// start addresses of the mappings inside `[lo, hi)`, in ascending order
+fn mappings_in(maps: &Maps, lo: u64, hi: u64) -> Vec<GuestAddr> {
+    let mut v: Vec<GuestAddr> = find(maps, lo)
+        .map(|(g, _)| g)
+        .filter(|g| g.0 < lo)
+        .into_iter()
+        .collect();
+    if lo < hi {
+        v.extend(maps.range(GuestAddr(lo)..GuestAddr(hi)).map(|(g, _)| *g));
+    }
+    v
+}
+
+fn is_covered(maps: &Maps, lo: u64, hi: u64) -> bool {
+    let mut next = lo;
+    for g in mappings_in(maps, lo, hi) {
+        if g.0 > next {
+            return false;
+        }
+        next = next.max(g.0 + maps[&g].block.len() as u64);
+    }
+    next >= hi
+}
+
+fn enomem() -> io::Error {
+    io::Error::from_raw_os_error(libc::ENOMEM)
+}
+
+fn einval() -> io::Error {
+    io::Error::from_raw_os_error(libc::EINVAL)
+}
+
+// program break, `start` is where the executable's BSS ends
+struct Brk {
+    start: u64,
+    cur: u64,
+}
+
+/// How guest addresses relate to host ones.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AddressSpace {
+    /// The guest address space is a reserved host region of `size` bytes,
+    /// and guest address `g` is at host address `guest_base + g`, so that
+    /// contiguous guest memory is contiguous on the host too.
+    GuestBase { size: u64 },
+    /// Every mapping lives wherever the host puts it, with its own offset
+    /// from the guest address. This does not reserve anything up front and
+    /// allows injecting host memory, but guest memory spanning mappings
+    /// may be scattered on the host.
+    Offsets,
+}
+
+// lowest address handed out for mappings without a fixed address, like
+// Linux's default vm.mmap_min_addr
+const MMAP_MIN_ADDR: u64 = 0x1_0000;
+// upper bound of guest addresses with `AddressSpace::Offsets`, that of
+// Sv48
+const OFFSETS_TOP: u64 = 1 << 47;
+
 /// Naïve implementation of an MMU.
 pub struct GuestMmu {
     guest_page_size: usize,
     guest_page_shift: usize,
     host_page_size: usize,
     host_page_shift: usize,
-    maps: std::sync::RwLock<HashMap<GuestAddr, MemBlock>>,
+    // start of the reserved region for `AddressSpace::GuestBase`
+    guest_base: Option<u64>,
+    // guest addresses are below this
+    top: u64,
+    maps: RwLock<Maps>,
+    // bumped on every change to `maps`, so that TLBs know to flush
+    generation: AtomicU64,
+    // bumped when executable mappings go away or change protection, so
+    // that decoded code is dropped
+    code_generation: AtomicU64,
+    brk: Mutex<Brk>,
 }
+
 impl GuestMmu {
+    /// Creates an MMU where every mapping has its own guest-to-host offset.
     pub fn new(guest_page_size: usize) -> Self {
+        Self::with_address_space(guest_page_size, AddressSpace::Offsets).unwrap()
+    }
+
+    pub fn with_address_space(
+        guest_page_size: usize,
+        space: AddressSpace,
+    ) -> ::std::io::Result<Self> {
         let host_page_size = host_page_size();
-        Self {
+        let (guest_base, top) = match space {
+            AddressSpace::GuestBase { size } => {
+                let p = reserve(0, size as usize, false);
+                if p == libc::MAP_FAILED {
+                    return Err(io::Error::last_os_error());
+                }
+                (Some(p as u64), size)
+            }
+            AddressSpace::Offsets => (None, OFFSETS_TOP),
+        };
+
+        Ok(Self {
             guest_page_size,
             guest_page_shift: get_page_shift(guest_page_size),
             host_page_size,
             host_page_shift: get_page_shift(host_page_size),
-            maps: std::sync::RwLock::new(HashMap::new()),
+            guest_base,
+            top,
+            maps: RwLock::new(BTreeMap::new()),
+            generation: AtomicU64::new(0),
+            code_generation: AtomicU64::new(0),
+            brk: Mutex::new(Brk { start: 0, cur: 0 }),
+        })
+    }
+
+    /// Host address of guest address 0 with `AddressSpace::GuestBase`.
+    pub fn guest_base(&self) -> Option<u64> {
+        self.guest_base
+    }
+
+    /// Granularity of mappings, the larger of the guest and host page sizes.
+    pub fn page_size(&self) -> usize {
+        self.host_page_size.max(self.guest_page_size)
+    }
+
+    fn align(&self, len: usize) -> usize {
+        // align to host page only if host page size is bigger than guest's,
+        // else align to guest page
+        if self.host_page_size > self.guest_page_size {
+            align_to_page(len, self.host_page_size, self.host_page_shift)
+        } else {
+            align_to_page(len, self.guest_page_size, self.guest_page_shift)
+        }
+    }
+
+    fn is_aligned(&self, g: u64) -> bool {
+        g.is_multiple_of(self.page_size() as u64)
+    }
+
+    // whether `[g, g + len)` can be mapped at all
+    fn in_range(&self, g: u64, len: u64) -> bool {
+        g >= MMAP_MIN_ADDR && g.checked_add(len).is_some_and(|end| end <= self.top)
+    }
+
+    // whether `[g, g + len)` can be mapped and is unused
+    fn is_free(&self, maps: &Maps, g: u64, len: u64) -> bool {
+        self.in_range(g, len) && mappings_in(maps, g, g + len).is_empty()
+    }
+
+    // the highest unused guest range of `len` bytes
+    fn find_free(&self, maps: &Maps, len: u64) -> Option<u64> {
+        let page = self.page_size() as u64;
+        let mut top = self.top;
+        for (g, m) in maps.range(..GuestAddr(self.top)).rev() {
+            let end = (g.0 + m.block.len() as u64).next_multiple_of(page);
+            if end < top && top - end >= len {
+                return Some((top - len) & !(page - 1)).filter(|x| *x >= end.max(MMAP_MIN_ADDR));
+            }
+            top = top.min(g.0 & !(page - 1));
         }
+        top.checked_sub(len)
+            .map(|x| x & !(page - 1))
+            .filter(|x| *x >= MMAP_MIN_ADDR)
     }
 
-    pub fn consume_host(&mut self, mem: *const u8, len: usize) -> ::std::io::Result<GuestAddr> {
-        let m = MemBlock::Injected { _p: mem, len: len };
-        let addr = mem as u64;
+    fn inject(&self, block: MemBlock, prot: Prot) -> ::std::io::Result<GuestAddr> {
+        // the region of guest_base cannot take foreign host memory
+        if self.guest_base.is_some() {
+            return Err(einval());
+        }
+
+        // the guest sees the memory at its host address where possible
+        let mut maps = self.maps_mut();
+        let len = block.len() as u64;
+        let g = if self.is_free(&maps, block.host(), len) {
+            block.host()
+        } else {
+            self.find_free(&maps, self.align(len as usize) as u64)
+                .ok_or_else(enomem)?
+        };
+        maps.insert(g.into(), Mapping { block, prot });
+        Ok(g.into())
+    }
 
-        let mut maps = self.maps.write().unwrap();
-        maps.insert(addr.into(), m);
+    /// Maps host memory into the guest as read-only code or data.
+    pub fn consume_host(&self, mem: *const u8, len: usize) -> ::std::io::Result<GuestAddr> {
+        self.inject(MemBlock::Injected { p: mem, len }, Prot::RX)
+    }
 
-        Ok(addr.into())
+    /// Maps host memory into the guest as read-write data.
+    pub fn consume_host_mut(&self, mem: *mut u8, len: usize) -> ::std::io::Result<GuestAddr> {
+        self.inject(MemBlock::InjectedMut { p: mem, len }, Prot::RW)
     }
 
-    pub fn consume_host_mut(&mut self, mem: *mut u8, len: usize) -> ::std::io::Result<GuestAddr> {
-        let m = MemBlock::InjectedMut { _p: mem, len: len };
-        let addr = mem as u64;
+    pub fn mmap(&self, len: usize, stack: bool) -> ::std::io::Result<GuestAddr> {
+        let flags = if stack { libc::MAP_STACK } else { 0 };
+        self.mmap_at(0.into(), len, Prot::RW, Placement::Hint, flags, None)
+    }
 
-        let mut maps = self.maps.write().unwrap();
-        maps.insert(addr.into(), m);
+    /// Maps zero-filled memory at exactly `g`, failing if anything is
+    /// already mapped there. `g` must be page-aligned.
+    pub fn mmap_fixed(&self, g: GuestAddr, len: usize) -> ::std::io::Result<GuestAddr> {
+        self.mmap_at(g, len, Prot::RW, Placement::FixedNoReplace, 0, None)
+    }
 
-        Ok(addr.into())
+    // Host address for a new mapping at guest `g`. With offsets, it follows
+    // the host memory of the mapping just below when possible, to keep
+    // growing regions like the heap contiguous.
+    fn host_hint(&self, maps: &Maps, g: u64) -> u64 {
+        match self.guest_base {
+            Some(base) => base + g,
+            None => g
+                .checked_sub(1)
+                .and_then(|x| find(maps, x))
+                .map(|(start, m)| m.block.host() + (g - start.0))
+                .unwrap_or(g),
+        }
     }
 
-    // Naïve implementation; doesn't support fixed maps nor file-backed maps.
-    pub fn mmap(&mut self, len: usize, stack: bool) -> ::std::io::Result<GuestAddr> {
+    /// General mmap(2). `flags` are extra host `MAP_*` flags such as
+    /// `MAP_SHARED`; mappings are private otherwise. `file` is a host file
+    /// descriptor and offset for file-backed mappings.
+    pub fn mmap_at(
+        &self,
+        g: GuestAddr,
+        len: usize,
+        prot: Prot,
+        placement: Placement,
+        flags: libc::c_int,
+        file: Option<(libc::c_int, u64)>,
+    ) -> ::std::io::Result<GuestAddr> {
         if len == 0 {
-            return Err(std::io::ErrorKind::InvalidInput.into());
+            return Err(einval());
+        }
+        let len = self.align(len);
+        if placement != Placement::Hint && !self.is_aligned(g.0) {
+            return Err(einval());
         }
 
-        // align to host page only if host page size is bigger than guest's,
-        // else align to guest page
-        let len = if self.host_page_size > self.guest_page_size {
-            align_to_page(len, self.host_page_size, self.host_page_shift)
-        } else {
-            align_to_page(len, self.guest_page_size, self.guest_page_shift)
+        let mut maps = self.maps_mut();
+        let g = match placement {
+            Placement::Hint => {
+                if self.is_aligned(g.0) && self.is_free(&maps, g.0, len as u64) {
+                    g.0
+                } else {
+                    self.find_free(&maps, len as u64).ok_or_else(enomem)?
+                }
+            }
+            Placement::Fixed => {
+                if !self.in_range(g.0, len as u64) {
+                    return Err(enomem());
+                }
+                let end = g.0 + len as u64;
+                split_range(&mut maps, g.0, end);
+                self.changing_code(&maps, g.0, end);
+                for k in mappings_in(&maps, g.0, end) {
+                    maps.remove(&k);
+                }
+                g.0
+            }
+            Placement::FixedNoReplace => {
+                if !self.is_free(&maps, g.0, len as u64) {
+                    return Err(io::Error::from_raw_os_error(libc::EEXIST));
+                }
+                g.0
+            }
         };
 
-        let mut maps = self.maps.write().unwrap();
+        // In the guest_base region, the host address is fixed and only ever
+        // replaces the reservation. Otherwise it is a mere hint.
+        let mut host_flags = flags | libc::MAP_PRIVATE;
+        if flags & libc::MAP_SHARED != 0 {
+            host_flags &= !libc::MAP_PRIVATE;
+        }
+        if self.guest_base.is_some() {
+            host_flags |= libc::MAP_FIXED;
+        }
+        let (fd, off) = match file {
+            Some((fd, off)) => (fd, off as libc::off_t),
+            None => {
+                host_flags |= libc::MAP_ANONYMOUS;
+                (-1, 0)
+            }
+        };
+        let p = unsafe {
+            libc::mmap(
+                self.host_hint(&maps, g) as *mut libc::c_void,
+                len,
+                prot.host_prot(),
+                host_flags,
+                fd,
+                off,
+            )
+        };
+        if p == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        let block = RawMap {
+            p: p as *mut u8,
+            len,
+            reserved: self.guest_base.is_some(),
+        };
+        maps.insert(
+            g.into(),
+            Mapping {
+                block: MemBlock::Owned(block),
+                prot,
+            },
+        );
+
+        Ok(g.into())
+    }
+
+    /// Changes the protection of `[g, g + len)`, widened to whole pages. All
+    /// of it must be mapped.
+    pub fn mprotect(&self, g: GuestAddr, len: usize, prot: Prot) -> ::std::io::Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let page = self.page_size() as u64;
+        let lo = g.0 & !(page - 1);
+        // like Linux, a range that wraps around is not mapped
+        let end = g.0.checked_add(len as u64).ok_or_else(enomem)?;
+        let hi = end.checked_add(page - 1).ok_or_else(enomem)? & !(page - 1);
 
-        let mut m = memmap::MmapOptions::new();
-        m.len(len);
-        if stack {
-            m.stack();
+        let mut maps = self.maps_mut();
+        if !is_covered(&maps, lo, hi) {
+            return Err(enomem());
+        }
+        split_range(&mut maps, lo, hi);
+        if mappings_in(&maps, lo, hi)
+            .iter()
+            .any(|k| maps[k].prot.exec && maps[k].prot != prot)
+        {
+            self.code_generation.fetch_add(1, Ordering::SeqCst);
+        }
+        for k in mappings_in(&maps, lo, hi) {
+            let m = maps.get_mut(&k).unwrap();
+            if let MemBlock::Owned(x) = &m.block {
+                let ret =
+                    unsafe { libc::mprotect(x.p as *mut libc::c_void, x.len, prot.host_prot()) };
+                if ret != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            m.prot = prot;
         }
+        Ok(())
+    }
 
-        let m = m.map_anon()?;
-        let addr = m.as_ptr() as u64;
-        maps.insert(addr.into(), MemBlock::Map(m));
+    /// Unmaps `[g, g + len)`, splitting the mappings that are only partially
+    /// covered. Holes in the range are fine.
+    pub fn munmap(&self, g: GuestAddr, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let hi = g.0.saturating_add(self.align(len) as u64);
 
-        Ok(addr.into())
+        let mut maps = self.maps_mut();
+        split_range(&mut maps, g.0, hi);
+        self.changing_code(&maps, g.0, hi);
+        for k in mappings_in(&maps, g.0, hi) {
+            maps.remove(&k);
+        }
     }
 
-    pub fn munmap(&mut self, g: GuestAddr, len: usize) {
-        let mut maps = self.maps.write().unwrap();
-        maps.retain(|g_start, m| {
-            // check for intersection
-            // [g, g + len) vs [g_start, g_start + m.len())
-            // only keep those ranges NOT overlapping the requested range
-            (g + len) <= *g_start || (*g_start + m.len()) <= g
-        });
+    /// mremap(2) on a range that must lie within a single mapping made by
+    /// this MMU. `new_addr` asks for MREMAP_FIXED.
+    pub fn mremap(
+        &self,
+        old: GuestAddr,
+        old_len: usize,
+        new_len: usize,
+        may_move: bool,
+        new_addr: Option<GuestAddr>,
+    ) -> ::std::io::Result<GuestAddr> {
+        if !self.is_aligned(old.0) || new_len == 0 {
+            return Err(einval());
+        }
+        let old_len = self.align(old_len);
+        let new_len = self.align(new_len);
+        let old_end = old.0.checked_add(old_len as u64).ok_or_else(einval)?;
+
+        if new_addr.is_none() && new_len <= old_len {
+            self.munmap(old + new_len, old_len - new_len);
+            return Ok(old);
+        }
+
+        let mut maps = self.maps_mut();
+        split_range(&mut maps, old.0, old_end);
+        self.changing_code(&maps, old.0, old_end);
+        let (host, prot) = match mappings_in(&maps, old.0, old_end).as_slice() {
+            [k] if *k == old && maps[k].block.len() == old_len => match &maps[k].block {
+                MemBlock::Owned(x) => (x.p as u64, maps[k].prot),
+                _ => return Err(einval()),
+            },
+            _ => return Err(io::Error::from_raw_os_error(libc::EFAULT)),
+        };
+
+        let target = match new_addr {
+            Some(t) => {
+                if !self.is_aligned(t.0)
+                    || !self.in_range(t.0, new_len as u64)
+                    || t.0 < old_end && old.0 < t.0 + new_len as u64
+                {
+                    return Err(einval());
+                }
+                // like MAP_FIXED, whatever the guest had there goes away
+                let end = t.0 + new_len as u64;
+                split_range(&mut maps, t.0, end);
+                self.changing_code(&maps, t.0, end);
+                for k in mappings_in(&maps, t.0, end) {
+                    maps.remove(&k);
+                }
+                t.0
+            }
+            None if self.is_free(&maps, old_end, (new_len - old_len) as u64) => old.0,
+            None if may_move => self.find_free(&maps, new_len as u64).ok_or_else(enomem)?,
+            None => return Err(enomem()),
+        };
+
+        let remap = |flags: libc::c_int, dst: u64| unsafe {
+            libc::mremap(
+                host as *mut libc::c_void,
+                old_len,
+                new_len,
+                flags,
+                dst as *mut libc::c_void,
+            )
+        };
+        let p = match self.guest_base {
+            // growing in place, over what is reserved past the end
+            Some(_) if target == old.0 => {
+                let tail = host + old_len as u64;
+                unsafe { libc::munmap(tail as *mut libc::c_void, new_len - old_len) };
+                let p = remap(0, 0);
+                if p == libc::MAP_FAILED {
+                    reserve(tail, new_len - old_len, true);
+                }
+                p
+            }
+            // moving within the region, the old range is reserved again
+            Some(base) => {
+                let p = remap(libc::MREMAP_MAYMOVE | libc::MREMAP_FIXED, base + target);
+                if p != libc::MAP_FAILED {
+                    reserve(host, old_len, true);
+                }
+                p
+            }
+            // the host address does not matter
+            None => remap(libc::MREMAP_MAYMOVE, 0),
+        };
+        if p == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        // the host moved or resized the memory, so forget about the old
+        // record without unmapping anything
+        if let Some(m) = maps.remove(&old) {
+            if let MemBlock::Owned(x) = m.block {
+                std::mem::forget(x);
+            }
+        }
+        maps.insert(
+            target.into(),
+            Mapping {
+                block: MemBlock::Owned(RawMap {
+                    p: p as *mut u8,
+                    len: new_len,
+                    reserved: self.guest_base.is_some(),
+                }),
+                prot,
+            },
+        );
+        Ok(target.into())
     }
 
-    pub fn g2h(&self, g: GuestAddr) -> Option<HostAddr> {
+    /// madvise(2), passed to the host for the memory this MMU owns.
+    pub fn madvise(&self, g: GuestAddr, len: usize, advice: libc::c_int) -> ::std::io::Result<()> {
+        if !self.is_aligned(g.0) {
+            return Err(einval());
+        }
+        if len == 0 {
+            return Ok(());
+        }
+        let hi = g.0.checked_add(self.align(len) as u64).ok_or_else(einval)?;
+
         let maps = self.maps.read().unwrap();
-        for (g_start, m) in maps.iter() {
-            if g < *g_start {
-                continue;
+        if !is_covered(&maps, g.0, hi) {
+            return Err(enomem());
+        }
+        for k in mappings_in(&maps, g.0, hi) {
+            let m = &maps[&k];
+            if let MemBlock::Owned(_) = m.block {
+                let lo = k.0.max(g.0);
+                let end = (k.0 + m.block.len() as u64).min(hi);
+                let h = m.block.host() + (lo - k.0);
+                let ret =
+                    unsafe { libc::madvise(h as *mut libc::c_void, (end - lo) as usize, advice) };
+                if ret != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits `[g, g + len)` into runs that are contiguous on the host,
+    /// regardless of protection. Returns None if anything in the range is
+    /// unmapped.
+    pub fn host_chunks(&self, g: GuestAddr, len: usize) -> Option<Vec<(HostAddr, usize)>> {
+        let end = g.0.checked_add(len as u64)?;
+        let maps = self.maps.read().unwrap();
+        let mut chunks: Vec<(HostAddr, usize)> = Vec::new();
+        let mut p = g.0;
+        while p < end {
+            let (start, m) = find(&maps, p)?;
+            let n = (start.0 + m.block.len() as u64).min(end) - p;
+            let h = m.block.host() + (p - start.0);
+            match chunks.last_mut() {
+                Some((x, l)) if x.0 + *l as u64 == h => *l += n as usize,
+                _ => chunks.push((HostAddr(h), n as usize)),
+            }
+            p += n;
+        }
+        Some(chunks)
+    }
+
+    /// Copies `data` into guest memory, regardless of protection, like the
+    /// kernel does on behalf of a process.
+    pub fn write_bytes(&self, g: GuestAddr, data: &[u8]) -> ::std::io::Result<()> {
+        let chunks = self
+            .host_chunks(g, data.len())
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EFAULT))?;
+        let mut off = 0;
+        for (h, n) in chunks {
+            unsafe {
+                std::ptr::copy_nonoverlapping(data[off..].as_ptr(), h.0 as *mut u8, n);
+            }
+            off += n;
+        }
+        Ok(())
+    }
+
+    /// Copies guest memory into `data`, regardless of protection.
+    pub fn read_bytes(&self, g: GuestAddr, data: &mut [u8]) -> ::std::io::Result<()> {
+        let chunks = self
+            .host_chunks(g, data.len())
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EFAULT))?;
+        let mut off = 0;
+        for (h, n) in chunks {
+            unsafe {
+                std::ptr::copy_nonoverlapping(h.0 as *const u8, data[off..].as_mut_ptr(), n);
             }
+            off += n;
+        }
+        Ok(())
+    }
+
+    /// Sets where the program break starts, normally right after the
+    /// executable's BSS.
+    pub fn init_brk(&self, start: u64) {
+        *self.brk.lock().unwrap() = Brk { start, cur: start };
+    }
+
+    /// brk(2): moves the program break and returns the new one, or the
+    /// current one if the request cannot be satisfied.
+    pub fn brk(&self, new: u64) -> u64 {
+        let mut brk = self.brk.lock().unwrap();
+        if new < brk.start {
+            return brk.cur;
+        }
+
+        let page = self.page_size() as u64;
+        let old_top = brk.cur.next_multiple_of(page);
+        let new_top = new.next_multiple_of(page);
+        if new_top > old_top {
+            let len = (new_top - old_top) as usize;
+            if self.mmap_fixed(old_top.into(), len).is_err() {
+                return brk.cur;
+            }
+        } else if new_top < old_top {
+            self.munmap(new_top.into(), (old_top - new_top) as usize);
+        }
+
+        brk.cur = new;
+        brk.cur
+    }
+
+    // the write lock on the mappings; the generation is bumped while it is
+    // held, so that nothing looked up before the change is tagged with the
+    // new generation
+    fn maps_mut(&self) -> RwLockWriteGuard<'_, Maps> {
+        let maps = self.maps.write().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        maps
+    }
+
+    /// Changes whenever the mappings do, so that threads can tell when what
+    /// they cached about them is stale.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Changes whenever executable mappings are unmapped, replaced or
+    /// reprotected, so that threads can tell when the code they decoded
+    /// may be stale. Other changes to the mappings leave it alone.
+    pub fn code_generation(&self) -> u64 {
+        self.code_generation.load(Ordering::SeqCst)
+    }
 
-            let offset = g - *g_start;
-            if offset < m.len() {
-                return Some(HostAddr(g.0));
+    // the mappings in `[lo, hi)` are about to go away
+    fn changing_code(&self, maps: &Maps, lo: u64, hi: u64) {
+        if mappings_in(maps, lo, hi).iter().any(|k| maps[k].prot.exec) {
+            self.code_generation.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Makes every thread drop what it cached about guest memory, including
+    /// decoded code.
+    pub fn flush_caches(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.code_generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn lookup(&self, g: GuestAddr) -> Option<TlbEntry> {
+        let maps = self.maps.read().unwrap();
+        find(&maps, g.0).map(|(start, m)| TlbEntry {
+            start: start.0,
+            end: start.0 + m.block.len() as u64,
+            host: m.block.host(),
+            prot: m.prot,
+        })
+    }
+
+    pub fn g2h(&self, g: GuestAddr) -> Option<HostAddr> {
+        self.lookup(g).map(|e| e.host_addr(g))
+    }
+
+    /// Translates an address for an access of the given kind, honoring
+    /// the mapping's protection.
+    pub fn translate(&self, g: GuestAddr, access: Access) -> Option<HostAddr> {
+        self.lookup(g)
+            .filter(|e| e.prot.allows(access))
+            .map(|e| e.host_addr(g))
+    }
+}
+
+impl Drop for GuestMmu {
+    fn drop(&mut self) {
+        self.maps.get_mut().unwrap().clear();
+        if let Some(base) = self.guest_base {
+            unsafe { libc::munmap(base as *mut libc::c_void, self.top as usize) };
+        }
+    }
+}
+
+const TLB_SIZE: usize = 256;
+
+// A cached mapping. Whole mappings are cached rather than pages, which
+// keeps mismatched guest and host page sizes, and injected blocks that do
+// not fill their pages, out of the picture.
+#[derive(Copy, Clone, Default)]
+struct TlbEntry {
+    start: u64,
+    end: u64,
+    host: u64,
+    prot: Prot,
+}
+
+impl TlbEntry {
+    fn contains(&self, g: u64) -> bool {
+        self.start <= g && g < self.end
+    }
+
+    fn host_addr(&self, g: GuestAddr) -> HostAddr {
+        HostAddr(self.host + (g.0 - self.start))
+    }
+}
+
+/// Direct-mapped cache of `GuestMmu` translations, private to one thread.
+/// It is flushed whenever the mappings change.
+pub struct Tlb {
+    generation: Cell<u64>,
+    entries: Box<[Cell<TlbEntry>]>,
+}
+
+impl Default for Tlb {
+    fn default() -> Self {
+        Self {
+            generation: Cell::new(u64::MAX),
+            entries: vec![Cell::new(TlbEntry::default()); TLB_SIZE].into_boxed_slice(),
+        }
+    }
+}
+
+impl Tlb {
+    /// Same as `GuestMmu::translate`, through the cache.
+    pub fn translate(&self, mmu: &GuestMmu, g: GuestAddr, access: Access) -> Option<HostAddr> {
+        self.translate_range(mmu, g, 1, access)
+    }
+
+    /// Translates the `len` bytes at `g` if they lie in a single mapping,
+    /// and are thus contiguous on the host too.
+    pub fn translate_range(
+        &self,
+        mmu: &GuestMmu,
+        g: GuestAddr,
+        len: usize,
+        access: Access,
+    ) -> Option<HostAddr> {
+        let generation = mmu.generation.load(Ordering::SeqCst);
+        if generation != self.generation.get() {
+            for e in self.entries.iter() {
+                e.set(TlbEntry::default());
             }
+            self.generation.set(generation);
+        }
+
+        let slot = &self.entries[(g.0 >> mmu.guest_page_shift) as usize % TLB_SIZE];
+        let mut e = slot.get();
+        if !e.contains(g.0) {
+            e = mmu.lookup(g)?;
+            slot.set(e);
+        }
+        if e.prot.allows(access) && len as u64 <= e.end - g.0 {
+            Some(e.host_addr(g))
+        } else {
+            None
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_protect() {
+        let mmu = GuestMmu::new(4096);
+        let g = mmu.mmap(8 * 4096, false).unwrap().as_u64();
+        // the upper half is left free for the break to grow into
+        mmu.munmap((g + 4 * 4096).into(), 4 * 4096);
+
+        // punch a hole in the middle, leaving two blocks around it
+        mmu.munmap((g + 4096).into(), 4096);
+        assert!(mmu.g2h(g.into()).is_some());
+        assert!(mmu.g2h((g + 4096).into()).is_none());
+        assert!(mmu.g2h((g + 2 * 4096).into()).is_some());
+
+        // protections are recorded per page
+        let ro = Prot {
+            read: true,
+            write: false,
+            exec: false,
+        };
+        mmu.mprotect((g + 3 * 4096).into(), 4096, ro).unwrap();
+        assert!(mmu
+            .translate((g + 2 * 4096).into(), Access::Write)
+            .is_some());
+        assert!(mmu
+            .translate((g + 3 * 4096).into(), Access::Write)
+            .is_none());
+        assert!(mmu.translate((g + 3 * 4096).into(), Access::Read).is_some());
+        assert!(mmu.mprotect((g + 4096).into(), 4096, ro).is_err());
+        assert!(mmu.mprotect(g.into(), usize::MAX, ro).is_err());
+
+        mmu.init_brk(g + 4 * 4096 + 16);
+        let top = mmu.brk(g + 6 * 4096);
+        assert_eq!(top, g + 6 * 4096);
+        assert!(mmu
+            .translate((g + 5 * 4096).into(), Access::Write)
+            .is_some());
+        assert_eq!(mmu.brk(0), top);
+    }
+
+    #[test]
+    fn test_code_generation() {
+        let mmu = GuestMmu::new(4096);
+        let g = mmu.mmap(4 * 4096, false).unwrap();
+        let code = mmu.code_generation();
+
+        // data coming and going leaves decoded code alone
+        mmu.munmap(g + 3 * 4096, 4096);
+        mmu.init_brk(g.as_u64() + 3 * 4096);
+        mmu.brk(g.as_u64() + 4 * 4096);
+        mmu.munmap(g + 4096, 4096);
+        mmu.mprotect(g, 4096, Prot::RX).unwrap();
+        assert_eq!(mmu.code_generation(), code);
+
+        // but not code losing exec, or going away
+        mmu.mprotect(g, 4096, Prot::RW).unwrap();
+        assert_ne!(mmu.code_generation(), code);
+        let code = mmu.code_generation();
+        mmu.mprotect(g, 4096, Prot::RX).unwrap();
+        mmu.munmap(g, 4096);
+        assert_ne!(mmu.code_generation(), code);
+    }
+
+    #[test]
+    fn test_tlb() {
+        let mmu = GuestMmu::new(4096);
+        let tlb = Tlb::default();
+        let g = mmu.mmap(2 * 4096, false).unwrap();
+        let h = mmu.g2h(g + 100).unwrap();
+        assert_eq!(tlb.translate(&mmu, g + 100, Access::Write), Some(h));
+
+        // cached translations go away with the mappings
+        let ro = Prot {
+            read: true,
+            write: false,
+            exec: false,
+        };
+        mmu.mprotect(g, 4096, ro).unwrap();
+        assert_eq!(tlb.translate(&mmu, g + 100, Access::Write), None);
+        assert_eq!(tlb.translate(&mmu, g + 100, Access::Read), Some(h));
+        mmu.munmap(g, 4096);
+        assert_eq!(tlb.translate(&mmu, g + 100, Access::Read), None);
+        assert!(tlb.translate(&mmu, g + 4096, Access::Write).is_some());
+
+        // an injected block ends where it ends, not at a page boundary
+        let buf = [0u8; 8];
+        let b = mmu.consume_host(buf.as_ptr(), 6).unwrap();
+        assert!(tlb.translate(&mmu, b + 5, Access::Read).is_some());
+        assert!(tlb.translate(&mmu, b + 6, Access::Read).is_none());
+    }
+
+    #[test]
+    fn test_guest_base() {
+        let size = 1 << 32;
+        let mmu = GuestMmu::with_address_space(4096, AddressSpace::GuestBase { size }).unwrap();
+        let base = mmu.guest_base().unwrap();
+
+        let g = GuestAddr(0x10000);
+        mmu.mmap_fixed(g, 4096).unwrap();
+        mmu.mmap_fixed(g + 4096, 4096).unwrap();
+        assert_eq!(mmu.g2h(g).unwrap().as_u64(), base + 0x10000);
+        // separate mappings are still contiguous on the host
+        assert_eq!(mmu.host_chunks(g + 100, 8000).unwrap().len(), 1);
+        mmu.write_bytes(g + 4090, b"crossing").unwrap();
+        let mut buf = [0u8; 8];
+        mmu.read_bytes(g + 4090, &mut buf).unwrap();
+        assert_eq!(&buf, b"crossing");
+
+        // addresses outside the region are refused
+        assert!(mmu.mmap_fixed(GuestAddr(size), 4096).is_err());
+        assert!(mmu.consume_host(buf.as_ptr(), 8).is_err());
+
+        // unmapped memory goes back to being reserved
+        mmu.munmap(g + 4096, 4096);
+        assert!(mmu.g2h(g + 4096).is_none());
+        assert!(mmu.read_bytes(g + 4090, &mut buf).is_err());
+
+        // growing in place keeps the contents
+        let g2 = mmu.mremap(g, 4096, 3 * 4096, false, None).unwrap();
+        assert_eq!(g2, g);
+        mmu.read_bytes(g + 4090, &mut buf).unwrap();
+        assert_eq!(&buf[..6], b"crossi");
+        assert_eq!(mmu.g2h(g + 2 * 4096).unwrap().as_u64(), base + 0x12000);
 
-        None
+        // and so does moving
+        let to = GuestAddr(0x100000);
+        assert_eq!(
+            mmu.mremap(g, 3 * 4096, 3 * 4096, true, Some(to)).unwrap(),
+            to
+        );
+        assert!(mmu.g2h(g).is_none());
+        mmu.read_bytes(to + 4090, &mut buf).unwrap();
+        assert_eq!(&buf[..6], b"crossi");
+        assert_eq!(mmu.g2h(to).unwrap().as_u64(), base + 0x100000);
     }
 }
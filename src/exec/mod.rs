@@ -1,21 +1,56 @@
 pub mod interp;
+pub mod loader;
 pub mod mem;
+pub mod stack;
 
-#[derive(Debug)]
+pub(crate) const CANONICAL_NAN_F32: u32 = 0x7fc0_0000;
+pub(crate) const CANONICAL_NAN_F64: u64 = 0x7ff8_0000_0000_0000;
+const NAN_BOX_F32: u64 = 0xffff_ffff_0000_0000;
+
+#[derive(Clone, Debug)]
 pub enum StopReason {
     Next,
     ContinueAt(u64),
 
+    /// exit_group(2), the whole guest process is done.
+    Exit(i32),
+    /// exit(2) of the calling thread.
+    ExitThread(i32),
+    /// The guest process was killed by a signal it did not handle.
+    Signaled(i32),
+
     Break,
     ReservedInsn,
-    Segv { read: bool, gaddr: u64 },
+    Segv {
+        access: mem::Access,
+        gaddr: u64,
+    },
+    Misaligned {
+        read: bool,
+        gaddr: u64,
+    },
+}
+
+impl StopReason {
+    /// The signal Linux would deliver for a guest fault.
+    pub fn signal(&self) -> Option<i32> {
+        match self {
+            StopReason::Segv { .. } => Some(libc::SIGSEGV),
+            StopReason::Misaligned { .. } => Some(libc::SIGBUS),
+            StopReason::ReservedInsn => Some(libc::SIGILL),
+            StopReason::Break => Some(libc::SIGTRAP),
+            StopReason::Signaled(sig) => Some(*sig),
+            _ => None,
+        }
+    }
 }
 
-#[derive(PartialEq, Debug, Default)]
+#[derive(Clone, PartialEq, Debug, Default)]
 pub struct RvIsaState {
     pc: u64,
     regs_x: [u64; 31],
     regs_f: [u64; 32],
+    fcsr: u32,
 }
 
 impl RvIsaState {
@@ -44,23 +79,120 @@ impl RvIsaState {
         self.regs_x[idx as usize - 1] = val;
     }
 
+    pub fn get_fcsr(&self) -> u32 {
+        self.fcsr
+    }
+
+    pub fn set_fcsr(&mut self, val: u32) {
+        self.fcsr = val & 0xff;
+    }
+
+    pub fn get_frm(&self) -> u8 {
+        (self.fcsr >> 5) as u8 & 0b111
+    }
+
+    pub fn set_frm(&mut self, val: u8) {
+        self.fcsr = (self.fcsr & 0x1f) | ((val as u32 & 0b111) << 5);
+    }
+
+    pub fn get_fflags(&self) -> u8 {
+        self.fcsr as u8 & 0x1f
+    }
+
+    pub fn set_fflags(&mut self, val: u8) {
+        self.fcsr = (self.fcsr & !0x1f) | (val as u32 & 0x1f);
+    }
+
+    /// ORs exception flags into fflags; they stay set until software clears
+    /// them.
+    pub fn accrue_fflags(&mut self, flags: u8) {
+        self.fcsr |= flags as u32 & 0x1f;
+    }
+
+    /// Reads the raw 64-bit pattern of an FP register.
+    pub fn get_f_bits(&self, idx: u8) -> u64 {
+        debug_assert!(idx < 32);
+        self.regs_f[idx as usize]
+    }
+
+    /// Writes the raw 64-bit pattern of an FP register.
+    pub fn set_f_bits(&mut self, idx: u8, val: u64) {
+        debug_assert!(idx < 32);
+        self.regs_f[idx as usize] = val;
+    }
+
+    /// Writes a raw single-precision pattern, NaN-boxing it.
+    pub fn set_f32_bits(&mut self, idx: u8, val: u32) {
+        debug_assert!(idx < 32);
+        self.regs_f[idx as usize] = NAN_BOX_F32 | val as u64;
+    }
+
+    /// Reads a single-precision value. Values that are not properly
+    /// NaN-boxed read as the canonical NaN.
     pub fn get_f32(&self, idx: u8) -> f32 {
         debug_assert!(idx < 32);
-        self.regs_f[idx as usize] as u32 as f32
+        let v = self.regs_f[idx as usize];
+        if v & NAN_BOX_F32 != NAN_BOX_F32 {
+            f32::from_bits(CANONICAL_NAN_F32)
+        } else {
+            f32::from_bits(v as u32)
+        }
     }
 
+    /// Writes a single-precision value, NaN-boxing it into the 64-bit
+    /// register.
     pub fn set_f32(&mut self, idx: u8, val: f32) {
         debug_assert!(idx < 32);
-        self.regs_f[idx as usize] = val as i32 as i64 as u64;
+        self.regs_f[idx as usize] = NAN_BOX_F32 | val.to_bits() as u64;
     }
 
     pub fn get_f64(&self, idx: u8) -> f64 {
         debug_assert!(idx < 32);
-        self.regs_f[idx as usize] as f64
+        f64::from_bits(self.regs_f[idx as usize])
     }
 
     pub fn set_f64(&mut self, idx: u8, val: f64) {
         debug_assert!(idx < 32);
-        self.regs_f[idx as usize] = val as u64;
+        self.regs_f[idx as usize] = val.to_bits();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_f_regs_bit_exact() {
+        let mut s = RvIsaState::default();
+
+        // a signalling NaN with payload must survive unchanged
+        s.set_f_bits(1, 0x7ff0_dead_beef_0001);
+        assert_eq!(s.get_f_bits(1), 0x7ff0_dead_beef_0001);
+        assert_eq!(s.get_f64(1).to_bits(), 0x7ff0_dead_beef_0001);
+
+        s.set_f64(2, -1.5);
+        assert_eq!(s.get_f_bits(2), (-1.5f64).to_bits());
+        assert_eq!(s.get_f64(2), -1.5);
+    }
+
+    #[test]
+    fn test_f_regs_nan_boxing() {
+        let mut s = RvIsaState::default();
+
+        s.set_f32(1, 2.5);
+        assert_eq!(
+            s.get_f_bits(1),
+            0xffff_ffff_0000_0000 | 2.5f32.to_bits() as u64
+        );
+        assert_eq!(s.get_f32(1), 2.5);
+
+        s.set_f32_bits(2, 0x7f80_0001);
+        assert_eq!(s.get_f32(2).to_bits(), 0x7f80_0001);
+
+        // improperly boxed values read as the canonical NaN
+        s.set_f64(3, 2.5);
+        assert_eq!(s.get_f32(3).to_bits(), CANONICAL_NAN_F32);
+        s.set_f_bits(4, 0x7fff_ffff_3f80_0000);
+        assert_eq!(s.get_f32(4).to_bits(), CANONICAL_NAN_F32);
     }
 }
@@ -0,0 +1,163 @@
+//! Initial process stack, as laid out by Linux at execve.
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+
+// auxv keys
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_BASE: u64 = 7;
+pub const AT_ENTRY: u64 = 9;
+pub const AT_UID: u64 = 11;
+pub const AT_EUID: u64 = 12;
+pub const AT_GID: u64 = 13;
+pub const AT_EGID: u64 = 14;
+pub const AT_HWCAP: u64 = 16;
+pub const AT_CLKTCK: u64 = 17;
+pub const AT_SECURE: u64 = 23;
+pub const AT_RANDOM: u64 = 25;
+pub const AT_EXECFN: u64 = 31;
+
+/// AT_HWCAP for RV64IMAFDC, one bit per extension letter.
+pub const HWCAP_RV64GC: u64 = {
+    let mut x = 0;
+    let exts = b"IMAFDC";
+    let mut i = 0;
+    while i < exts.len() {
+        x |= 1 << (exts[i] - b'A');
+        i += 1;
+    }
+    x
+};
+
+/// The top part of a stack, ready to be copied to `sp`.
+pub struct InitialStack {
+    pub sp: u64,
+    pub bytes: Vec<u8>,
+}
+
+fn align_down(x: u64, align: u64) -> u64 {
+    x & !(align - 1)
+}
+
+/// Lays out argc, argv, envp and the auxiliary vector below `top`, along with
+/// the strings they point to and the AT_RANDOM bytes. AT_RANDOM, AT_EXECFN
+/// and the terminating AT_NULL are appended to `auxv`. The strings are
+/// copied byte for byte, whether they are UTF-8 or not.
+pub fn build_initial_stack<'s>(
+    top: u64,
+    execfn: &'s OsStr,
+    argv: &'s [OsString],
+    envp: &'s [OsString],
+    auxv: &[(u64, u64)],
+    random: &[u8; 16],
+) -> InitialStack {
+    // strings go at the very top, in the same order as Linux: the file name,
+    // then the environment, then the arguments, each group ascending
+    let mut strings: Vec<(u64, &[u8])> = Vec::new();
+    let mut p = top - 8;
+    let mut push_str = |s: &'s OsStr| {
+        p -= s.len() as u64 + 1;
+        strings.push((p, s.as_bytes()));
+        p
+    };
+    let execfn_addr = push_str(execfn);
+    let mut envp_addrs: Vec<u64> = envp.iter().rev().map(|s| push_str(s)).collect();
+    let mut argv_addrs: Vec<u64> = argv.iter().rev().map(|s| push_str(s)).collect();
+    envp_addrs.reverse();
+    argv_addrs.reverse();
+
+    let random_addr = align_down(p - random.len() as u64, 16);
+
+    let mut words: Vec<u64> = Vec::new();
+    words.push(argv.len() as u64);
+    words.extend(argv_addrs);
+    words.push(0);
+    words.extend(envp_addrs);
+    words.push(0);
+    for &(k, v) in auxv {
+        words.extend([k, v]);
+    }
+    words.extend([AT_RANDOM, random_addr, AT_EXECFN, execfn_addr, AT_NULL, 0]);
+
+    // the ABI requires sp to be 16-byte aligned at process entry
+    let sp = align_down(random_addr - words.len() as u64 * 8, 16);
+
+    let mut bytes = vec![0u8; (top - sp) as usize];
+    let off = |addr: u64| (addr - sp) as usize;
+    for (i, w) in words.iter().enumerate() {
+        let o = off(sp) + i * 8;
+        bytes[o..o + 8].copy_from_slice(&w.to_le_bytes());
+    }
+    bytes[off(random_addr)..off(random_addr) + 16].copy_from_slice(random);
+    for (addr, s) in strings {
+        // the NUL terminator is already there
+        bytes[off(addr)..off(addr) + s.len()].copy_from_slice(s);
+    }
+
+    InitialStack { sp, bytes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(st: &InitialStack, addr: u64) -> u64 {
+        let o = (addr - st.sp) as usize;
+        u64::from_le_bytes(st.bytes[o..o + 8].try_into().unwrap())
+    }
+
+    fn cstr(st: &InitialStack, addr: u64) -> &[u8] {
+        let o = (addr - st.sp) as usize;
+        let len = st.bytes[o..].iter().position(|&b| b == 0).unwrap();
+        &st.bytes[o..o + len]
+    }
+
+    #[test]
+    fn test_hwcap() {
+        assert_eq!(HWCAP_RV64GC, 0x112d);
+    }
+
+    #[test]
+    fn test_layout() {
+        let top = 0x7fff_0000;
+        let argv: Vec<OsString> = vec!["/bin/true".into(), "-x".into()];
+        let envp: Vec<OsString> = vec!["A=b".into(), OsStr::from_bytes(b"B=\xff").into()];
+        let random = [7u8; 16];
+        let st = build_initial_stack(
+            top,
+            OsStr::new("/bin/true"),
+            &argv,
+            &envp,
+            &[(AT_PAGESZ, 4096)],
+            &random,
+        );
+
+        assert_eq!(st.sp % 16, 0);
+        assert_eq!(st.sp + st.bytes.len() as u64, top);
+
+        let mut p = st.sp;
+        let mut next = || {
+            let w = word(&st, p);
+            p += 8;
+            w
+        };
+        assert_eq!(next(), 2);
+        assert_eq!(cstr(&st, next()), b"/bin/true");
+        assert_eq!(cstr(&st, next()), b"-x");
+        assert_eq!(next(), 0);
+        assert_eq!(cstr(&st, next()), b"A=b");
+        assert_eq!(cstr(&st, next()), b"B=\xff");
+        assert_eq!(next(), 0);
+        assert_eq!((next(), next()), (AT_PAGESZ, 4096));
+        assert_eq!(next(), AT_RANDOM);
+        let r = next();
+        assert_eq!(&st.bytes[(r - st.sp) as usize..][..16], &random);
+        assert_eq!(next(), AT_EXECFN);
+        assert_eq!(cstr(&st, next()), b"/bin/true");
+        assert_eq!((next(), next()), (AT_NULL, 0));
+    }
+}
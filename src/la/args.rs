@@ -0,0 +1,194 @@
+#[derive(Clone, Copy, Debug)]
+pub struct R2Args {
+    pub rd: u8,
+    pub rj: u8,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct R3Args {
+    pub rd: u8,
+    pub rj: u8,
+    pub rk: u8,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct R4Args {
+    pub rd: u8,
+    pub rj: u8,
+    pub rk: u8,
+    pub ra: u8,
+}
+
+// also used for the zero-extended ui12 of andi/ori/xori
+#[derive(Clone, Copy, Debug)]
+pub struct R2I12Args {
+    pub rd: u8,
+    pub rj: u8,
+    pub imm: i32,
+}
+
+// variant of R2I12Args; imm is in bytes and must be a multiple of 4
+#[derive(Clone, Copy, Debug)]
+pub struct R2I14Args {
+    pub rd: u8,
+    pub rj: u8,
+    pub imm: i32,
+}
+
+// jirl and the two-register branches; imm is in bytes
+#[derive(Clone, Copy, Debug)]
+pub struct R2I16Args {
+    pub rd: u8,
+    pub rj: u8,
+    pub imm: i32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct R1I20Args {
+    pub rd: u8,
+    pub imm: i32,
+}
+
+// beqz and bnez; imm is in bytes
+#[derive(Clone, Copy, Debug)]
+pub struct R1I21Args {
+    pub rj: u8,
+    pub imm: i32,
+}
+
+// variant of R2I12Args
+#[derive(Clone, Copy, Debug)]
+pub struct ShiftArgs {
+    pub rd: u8,
+    pub rj: u8,
+    pub shamt: u8,
+}
+
+// variant of R2I12Args
+#[derive(Clone, Copy, Debug)]
+pub struct BstrArgs {
+    pub rd: u8,
+    pub rj: u8,
+    pub msb: u8,
+    pub lsb: u8,
+}
+
+/// Conditions of `fcmp.cond.{s,d}`.
+///
+/// The `C` forms are quiet and the `S` forms signal on any NaN operand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FcmpCond {
+    Caf,
+    Saf,
+    Clt,
+    Slt,
+    Ceq,
+    Seq,
+    Cle,
+    Sle,
+    Cun,
+    Sun,
+    Cult,
+    Sult,
+    Cueq,
+    Sueq,
+    Cule,
+    Sule,
+    Cne,
+    Sne,
+    Cor,
+    Sor,
+    Cune,
+    Sune,
+    Reserved(u8),
+}
+
+impl From<u8> for FcmpCond {
+    fn from(x: u8) -> Self {
+        match x {
+            0x00 => Self::Caf,
+            0x01 => Self::Saf,
+            0x02 => Self::Clt,
+            0x03 => Self::Slt,
+            0x04 => Self::Ceq,
+            0x05 => Self::Seq,
+            0x06 => Self::Cle,
+            0x07 => Self::Sle,
+            0x08 => Self::Cun,
+            0x09 => Self::Sun,
+            0x0a => Self::Cult,
+            0x0b => Self::Sult,
+            0x0c => Self::Cueq,
+            0x0d => Self::Sueq,
+            0x0e => Self::Cule,
+            0x0f => Self::Sule,
+            0x10 => Self::Cne,
+            0x11 => Self::Sne,
+            0x14 => Self::Cor,
+            0x15 => Self::Sor,
+            0x18 => Self::Cune,
+            0x19 => Self::Sune,
+            _ => Self::Reserved(x),
+        }
+    }
+}
+
+impl From<FcmpCond> for u8 {
+    fn from(x: FcmpCond) -> Self {
+        match x {
+            FcmpCond::Caf => 0x00,
+            FcmpCond::Saf => 0x01,
+            FcmpCond::Clt => 0x02,
+            FcmpCond::Slt => 0x03,
+            FcmpCond::Ceq => 0x04,
+            FcmpCond::Seq => 0x05,
+            FcmpCond::Cle => 0x06,
+            FcmpCond::Sle => 0x07,
+            FcmpCond::Cun => 0x08,
+            FcmpCond::Sun => 0x09,
+            FcmpCond::Cult => 0x0a,
+            FcmpCond::Sult => 0x0b,
+            FcmpCond::Cueq => 0x0c,
+            FcmpCond::Sueq => 0x0d,
+            FcmpCond::Cule => 0x0e,
+            FcmpCond::Sule => 0x0f,
+            FcmpCond::Cne => 0x10,
+            FcmpCond::Sne => 0x11,
+            FcmpCond::Cor => 0x14,
+            FcmpCond::Sor => 0x15,
+            FcmpCond::Cune => 0x18,
+            FcmpCond::Sune => 0x19,
+            FcmpCond::Reserved(x) => x,
+        }
+    }
+}
+
+// variant of R3Args; cd is a condition flag register
+#[derive(Clone, Copy, Debug)]
+pub struct FcmpArgs {
+    pub cond: FcmpCond,
+    pub cd: u8,
+    pub fj: u8,
+    pub fk: u8,
+}
+
+// bceqz and bcnez; imm is in bytes
+#[derive(Clone, Copy, Debug)]
+pub struct CfBranchArgs {
+    pub cj: u8,
+    pub imm: i32,
+}
+
+// movgr2cf: cd, rj; movcf2gr: rd, cj
+#[derive(Clone, Copy, Debug)]
+pub struct CfMoveArgs {
+    pub cf: u8,
+    pub gr: u8,
+}
+
+// movgr2fcsr: fcsr, rj; movfcsr2gr: rd, fcsr
+#[derive(Clone, Copy, Debug)]
+pub struct FcsrMoveArgs {
+    pub fcsr: u8,
+    pub gr: u8,
+}
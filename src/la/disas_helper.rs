@@ -0,0 +1,141 @@
+use super::args::*;
+
+fn sext(x: u32, bits: u32) -> i32 {
+    ((x << (32 - bits)) as i32) >> (32 - bits)
+}
+
+fn rd(insn: u32) -> u8 {
+    (insn & 0x1f) as u8
+}
+
+fn rj(insn: u32) -> u8 {
+    ((insn >> 5) & 0x1f) as u8
+}
+
+fn rk(insn: u32) -> u8 {
+    ((insn >> 10) & 0x1f) as u8
+}
+
+pub(super) fn r2(insn: u32) -> R2Args {
+    R2Args {
+        rd: rd(insn),
+        rj: rj(insn),
+    }
+}
+
+pub(super) fn r3(insn: u32) -> R3Args {
+    R3Args {
+        rd: rd(insn),
+        rj: rj(insn),
+        rk: rk(insn),
+    }
+}
+
+pub(super) fn r4(insn: u32) -> R4Args {
+    R4Args {
+        rd: rd(insn),
+        rj: rj(insn),
+        rk: rk(insn),
+        ra: ((insn >> 15) & 0x1f) as u8,
+    }
+}
+
+pub(super) fn shift5(insn: u32) -> ShiftArgs {
+    ShiftArgs {
+        rd: rd(insn),
+        rj: rj(insn),
+        shamt: ((insn >> 10) & 0x1f) as u8,
+    }
+}
+
+pub(super) fn shift6(insn: u32) -> ShiftArgs {
+    ShiftArgs {
+        rd: rd(insn),
+        rj: rj(insn),
+        shamt: ((insn >> 10) & 0x3f) as u8,
+    }
+}
+
+pub(super) fn bstr_d(insn: u32) -> BstrArgs {
+    BstrArgs {
+        rd: rd(insn),
+        rj: rj(insn),
+        msb: ((insn >> 16) & 0x3f) as u8,
+        lsb: ((insn >> 10) & 0x3f) as u8,
+    }
+}
+
+pub(super) fn si12(insn: u32) -> R2I12Args {
+    R2I12Args {
+        rd: rd(insn),
+        rj: rj(insn),
+        imm: sext((insn >> 10) & 0xfff, 12),
+    }
+}
+
+pub(super) fn ui12(insn: u32) -> R2I12Args {
+    R2I12Args {
+        rd: rd(insn),
+        rj: rj(insn),
+        imm: ((insn >> 10) & 0xfff) as i32,
+    }
+}
+
+pub(super) fn si14(insn: u32) -> R2I14Args {
+    R2I14Args {
+        rd: rd(insn),
+        rj: rj(insn),
+        imm: sext((insn >> 10) & 0x3fff, 14) << 2,
+    }
+}
+
+pub(super) fn si16(insn: u32) -> R2I16Args {
+    R2I16Args {
+        rd: rd(insn),
+        rj: rj(insn),
+        imm: sext((insn >> 10) & 0xffff, 16) << 2,
+    }
+}
+
+pub(super) fn si20(insn: u32) -> R1I20Args {
+    R1I20Args {
+        rd: rd(insn),
+        imm: sext((insn >> 5) & 0xfffff, 20),
+    }
+}
+
+// offs[15:0] is above the register, offs[20:16] below it
+pub(super) fn offs21(insn: u32) -> i32 {
+    sext((insn & 0x1f) << 16 | (insn >> 10) & 0xffff, 21) << 2
+}
+
+pub(super) fn si21(insn: u32) -> R1I21Args {
+    R1I21Args {
+        rj: rj(insn),
+        imm: offs21(insn),
+    }
+}
+
+pub(super) fn si26(insn: u32) -> i32 {
+    sext((insn & 0x3ff) << 16 | (insn >> 10) & 0xffff, 26) << 2
+}
+
+pub(super) fn code15(insn: u32) -> u16 {
+    (insn & 0x7fff) as u16
+}
+
+pub(super) fn fcmp(insn: u32) -> FcmpArgs {
+    FcmpArgs {
+        cond: (((insn >> 15) & 0x1f) as u8).into(),
+        cd: (insn & 0x7) as u8,
+        fj: rj(insn),
+        fk: rk(insn),
+    }
+}
+
+pub(super) fn cf_branch(insn: u32) -> CfBranchArgs {
+    CfBranchArgs {
+        cj: ((insn >> 5) & 0x7) as u8,
+        imm: offs21(insn),
+    }
+}
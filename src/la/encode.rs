@@ -0,0 +1,403 @@
+use super::args::*;
+use super::insn::LaInsn;
+
+fn fits_signed(x: i32, bits: u32) -> bool {
+    let lim = 1i32 << (bits - 1);
+    (-lim..lim).contains(&x)
+}
+
+// branch offsets are in bytes but encoded in instruction units
+fn offs(imm: i32, bits: u32) -> u32 {
+    debug_assert!(imm & 3 == 0, "misaligned offset {}", imm);
+    debug_assert!(fits_signed(imm >> 2, bits), "offset {} out of range", imm);
+    (imm >> 2) as u32 & ((1 << bits) - 1)
+}
+
+fn reg(r: u8) -> u32 {
+    debug_assert!(r < 32, "bad register {}", r);
+    r as u32
+}
+
+fn cf(r: u8) -> u32 {
+    debug_assert!(r < 8, "bad condition flag {}", r);
+    r as u32
+}
+
+fn r2(op: u32, a: R2Args) -> u32 {
+    op | reg(a.rj) << 5 | reg(a.rd)
+}
+
+fn r3(op: u32, a: R3Args) -> u32 {
+    op | reg(a.rk) << 10 | reg(a.rj) << 5 | reg(a.rd)
+}
+
+fn r4(op: u32, a: R4Args) -> u32 {
+    op | reg(a.ra) << 15 | reg(a.rk) << 10 | reg(a.rj) << 5 | reg(a.rd)
+}
+
+fn shift5(op: u32, a: ShiftArgs) -> u32 {
+    debug_assert!(a.shamt < 32);
+    op | (a.shamt as u32) << 10 | reg(a.rj) << 5 | reg(a.rd)
+}
+
+fn shift6(op: u32, a: ShiftArgs) -> u32 {
+    debug_assert!(a.shamt < 64);
+    op | (a.shamt as u32) << 10 | reg(a.rj) << 5 | reg(a.rd)
+}
+
+fn bstr_d(op: u32, a: BstrArgs) -> u32 {
+    debug_assert!(a.lsb <= a.msb && a.msb < 64);
+    op | (a.msb as u32) << 16 | (a.lsb as u32) << 10 | reg(a.rj) << 5 | reg(a.rd)
+}
+
+fn si12(op: u32, a: R2I12Args) -> u32 {
+    debug_assert!(fits_signed(a.imm, 12), "si12 {} out of range", a.imm);
+    op | (a.imm as u32 & 0xfff) << 10 | reg(a.rj) << 5 | reg(a.rd)
+}
+
+fn ui12(op: u32, a: R2I12Args) -> u32 {
+    debug_assert!((0..0x1000).contains(&a.imm), "ui12 {} out of range", a.imm);
+    op | (a.imm as u32) << 10 | reg(a.rj) << 5 | reg(a.rd)
+}
+
+fn si14(op: u32, a: R2I14Args) -> u32 {
+    op | offs(a.imm, 14) << 10 | reg(a.rj) << 5 | reg(a.rd)
+}
+
+fn si16(op: u32, a: R2I16Args) -> u32 {
+    op | offs(a.imm, 16) << 10 | reg(a.rj) << 5 | reg(a.rd)
+}
+
+fn si20(op: u32, a: R1I20Args) -> u32 {
+    debug_assert!(fits_signed(a.imm, 20), "si20 {} out of range", a.imm);
+    op | (a.imm as u32 & 0xfffff) << 5 | reg(a.rd)
+}
+
+// offs[15:0] goes above the register, offs[20:16] below it
+fn si21(op: u32, rj: u32, imm: i32) -> u32 {
+    let o = offs(imm, 21);
+    op | (o & 0xffff) << 10 | rj << 5 | o >> 16
+}
+
+fn si26(op: u32, imm: i32) -> u32 {
+    let o = offs(imm, 26);
+    op | (o & 0xffff) << 10 | o >> 16
+}
+
+fn code15(op: u32, code: u16) -> u32 {
+    debug_assert!(code < 0x8000);
+    op | code as u32
+}
+
+fn fcmp(op: u32, a: FcmpArgs) -> u32 {
+    op | (u8::from(a.cond) as u32) << 15 | reg(a.fk) << 10 | reg(a.fj) << 5 | cf(a.cd)
+}
+
+impl LaInsn {
+    /// Returns the 32-bit instruction word.
+    ///
+    /// Immediates are expected to be in range; this is only checked in debug
+    /// builds.
+    pub fn encode(&self) -> u32 {
+        match *self {
+            LaInsn::Invalid(insn) => insn,
+
+            LaInsn::AddW(a) => r3(0x0010_0000, a),
+            LaInsn::AddD(a) => r3(0x0010_8000, a),
+            LaInsn::SubW(a) => r3(0x0011_0000, a),
+            LaInsn::SubD(a) => r3(0x0011_8000, a),
+            LaInsn::Slt(a) => r3(0x0012_0000, a),
+            LaInsn::Sltu(a) => r3(0x0012_8000, a),
+            LaInsn::Maskeqz(a) => r3(0x0013_0000, a),
+            LaInsn::Masknez(a) => r3(0x0013_8000, a),
+            LaInsn::Nor(a) => r3(0x0014_0000, a),
+            LaInsn::And(a) => r3(0x0014_8000, a),
+            LaInsn::Or(a) => r3(0x0015_0000, a),
+            LaInsn::Xor(a) => r3(0x0015_8000, a),
+            LaInsn::Orn(a) => r3(0x0016_0000, a),
+            LaInsn::Andn(a) => r3(0x0016_8000, a),
+            LaInsn::SllW(a) => r3(0x0017_0000, a),
+            LaInsn::SrlW(a) => r3(0x0017_8000, a),
+            LaInsn::SraW(a) => r3(0x0018_0000, a),
+            LaInsn::SllD(a) => r3(0x0018_8000, a),
+            LaInsn::SrlD(a) => r3(0x0019_0000, a),
+            LaInsn::SraD(a) => r3(0x0019_8000, a),
+            LaInsn::SlliW(a) => shift5(0x0040_8000, a),
+            LaInsn::SlliD(a) => shift6(0x0041_0000, a),
+            LaInsn::SrliW(a) => shift5(0x0044_8000, a),
+            LaInsn::SrliD(a) => shift6(0x0045_0000, a),
+            LaInsn::SraiW(a) => shift5(0x0048_8000, a),
+            LaInsn::SraiD(a) => shift6(0x0049_0000, a),
+            LaInsn::BstrinsD(a) => bstr_d(0x0080_0000, a),
+            LaInsn::BstrpickD(a) => bstr_d(0x00c0_0000, a),
+            LaInsn::Slti(a) => si12(0x0200_0000, a),
+            LaInsn::Sltui(a) => si12(0x0240_0000, a),
+            LaInsn::AddiW(a) => si12(0x0280_0000, a),
+            LaInsn::AddiD(a) => si12(0x02c0_0000, a),
+            LaInsn::Lu52iD(a) => si12(0x0300_0000, a),
+            LaInsn::Andi(a) => ui12(0x0340_0000, a),
+            LaInsn::Ori(a) => ui12(0x0380_0000, a),
+            LaInsn::Xori(a) => ui12(0x03c0_0000, a),
+            LaInsn::Lu12iW(a) => si20(0x1400_0000, a),
+            LaInsn::Lu32iD(a) => si20(0x1600_0000, a),
+            LaInsn::Pcaddu12i(a) => si20(0x1c00_0000, a),
+
+            LaInsn::MulW(a) => r3(0x001c_0000, a),
+            LaInsn::MulhW(a) => r3(0x001c_8000, a),
+            LaInsn::MulhWu(a) => r3(0x001d_0000, a),
+            LaInsn::MulD(a) => r3(0x001d_8000, a),
+            LaInsn::MulhD(a) => r3(0x001e_0000, a),
+            LaInsn::MulhDu(a) => r3(0x001e_8000, a),
+            LaInsn::DivW(a) => r3(0x0020_0000, a),
+            LaInsn::ModW(a) => r3(0x0020_8000, a),
+            LaInsn::DivWu(a) => r3(0x0021_0000, a),
+            LaInsn::ModWu(a) => r3(0x0021_8000, a),
+            LaInsn::DivD(a) => r3(0x0022_0000, a),
+            LaInsn::ModD(a) => r3(0x0022_8000, a),
+            LaInsn::DivDu(a) => r3(0x0023_0000, a),
+            LaInsn::ModDu(a) => r3(0x0023_8000, a),
+
+            LaInsn::LdB(a) => si12(0x2800_0000, a),
+            LaInsn::LdH(a) => si12(0x2840_0000, a),
+            LaInsn::LdW(a) => si12(0x2880_0000, a),
+            LaInsn::LdD(a) => si12(0x28c0_0000, a),
+            LaInsn::StB(a) => si12(0x2900_0000, a),
+            LaInsn::StH(a) => si12(0x2940_0000, a),
+            LaInsn::StW(a) => si12(0x2980_0000, a),
+            LaInsn::StD(a) => si12(0x29c0_0000, a),
+            LaInsn::LdBu(a) => si12(0x2a00_0000, a),
+            LaInsn::LdHu(a) => si12(0x2a40_0000, a),
+            LaInsn::LdWu(a) => si12(0x2a80_0000, a),
+
+            LaInsn::LlW(a) => si14(0x2000_0000, a),
+            LaInsn::ScW(a) => si14(0x2100_0000, a),
+            LaInsn::LlD(a) => si14(0x2200_0000, a),
+            LaInsn::ScD(a) => si14(0x2300_0000, a),
+            LaInsn::AmswapW(a) => r3(0x3860_0000, a),
+            LaInsn::AmswapD(a) => r3(0x3860_8000, a),
+            LaInsn::AmaddW(a) => r3(0x3861_0000, a),
+            LaInsn::AmaddD(a) => r3(0x3861_8000, a),
+            LaInsn::AmandW(a) => r3(0x3862_0000, a),
+            LaInsn::AmandD(a) => r3(0x3862_8000, a),
+            LaInsn::AmorW(a) => r3(0x3863_0000, a),
+            LaInsn::AmorD(a) => r3(0x3863_8000, a),
+            LaInsn::AmxorW(a) => r3(0x3864_0000, a),
+            LaInsn::AmxorD(a) => r3(0x3864_8000, a),
+            LaInsn::AmmaxW(a) => r3(0x3865_0000, a),
+            LaInsn::AmmaxD(a) => r3(0x3865_8000, a),
+            LaInsn::AmminW(a) => r3(0x3866_0000, a),
+            LaInsn::AmminD(a) => r3(0x3866_8000, a),
+            LaInsn::AmmaxWu(a) => r3(0x3867_0000, a),
+            LaInsn::AmmaxDu(a) => r3(0x3867_8000, a),
+            LaInsn::AmminWu(a) => r3(0x3868_0000, a),
+            LaInsn::AmminDu(a) => r3(0x3868_8000, a),
+
+            LaInsn::Beqz(a) => si21(0x4000_0000, reg(a.rj), a.imm),
+            LaInsn::Bnez(a) => si21(0x4400_0000, reg(a.rj), a.imm),
+            LaInsn::Jirl(a) => si16(0x4c00_0000, a),
+            LaInsn::B(imm) => si26(0x5000_0000, imm),
+            LaInsn::Bl(imm) => si26(0x5400_0000, imm),
+            LaInsn::Beq(a) => si16(0x5800_0000, a),
+            LaInsn::Bne(a) => si16(0x5c00_0000, a),
+            LaInsn::Blt(a) => si16(0x6000_0000, a),
+            LaInsn::Bge(a) => si16(0x6400_0000, a),
+            LaInsn::Bltu(a) => si16(0x6800_0000, a),
+            LaInsn::Bgeu(a) => si16(0x6c00_0000, a),
+
+            LaInsn::Dbar(hint) => code15(0x3872_0000, hint),
+            LaInsn::Ibar(hint) => code15(0x3872_8000, hint),
+            LaInsn::Break(code) => code15(0x002a_0000, code),
+            LaInsn::Syscall(code) => code15(0x002b_0000, code),
+
+            LaInsn::RdtimeD(a) => r2(0x0000_6800, a),
+
+            LaInsn::FaddS(a) => r3(0x0100_8000, a),
+            LaInsn::FaddD(a) => r3(0x0101_0000, a),
+            LaInsn::FsubS(a) => r3(0x0102_8000, a),
+            LaInsn::FsubD(a) => r3(0x0103_0000, a),
+            LaInsn::FmulS(a) => r3(0x0104_8000, a),
+            LaInsn::FmulD(a) => r3(0x0105_0000, a),
+            LaInsn::FdivS(a) => r3(0x0106_8000, a),
+            LaInsn::FdivD(a) => r3(0x0107_0000, a),
+            LaInsn::FmaxS(a) => r3(0x0108_8000, a),
+            LaInsn::FmaxD(a) => r3(0x0109_0000, a),
+            LaInsn::FminS(a) => r3(0x010a_8000, a),
+            LaInsn::FminD(a) => r3(0x010b_0000, a),
+            LaInsn::FcopysignS(a) => r3(0x0112_8000, a),
+            LaInsn::FcopysignD(a) => r3(0x0113_0000, a),
+            LaInsn::FabsS(a) => r2(0x0114_0400, a),
+            LaInsn::FabsD(a) => r2(0x0114_0800, a),
+            LaInsn::FnegS(a) => r2(0x0114_1400, a),
+            LaInsn::FnegD(a) => r2(0x0114_1800, a),
+            LaInsn::FclassS(a) => r2(0x0114_3400, a),
+            LaInsn::FclassD(a) => r2(0x0114_3800, a),
+            LaInsn::FsqrtS(a) => r2(0x0114_4400, a),
+            LaInsn::FsqrtD(a) => r2(0x0114_4800, a),
+            LaInsn::FmaddS(a) => r4(0x0810_0000, a),
+            LaInsn::FmaddD(a) => r4(0x0820_0000, a),
+            LaInsn::FmsubS(a) => r4(0x0850_0000, a),
+            LaInsn::FmsubD(a) => r4(0x0860_0000, a),
+            LaInsn::FnmaddS(a) => r4(0x0890_0000, a),
+            LaInsn::FnmaddD(a) => r4(0x08a0_0000, a),
+            LaInsn::FnmsubS(a) => r4(0x08d0_0000, a),
+            LaInsn::FnmsubD(a) => r4(0x08e0_0000, a),
+            LaInsn::FcmpS(a) => fcmp(0x0c10_0000, a),
+            LaInsn::FcmpD(a) => fcmp(0x0c20_0000, a),
+
+            LaInsn::FcvtSD(a) => r2(0x0119_1800, a),
+            LaInsn::FcvtDS(a) => r2(0x0119_2400, a),
+            LaInsn::FtintrmWS(a) => r2(0x011a_0400, a),
+            LaInsn::FtintrmWD(a) => r2(0x011a_0800, a),
+            LaInsn::FtintrmLS(a) => r2(0x011a_2400, a),
+            LaInsn::FtintrmLD(a) => r2(0x011a_2800, a),
+            LaInsn::FtintrpWS(a) => r2(0x011a_4400, a),
+            LaInsn::FtintrpWD(a) => r2(0x011a_4800, a),
+            LaInsn::FtintrpLS(a) => r2(0x011a_6400, a),
+            LaInsn::FtintrpLD(a) => r2(0x011a_6800, a),
+            LaInsn::FtintrzWS(a) => r2(0x011a_8400, a),
+            LaInsn::FtintrzWD(a) => r2(0x011a_8800, a),
+            LaInsn::FtintrzLS(a) => r2(0x011a_a400, a),
+            LaInsn::FtintrzLD(a) => r2(0x011a_a800, a),
+            LaInsn::FtintrneWS(a) => r2(0x011a_c400, a),
+            LaInsn::FtintrneWD(a) => r2(0x011a_c800, a),
+            LaInsn::FtintrneLS(a) => r2(0x011a_e400, a),
+            LaInsn::FtintrneLD(a) => r2(0x011a_e800, a),
+            LaInsn::FtintWS(a) => r2(0x011b_0400, a),
+            LaInsn::FtintWD(a) => r2(0x011b_0800, a),
+            LaInsn::FtintLS(a) => r2(0x011b_2400, a),
+            LaInsn::FtintLD(a) => r2(0x011b_2800, a),
+            LaInsn::FfintSW(a) => r2(0x011d_1000, a),
+            LaInsn::FfintSL(a) => r2(0x011d_1800, a),
+            LaInsn::FfintDW(a) => r2(0x011d_2000, a),
+            LaInsn::FfintDL(a) => r2(0x011d_2800, a),
+
+            LaInsn::FmovS(a) => r2(0x0114_9400, a),
+            LaInsn::FmovD(a) => r2(0x0114_9800, a),
+            LaInsn::Movgr2frW(a) => r2(0x0114_a400, a),
+            LaInsn::Movgr2frD(a) => r2(0x0114_a800, a),
+            LaInsn::Movfr2grS(a) => r2(0x0114_b400, a),
+            LaInsn::Movfr2grD(a) => r2(0x0114_b800, a),
+            LaInsn::Movgr2fcsr(a) => 0x0114_c000 | reg(a.gr) << 5 | reg(a.fcsr),
+            LaInsn::Movfcsr2gr(a) => 0x0114_c800 | reg(a.fcsr) << 5 | reg(a.gr),
+            LaInsn::Movgr2cf(a) => 0x0114_d800 | reg(a.gr) << 5 | cf(a.cf),
+            LaInsn::Movcf2gr(a) => 0x0114_dc00 | cf(a.cf) << 5 | reg(a.gr),
+            LaInsn::Bceqz(a) => si21(0x4800_0000, cf(a.cj), a.imm),
+            LaInsn::Bcnez(a) => si21(0x4800_0000, 0b01000 | cf(a.cj), a.imm),
+
+            LaInsn::FldS(a) => si12(0x2b00_0000, a),
+            LaInsn::FstS(a) => si12(0x2b40_0000, a),
+            LaInsn::FldD(a) => si12(0x2b80_0000, a),
+            LaInsn::FstD(a) => si12(0x2bc0_0000, a),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn r3(rd: u8, rj: u8, rk: u8) -> R3Args {
+        R3Args { rd, rj, rk }
+    }
+
+    fn i12(rd: u8, rj: u8, imm: i32) -> R2I12Args {
+        R2I12Args { rd, rj, imm }
+    }
+
+    fn i16(rd: u8, rj: u8, imm: i32) -> R2I16Args {
+        R2I16Args { rd, rj, imm }
+    }
+
+    #[test]
+    fn test_encode() {
+        #[rustfmt::skip]
+        let cases = [
+            // ret
+            (LaInsn::Jirl(i16(0, 1, 0)), 0x4c00_0020),
+            // move $a0,$a1
+            (LaInsn::Or(r3(4, 5, 0)), 0x0015_00a4),
+            // nop
+            (LaInsn::Andi(i12(0, 0, 0)), 0x0340_0000),
+            // addi.d $sp,$sp,-16
+            (LaInsn::AddiD(i12(3, 3, -16)), 0x02ff_c063),
+            // st.d $ra,$sp,8
+            (LaInsn::StD(i12(1, 3, 8)), 0x29c0_2061),
+            // ld.d $ra,$sp,8
+            (LaInsn::LdD(i12(1, 3, 8)), 0x28c0_2061),
+            // ori $a0,$zero,0xfff
+            (LaInsn::Ori(i12(4, 0, 0xfff)), 0x03bf_fc04),
+            // pcaddu12i $t3,0
+            (LaInsn::Pcaddu12i(R1I20Args { rd: 15, imm: 0 }), 0x1c00_000f),
+            // lu12i.w $a0,-1
+            (LaInsn::Lu12iW(R1I20Args { rd: 4, imm: -1 }), 0x15ff_ffe4),
+            // jirl $t1,$t3,0
+            (LaInsn::Jirl(i16(13, 15, 0)), 0x4c00_01ed),
+            // add.d $a0,$a1,$a2
+            (LaInsn::AddD(r3(4, 5, 6)), 0x0010_98a4),
+            // mulh.du $a0,$a1,$a2
+            (LaInsn::MulhDu(r3(4, 5, 6)), 0x001e_98a4),
+            // div.wu $a0,$a1,$a2
+            (LaInsn::DivWu(r3(4, 5, 6)), 0x0021_18a4),
+            // slli.d $a0,$a0,32
+            (LaInsn::SlliD(ShiftArgs { rd: 4, rj: 4, shamt: 32 }), 0x0041_8084),
+            // srai.w $a0,$a1,3
+            (LaInsn::SraiW(ShiftArgs { rd: 4, rj: 5, shamt: 3 }), 0x0048_8ca4),
+            // bstrpick.d $a0,$a0,31,0
+            (LaInsn::BstrpickD(BstrArgs { rd: 4, rj: 4, msb: 31, lsb: 0 }), 0x00df_0084),
+            // ll.w $a0,$a1,4
+            (LaInsn::LlW(R2I14Args { rd: 4, rj: 5, imm: 4 }), 0x2000_04a4),
+            // sc.d $a2,$a1,-4
+            (LaInsn::ScD(R2I14Args { rd: 6, rj: 5, imm: -4 }), 0x23ff_fca6),
+            // amswap.w $a0,$a1,$a2
+            (LaInsn::AmswapW(r3(4, 6, 5)), 0x3860_14c4),
+            // ammax.du $a0,$a1,$a2
+            (LaInsn::AmmaxDu(r3(4, 6, 5)), 0x3867_94c4),
+            // beq $a0,$a1,16
+            (LaInsn::Beq(i16(5, 4, 16)), 0x5800_1085),
+            // blt $a1,$a0,-8
+            (LaInsn::Blt(i16(4, 5, -8)), 0x63ff_f8a4),
+            // beqz $a0,8
+            (LaInsn::Beqz(R1I21Args { rj: 4, imm: 8 }), 0x4000_0880),
+            // bnez $a0,-4
+            (LaInsn::Bnez(R1I21Args { rj: 4, imm: -4 }), 0x47ff_fc9f),
+            // b -4
+            (LaInsn::B(-4), 0x53ff_ffff),
+            // bl 8
+            (LaInsn::Bl(8), 0x5400_0800),
+            // syscall 0x0
+            (LaInsn::Syscall(0), 0x002b_0000),
+            // dbar 0x0
+            (LaInsn::Dbar(0), 0x3872_0000),
+            // ibar 0x0
+            (LaInsn::Ibar(0), 0x3872_8000),
+            // rdtime.d $a0,$zero
+            (LaInsn::RdtimeD(R2Args { rd: 4, rj: 0 }), 0x0000_6804),
+            // fadd.d $fa0,$fa0,$fa1
+            (LaInsn::FaddD(r3(0, 0, 1)), 0x0101_0400),
+            // fmadd.d $fa0,$fa1,$fa2,$fa3
+            (LaInsn::FmaddD(R4Args { rd: 0, rj: 1, rk: 2, ra: 3 }), 0x0821_8820),
+            // fcmp.clt.d $fcc0,$fa0,$fa1
+            (LaInsn::FcmpD(FcmpArgs { cond: FcmpCond::Clt, cd: 0, fj: 0, fk: 1 }), 0x0c21_0400),
+            // fcmp.ceq.s $fcc1,$fa2,$fa3
+            (LaInsn::FcmpS(FcmpArgs { cond: FcmpCond::Ceq, cd: 1, fj: 2, fk: 3 }), 0x0c12_0c41),
+            // ftintrz.l.d $fa0,$fa1
+            (LaInsn::FtintrzLD(R2Args { rd: 0, rj: 1 }), 0x011a_a820),
+            // movgr2fr.d $fa0,$a0
+            (LaInsn::Movgr2frD(R2Args { rd: 0, rj: 4 }), 0x0114_a880),
+            // movfr2gr.s $a0,$fa0
+            (LaInsn::Movfr2grS(R2Args { rd: 4, rj: 0 }), 0x0114_b404),
+            // movgr2fcsr $fcsr0,$a0
+            (LaInsn::Movgr2fcsr(FcsrMoveArgs { fcsr: 0, gr: 4 }), 0x0114_c080),
+            // movcf2gr $a0,$fcc1
+            (LaInsn::Movcf2gr(CfMoveArgs { cf: 1, gr: 4 }), 0x0114_dc24),
+            // bcnez $fcc0,8
+            (LaInsn::Bcnez(CfBranchArgs { cj: 0, imm: 8 }), 0x4800_0900),
+            // fld.d $fa0,$sp,8
+            (LaInsn::FldD(i12(0, 3, 8)), 0x2b80_2060),
+        ];
+
+        for (insn, word) in cases {
+            assert_eq!(insn.encode(), word, "{:?}", insn);
+        }
+    }
+}
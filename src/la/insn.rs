@@ -0,0 +1,624 @@
+use super::args::*;
+use super::disas_helper::*;
+
+#[derive(Clone, Copy, Debug)]
+pub enum LaInsn {
+    // Invalid or unsupported encoding
+    Invalid(u32),
+
+    // Integer arithmetic and logic
+    AddW(R3Args),
+    AddD(R3Args),
+    SubW(R3Args),
+    SubD(R3Args),
+    Slt(R3Args),
+    Sltu(R3Args),
+    Maskeqz(R3Args),
+    Masknez(R3Args),
+    Nor(R3Args),
+    And(R3Args),
+    Or(R3Args),
+    Xor(R3Args),
+    Orn(R3Args),
+    Andn(R3Args),
+    SllW(R3Args),
+    SrlW(R3Args),
+    SraW(R3Args),
+    SllD(R3Args),
+    SrlD(R3Args),
+    SraD(R3Args),
+    SlliW(ShiftArgs),
+    SlliD(ShiftArgs),
+    SrliW(ShiftArgs),
+    SrliD(ShiftArgs),
+    SraiW(ShiftArgs),
+    SraiD(ShiftArgs),
+    BstrinsD(BstrArgs),
+    BstrpickD(BstrArgs),
+    Slti(R2I12Args),
+    Sltui(R2I12Args),
+    AddiW(R2I12Args),
+    AddiD(R2I12Args),
+    Lu52iD(R2I12Args),
+    Andi(R2I12Args),
+    Ori(R2I12Args),
+    Xori(R2I12Args),
+    Lu12iW(R1I20Args),
+    Lu32iD(R1I20Args),
+    Pcaddu12i(R1I20Args),
+
+    // Multiplication and division
+    MulW(R3Args),
+    MulhW(R3Args),
+    MulhWu(R3Args),
+    MulD(R3Args),
+    MulhD(R3Args),
+    MulhDu(R3Args),
+    DivW(R3Args),
+    ModW(R3Args),
+    DivWu(R3Args),
+    ModWu(R3Args),
+    DivD(R3Args),
+    ModD(R3Args),
+    DivDu(R3Args),
+    ModDu(R3Args),
+
+    // Memory access
+    LdB(R2I12Args),
+    LdH(R2I12Args),
+    LdW(R2I12Args),
+    LdD(R2I12Args),
+    StB(R2I12Args),
+    StH(R2I12Args),
+    StW(R2I12Args),
+    StD(R2I12Args),
+    LdBu(R2I12Args),
+    LdHu(R2I12Args),
+    LdWu(R2I12Args),
+
+    // Atomics
+    LlW(R2I14Args),
+    ScW(R2I14Args),
+    LlD(R2I14Args),
+    ScD(R2I14Args),
+    AmswapW(R3Args),
+    AmswapD(R3Args),
+    AmaddW(R3Args),
+    AmaddD(R3Args),
+    AmandW(R3Args),
+    AmandD(R3Args),
+    AmorW(R3Args),
+    AmorD(R3Args),
+    AmxorW(R3Args),
+    AmxorD(R3Args),
+    AmmaxW(R3Args),
+    AmmaxD(R3Args),
+    AmminW(R3Args),
+    AmminD(R3Args),
+    AmmaxWu(R3Args),
+    AmmaxDu(R3Args),
+    AmminWu(R3Args),
+    AmminDu(R3Args),
+
+    // Branches; the two-register compares test rj against rd
+    Beqz(R1I21Args),
+    Bnez(R1I21Args),
+    Jirl(R2I16Args),
+    B(i32),
+    Bl(i32),
+    Beq(R2I16Args),
+    Bne(R2I16Args),
+    Blt(R2I16Args),
+    Bge(R2I16Args),
+    Bltu(R2I16Args),
+    Bgeu(R2I16Args),
+
+    // Barriers and traps
+    Dbar(u16),
+    Ibar(u16),
+    Break(u16),
+    Syscall(u16),
+
+    // Timer; rj receives the counter ID
+    RdtimeD(R2Args),
+
+    // Floating-point arithmetic
+    FaddS(R3Args),
+    FaddD(R3Args),
+    FsubS(R3Args),
+    FsubD(R3Args),
+    FmulS(R3Args),
+    FmulD(R3Args),
+    FdivS(R3Args),
+    FdivD(R3Args),
+    FmaxS(R3Args),
+    FmaxD(R3Args),
+    FminS(R3Args),
+    FminD(R3Args),
+    FcopysignS(R3Args),
+    FcopysignD(R3Args),
+    FabsS(R2Args),
+    FabsD(R2Args),
+    FnegS(R2Args),
+    FnegD(R2Args),
+    FclassS(R2Args),
+    FclassD(R2Args),
+    FsqrtS(R2Args),
+    FsqrtD(R2Args),
+    FmaddS(R4Args),
+    FmaddD(R4Args),
+    FmsubS(R4Args),
+    FmsubD(R4Args),
+    FnmaddS(R4Args),
+    FnmaddD(R4Args),
+    FnmsubS(R4Args),
+    FnmsubD(R4Args),
+    FcmpS(FcmpArgs),
+    FcmpD(FcmpArgs),
+
+    // Floating-point conversion
+    FcvtSD(R2Args),
+    FcvtDS(R2Args),
+    FtintrmWS(R2Args),
+    FtintrmWD(R2Args),
+    FtintrmLS(R2Args),
+    FtintrmLD(R2Args),
+    FtintrpWS(R2Args),
+    FtintrpWD(R2Args),
+    FtintrpLS(R2Args),
+    FtintrpLD(R2Args),
+    FtintrzWS(R2Args),
+    FtintrzWD(R2Args),
+    FtintrzLS(R2Args),
+    FtintrzLD(R2Args),
+    FtintrneWS(R2Args),
+    FtintrneWD(R2Args),
+    FtintrneLS(R2Args),
+    FtintrneLD(R2Args),
+    FtintWS(R2Args),
+    FtintWD(R2Args),
+    FtintLS(R2Args),
+    FtintLD(R2Args),
+    FfintSW(R2Args),
+    FfintSL(R2Args),
+    FfintDW(R2Args),
+    FfintDL(R2Args),
+
+    // Floating-point moves
+    FmovS(R2Args),
+    FmovD(R2Args),
+    Movgr2frW(R2Args),
+    Movgr2frD(R2Args),
+    Movfr2grS(R2Args),
+    Movfr2grD(R2Args),
+    Movgr2fcsr(FcsrMoveArgs),
+    Movfcsr2gr(FcsrMoveArgs),
+    Movgr2cf(CfMoveArgs),
+    Movcf2gr(CfMoveArgs),
+    Bceqz(CfBranchArgs),
+    Bcnez(CfBranchArgs),
+
+    // Floating-point memory access
+    FldS(R2I12Args),
+    FstS(R2I12Args),
+    FldD(R2I12Args),
+    FstD(R2I12Args),
+}
+
+#[derive(Default)]
+pub struct LaDecoder {}
+
+impl LaDecoder {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Decodes one LA64 instruction. Returns the decoded instruction and the
+    /// instruction length in bytes, which is always 4.
+    pub fn disas(&self, mem: &[u8]) -> Option<(LaInsn, usize)> {
+        let insn = u32::from_le_bytes(mem.get(..4)?.try_into().unwrap());
+        Some((disas_32bit(insn), 4))
+    }
+
+    pub fn disas_32bit(&self, insn: u32) -> LaInsn {
+        disas_32bit(insn)
+    }
+}
+
+fn disas_32bit(insn: u32) -> LaInsn {
+    match insn >> 26 {
+        0x00 => disas_op00(insn),
+        0x02 => disas_fmadd(insn),
+        0x03 => disas_fcmp(insn),
+        0x05 | 0x07 => disas_1ri20(insn),
+        0x08 => disas_llsc(insn),
+        0x0a => disas_mem(insn),
+        0x0e => disas_atomic(insn),
+        0x10..=0x1b => disas_branch(insn),
+        _ => LaInsn::Invalid(insn),
+    }
+}
+
+fn disas_op00(insn: u32) -> LaInsn {
+    match insn & 0xffc0_0000 {
+        0x0000_0000 => disas_3r(insn),
+        0x0040_0000 => disas_shift(insn),
+        0x0080_0000 => LaInsn::BstrinsD(bstr_d(insn)),
+        0x00c0_0000 => LaInsn::BstrpickD(bstr_d(insn)),
+        0x0100_0000 => disas_fp(insn),
+        0x0200_0000 => LaInsn::Slti(si12(insn)),
+        0x0240_0000 => LaInsn::Sltui(si12(insn)),
+        0x0280_0000 => LaInsn::AddiW(si12(insn)),
+        0x02c0_0000 => LaInsn::AddiD(si12(insn)),
+        0x0300_0000 => LaInsn::Lu52iD(si12(insn)),
+        0x0340_0000 => LaInsn::Andi(ui12(insn)),
+        0x0380_0000 => LaInsn::Ori(ui12(insn)),
+        0x03c0_0000 => LaInsn::Xori(ui12(insn)),
+        _ => LaInsn::Invalid(insn),
+    }
+}
+
+fn disas_3r(insn: u32) -> LaInsn {
+    let a = r3(insn);
+    match insn & 0xffff_8000 {
+        0x0000_0000 => disas_2r(insn),
+        0x0010_0000 => LaInsn::AddW(a),
+        0x0010_8000 => LaInsn::AddD(a),
+        0x0011_0000 => LaInsn::SubW(a),
+        0x0011_8000 => LaInsn::SubD(a),
+        0x0012_0000 => LaInsn::Slt(a),
+        0x0012_8000 => LaInsn::Sltu(a),
+        0x0013_0000 => LaInsn::Maskeqz(a),
+        0x0013_8000 => LaInsn::Masknez(a),
+        0x0014_0000 => LaInsn::Nor(a),
+        0x0014_8000 => LaInsn::And(a),
+        0x0015_0000 => LaInsn::Or(a),
+        0x0015_8000 => LaInsn::Xor(a),
+        0x0016_0000 => LaInsn::Orn(a),
+        0x0016_8000 => LaInsn::Andn(a),
+        0x0017_0000 => LaInsn::SllW(a),
+        0x0017_8000 => LaInsn::SrlW(a),
+        0x0018_0000 => LaInsn::SraW(a),
+        0x0018_8000 => LaInsn::SllD(a),
+        0x0019_0000 => LaInsn::SrlD(a),
+        0x0019_8000 => LaInsn::SraD(a),
+        0x001c_0000 => LaInsn::MulW(a),
+        0x001c_8000 => LaInsn::MulhW(a),
+        0x001d_0000 => LaInsn::MulhWu(a),
+        0x001d_8000 => LaInsn::MulD(a),
+        0x001e_0000 => LaInsn::MulhD(a),
+        0x001e_8000 => LaInsn::MulhDu(a),
+        0x0020_0000 => LaInsn::DivW(a),
+        0x0020_8000 => LaInsn::ModW(a),
+        0x0021_0000 => LaInsn::DivWu(a),
+        0x0021_8000 => LaInsn::ModWu(a),
+        0x0022_0000 => LaInsn::DivD(a),
+        0x0022_8000 => LaInsn::ModD(a),
+        0x0023_0000 => LaInsn::DivDu(a),
+        0x0023_8000 => LaInsn::ModDu(a),
+        0x002a_0000 => LaInsn::Break(code15(insn)),
+        0x002b_0000 => LaInsn::Syscall(code15(insn)),
+        _ => LaInsn::Invalid(insn),
+    }
+}
+
+fn disas_2r(insn: u32) -> LaInsn {
+    match insn & 0xffff_fc00 {
+        0x0000_6800 => LaInsn::RdtimeD(r2(insn)),
+        _ => LaInsn::Invalid(insn),
+    }
+}
+
+fn disas_shift(insn: u32) -> LaInsn {
+    match insn & 0xffff_8000 {
+        0x0040_8000 => LaInsn::SlliW(shift5(insn)),
+        0x0044_8000 => LaInsn::SrliW(shift5(insn)),
+        0x0048_8000 => LaInsn::SraiW(shift5(insn)),
+        _ => match insn & 0xffff_0000 {
+            0x0041_0000 => LaInsn::SlliD(shift6(insn)),
+            0x0045_0000 => LaInsn::SrliD(shift6(insn)),
+            0x0049_0000 => LaInsn::SraiD(shift6(insn)),
+            _ => LaInsn::Invalid(insn),
+        },
+    }
+}
+
+fn disas_fp(insn: u32) -> LaInsn {
+    let a = r3(insn);
+    match insn & 0xffff_8000 {
+        0x0100_8000 => LaInsn::FaddS(a),
+        0x0101_0000 => LaInsn::FaddD(a),
+        0x0102_8000 => LaInsn::FsubS(a),
+        0x0103_0000 => LaInsn::FsubD(a),
+        0x0104_8000 => LaInsn::FmulS(a),
+        0x0105_0000 => LaInsn::FmulD(a),
+        0x0106_8000 => LaInsn::FdivS(a),
+        0x0107_0000 => LaInsn::FdivD(a),
+        0x0108_8000 => LaInsn::FmaxS(a),
+        0x0109_0000 => LaInsn::FmaxD(a),
+        0x010a_8000 => LaInsn::FminS(a),
+        0x010b_0000 => LaInsn::FminD(a),
+        0x0112_8000 => LaInsn::FcopysignS(a),
+        0x0113_0000 => LaInsn::FcopysignD(a),
+        _ => disas_fp_2r(insn),
+    }
+}
+
+fn disas_fp_2r(insn: u32) -> LaInsn {
+    let a = r2(insn);
+    match insn & 0xffff_fc00 {
+        0x0114_0400 => LaInsn::FabsS(a),
+        0x0114_0800 => LaInsn::FabsD(a),
+        0x0114_1400 => LaInsn::FnegS(a),
+        0x0114_1800 => LaInsn::FnegD(a),
+        0x0114_3400 => LaInsn::FclassS(a),
+        0x0114_3800 => LaInsn::FclassD(a),
+        0x0114_4400 => LaInsn::FsqrtS(a),
+        0x0114_4800 => LaInsn::FsqrtD(a),
+        0x0114_9400 => LaInsn::FmovS(a),
+        0x0114_9800 => LaInsn::FmovD(a),
+        0x0114_a400 => LaInsn::Movgr2frW(a),
+        0x0114_a800 => LaInsn::Movgr2frD(a),
+        0x0114_b400 => LaInsn::Movfr2grS(a),
+        0x0114_b800 => LaInsn::Movfr2grD(a),
+        0x0114_c000 => LaInsn::Movgr2fcsr(FcsrMoveArgs {
+            fcsr: a.rd,
+            gr: a.rj,
+        }),
+        0x0114_c800 => LaInsn::Movfcsr2gr(FcsrMoveArgs {
+            fcsr: a.rj,
+            gr: a.rd,
+        }),
+        // the upper bits of the condition flag number must be zero
+        0x0114_d800 if a.rd < 8 => LaInsn::Movgr2cf(CfMoveArgs { cf: a.rd, gr: a.rj }),
+        0x0114_dc00 if a.rj < 8 => LaInsn::Movcf2gr(CfMoveArgs { cf: a.rj, gr: a.rd }),
+        0x0119_1800 => LaInsn::FcvtSD(a),
+        0x0119_2400 => LaInsn::FcvtDS(a),
+        0x011a_0400 => LaInsn::FtintrmWS(a),
+        0x011a_0800 => LaInsn::FtintrmWD(a),
+        0x011a_2400 => LaInsn::FtintrmLS(a),
+        0x011a_2800 => LaInsn::FtintrmLD(a),
+        0x011a_4400 => LaInsn::FtintrpWS(a),
+        0x011a_4800 => LaInsn::FtintrpWD(a),
+        0x011a_6400 => LaInsn::FtintrpLS(a),
+        0x011a_6800 => LaInsn::FtintrpLD(a),
+        0x011a_8400 => LaInsn::FtintrzWS(a),
+        0x011a_8800 => LaInsn::FtintrzWD(a),
+        0x011a_a400 => LaInsn::FtintrzLS(a),
+        0x011a_a800 => LaInsn::FtintrzLD(a),
+        0x011a_c400 => LaInsn::FtintrneWS(a),
+        0x011a_c800 => LaInsn::FtintrneWD(a),
+        0x011a_e400 => LaInsn::FtintrneLS(a),
+        0x011a_e800 => LaInsn::FtintrneLD(a),
+        0x011b_0400 => LaInsn::FtintWS(a),
+        0x011b_0800 => LaInsn::FtintWD(a),
+        0x011b_2400 => LaInsn::FtintLS(a),
+        0x011b_2800 => LaInsn::FtintLD(a),
+        0x011d_1000 => LaInsn::FfintSW(a),
+        0x011d_1800 => LaInsn::FfintSL(a),
+        0x011d_2000 => LaInsn::FfintDW(a),
+        0x011d_2800 => LaInsn::FfintDL(a),
+        _ => LaInsn::Invalid(insn),
+    }
+}
+
+fn disas_fmadd(insn: u32) -> LaInsn {
+    let a = r4(insn);
+    match insn & 0xfff0_0000 {
+        0x0810_0000 => LaInsn::FmaddS(a),
+        0x0820_0000 => LaInsn::FmaddD(a),
+        0x0850_0000 => LaInsn::FmsubS(a),
+        0x0860_0000 => LaInsn::FmsubD(a),
+        0x0890_0000 => LaInsn::FnmaddS(a),
+        0x08a0_0000 => LaInsn::FnmaddD(a),
+        0x08d0_0000 => LaInsn::FnmsubS(a),
+        0x08e0_0000 => LaInsn::FnmsubD(a),
+        _ => LaInsn::Invalid(insn),
+    }
+}
+
+fn disas_fcmp(insn: u32) -> LaInsn {
+    match insn & 0xfff0_0018 {
+        0x0c10_0000 => LaInsn::FcmpS(fcmp(insn)),
+        0x0c20_0000 => LaInsn::FcmpD(fcmp(insn)),
+        _ => LaInsn::Invalid(insn),
+    }
+}
+
+fn disas_1ri20(insn: u32) -> LaInsn {
+    match insn & 0xfe00_0000 {
+        0x1400_0000 => LaInsn::Lu12iW(si20(insn)),
+        0x1600_0000 => LaInsn::Lu32iD(si20(insn)),
+        0x1c00_0000 => LaInsn::Pcaddu12i(si20(insn)),
+        _ => LaInsn::Invalid(insn),
+    }
+}
+
+fn disas_llsc(insn: u32) -> LaInsn {
+    match insn & 0xff00_0000 {
+        0x2000_0000 => LaInsn::LlW(si14(insn)),
+        0x2100_0000 => LaInsn::ScW(si14(insn)),
+        0x2200_0000 => LaInsn::LlD(si14(insn)),
+        0x2300_0000 => LaInsn::ScD(si14(insn)),
+        _ => LaInsn::Invalid(insn),
+    }
+}
+
+fn disas_mem(insn: u32) -> LaInsn {
+    let a = si12(insn);
+    match insn & 0xffc0_0000 {
+        0x2800_0000 => LaInsn::LdB(a),
+        0x2840_0000 => LaInsn::LdH(a),
+        0x2880_0000 => LaInsn::LdW(a),
+        0x28c0_0000 => LaInsn::LdD(a),
+        0x2900_0000 => LaInsn::StB(a),
+        0x2940_0000 => LaInsn::StH(a),
+        0x2980_0000 => LaInsn::StW(a),
+        0x29c0_0000 => LaInsn::StD(a),
+        0x2a00_0000 => LaInsn::LdBu(a),
+        0x2a40_0000 => LaInsn::LdHu(a),
+        0x2a80_0000 => LaInsn::LdWu(a),
+        0x2b00_0000 => LaInsn::FldS(a),
+        0x2b40_0000 => LaInsn::FstS(a),
+        0x2b80_0000 => LaInsn::FldD(a),
+        0x2bc0_0000 => LaInsn::FstD(a),
+        _ => LaInsn::Invalid(insn),
+    }
+}
+
+fn disas_atomic(insn: u32) -> LaInsn {
+    let a = r3(insn);
+    match insn & 0xffff_8000 {
+        0x3860_0000 => LaInsn::AmswapW(a),
+        0x3860_8000 => LaInsn::AmswapD(a),
+        0x3861_0000 => LaInsn::AmaddW(a),
+        0x3861_8000 => LaInsn::AmaddD(a),
+        0x3862_0000 => LaInsn::AmandW(a),
+        0x3862_8000 => LaInsn::AmandD(a),
+        0x3863_0000 => LaInsn::AmorW(a),
+        0x3863_8000 => LaInsn::AmorD(a),
+        0x3864_0000 => LaInsn::AmxorW(a),
+        0x3864_8000 => LaInsn::AmxorD(a),
+        0x3865_0000 => LaInsn::AmmaxW(a),
+        0x3865_8000 => LaInsn::AmmaxD(a),
+        0x3866_0000 => LaInsn::AmminW(a),
+        0x3866_8000 => LaInsn::AmminD(a),
+        0x3867_0000 => LaInsn::AmmaxWu(a),
+        0x3867_8000 => LaInsn::AmmaxDu(a),
+        0x3868_0000 => LaInsn::AmminWu(a),
+        0x3868_8000 => LaInsn::AmminDu(a),
+        0x3872_0000 => LaInsn::Dbar(code15(insn)),
+        0x3872_8000 => LaInsn::Ibar(code15(insn)),
+        _ => LaInsn::Invalid(insn),
+    }
+}
+
+fn disas_branch(insn: u32) -> LaInsn {
+    match insn & 0xfc00_0000 {
+        0x4000_0000 => LaInsn::Beqz(si21(insn)),
+        0x4400_0000 => LaInsn::Bnez(si21(insn)),
+        0x4800_0000 => match insn & 0x0000_0300 {
+            0x0000_0000 => LaInsn::Bceqz(cf_branch(insn)),
+            0x0000_0100 => LaInsn::Bcnez(cf_branch(insn)),
+            _ => LaInsn::Invalid(insn),
+        },
+        0x4c00_0000 => LaInsn::Jirl(si16(insn)),
+        0x5000_0000 => LaInsn::B(si26(insn)),
+        0x5400_0000 => LaInsn::Bl(si26(insn)),
+        0x5800_0000 => LaInsn::Beq(si16(insn)),
+        0x5c00_0000 => LaInsn::Bne(si16(insn)),
+        0x6000_0000 => LaInsn::Blt(si16(insn)),
+        0x6400_0000 => LaInsn::Bge(si16(insn)),
+        0x6800_0000 => LaInsn::Bltu(si16(insn)),
+        0x6c00_0000 => LaInsn::Bgeu(si16(insn)),
+        _ => LaInsn::Invalid(insn),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disas() {
+        // addi.d $sp,$sp,-16
+        assert!(matches!(
+            disas_32bit(0x02ffc063),
+            LaInsn::AddiD(R2I12Args {
+                rd: 3,
+                rj: 3,
+                imm: -16
+            })
+        ));
+
+        // ori $a0,$zero,0xfff is zero-extended
+        assert!(matches!(
+            disas_32bit(0x03bffc04),
+            LaInsn::Ori(R2I12Args {
+                rd: 4,
+                rj: 0,
+                imm: 0xfff
+            })
+        ));
+
+        // sc.d $a2,$a1,-4
+        assert!(matches!(
+            disas_32bit(0x23fffca6),
+            LaInsn::ScD(R2I14Args {
+                rd: 6,
+                rj: 5,
+                imm: -4
+            })
+        ));
+
+        // bnez $a0,-4
+        assert!(matches!(
+            disas_32bit(0x47fffc9f),
+            LaInsn::Bnez(R1I21Args { rj: 4, imm: -4 })
+        ));
+
+        // b -4
+        assert!(matches!(disas_32bit(0x53ffffff), LaInsn::B(-4)));
+
+        // bcnez $fcc0,-4 has ones in the low offset bits
+        assert!(matches!(
+            disas_32bit(0x4bfffd1f),
+            LaInsn::Bcnez(CfBranchArgs { cj: 0, imm: -4 })
+        ));
+
+        // fcmp.sle.d $fcc2,$fa0,$fa1
+        assert!(matches!(
+            disas_32bit(0x0c238402),
+            LaInsn::FcmpD(FcmpArgs {
+                cond: FcmpCond::Sle,
+                cd: 2,
+                fj: 0,
+                fk: 1
+            })
+        ));
+
+        // fcmp with the upper bits of cd set
+        assert!(matches!(disas_32bit(0x0c238418), LaInsn::Invalid(_)));
+        // rdtime.d $a0,$a1
+        assert!(matches!(
+            disas_32bit(0x000068a4),
+            LaInsn::RdtimeD(R2Args { rd: 4, rj: 5 })
+        ));
+        // rdtimel.w and rdtimeh.w are not supported
+        assert!(matches!(disas_32bit(0x000060a4), LaInsn::Invalid(_)));
+        assert!(matches!(disas_32bit(0x000064a4), LaInsn::Invalid(_)));
+
+        // pcaddu18i is not supported
+        assert!(matches!(disas_32bit(0x1e00000f), LaInsn::Invalid(_)));
+        assert!(matches!(disas_32bit(0x00000000), LaInsn::Invalid(_)));
+        assert!(matches!(disas_32bit(0xffffffff), LaInsn::Invalid(_)));
+
+        let d = LaDecoder::new();
+        assert!(matches!(
+            d.disas(&[0x20, 0x00, 0x00, 0x4c, 0xff]),
+            Some((LaInsn::Jirl(_), 4))
+        ));
+        assert!(d.disas(&[0x20, 0x00, 0x00]).is_none());
+    }
+
+    #[test]
+    fn test_disas_roundtrip() {
+        #[rustfmt::skip]
+        let words = [
+            0x4c000020, 0x001500a4, 0x03400000, 0x02ffc063, 0x29c02061, 0x28c02061,
+            0x03bffc04, 0x1c00000f, 0x15ffffe4, 0x4c0001ed, 0x001098a4, 0x001e98a4,
+            0x002118a4, 0x00418084, 0x00488ca4, 0x00df0084, 0x200004a4, 0x23fffca6,
+            0x386014c4, 0x386794c4, 0x58001085, 0x63fff8a4, 0x40000880, 0x47fffc9f,
+            0x53ffffff, 0x54000800, 0x002b0000, 0x38720000, 0x38728000, 0x01010400,
+            0x08218820, 0x0c210400, 0x0c120c41, 0x011aa820, 0x0114a880, 0x0114b404,
+            0x0114c080, 0x0114dc24, 0x48000900, 0x2b802060, 0x4bfffd1f, 0x0c238402,
+            0x00006804,
+        ];
+
+        for word in words {
+            let insn = disas_32bit(word);
+            assert!(!matches!(insn, LaInsn::Invalid(_)), "{:08x}", word);
+            assert_eq!(insn.encode(), word, "{:?}", insn);
+        }
+    }
+}
@@ -0,0 +1,7 @@
+mod args;
+mod disas_helper;
+mod encode;
+mod insn;
+
+pub use args::*;
+pub use insn::{LaDecoder, LaInsn};
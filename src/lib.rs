@@ -1,2 +1,4 @@
 pub mod exec;
+pub mod la;
 pub mod rv;
+pub mod translate;
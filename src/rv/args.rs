@@ -1,32 +1,32 @@
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct RTypeArgs {
     pub rd: u8,
     pub rs1: u8,
     pub rs2: u8,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct ITypeArgs {
     pub rd: u8,
     pub rs1: u8,
     pub imm: i32,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct SBTypeArgs {
     pub rs1: u8,
     pub rs2: u8,
     pub imm: i32,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct UJTypeArgs {
     pub rd: u8,
     pub imm: i32,
 }
 
 // variant of RTypeArgs
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct ShiftArgs {
     pub rd: u8,
     pub rs1: u8,
@@ -34,7 +34,7 @@ pub struct ShiftArgs {
 }
 
 // variant of RTypeArgs
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct AmoArgs {
     pub aq: bool,
     pub rl: bool,
@@ -44,7 +44,7 @@ pub struct AmoArgs {
 }
 
 // variant of RTypeArgs
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct AmoLrArgs {
     pub aq: bool,
     pub rl: bool,
@@ -52,7 +52,7 @@ pub struct AmoLrArgs {
     pub rs1: u8,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct FenceSet {
     pub i: bool,
     pub o: bool,
@@ -72,14 +72,14 @@ impl From<u8> for FenceSet {
 }
 
 // variant of ITypeArgs
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct FenceArgs {
     pub fm: u8,
     pub pred: FenceSet,
     pub succ: FenceSet,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum RoundingMode {
     Rne,
     Rtz,
@@ -104,7 +104,7 @@ impl From<u8> for RoundingMode {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct R4TypeArgs {
     pub rm: RoundingMode,
     pub rd: u8,
@@ -114,7 +114,7 @@ pub struct R4TypeArgs {
 }
 
 // variant of RTypeArgs
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct RFTypeArgs {
     pub rm: RoundingMode,
     pub rd: u8,
@@ -123,16 +123,58 @@ pub struct RFTypeArgs {
 }
 
 // variant of RTypeArgs
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct R2TypeArgs {
     pub rd: u8,
     pub rs1: u8,
 }
 
 // variant of RTypeArgs
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct R2FTypeArgs {
     pub rm: RoundingMode,
     pub rd: u8,
     pub rs1: u8,
 }
+
+/// CSR numbers known to the user-mode emulator.
+#[derive(Clone, Copy, Debug)]
+pub enum Csr {
+    Fflags,
+    Frm,
+    Fcsr,
+    Cycle,
+    Time,
+    Instret,
+    Other(u16),
+}
+
+impl From<u16> for Csr {
+    fn from(x: u16) -> Self {
+        match x {
+            0x001 => Self::Fflags,
+            0x002 => Self::Frm,
+            0x003 => Self::Fcsr,
+            0xc00 => Self::Cycle,
+            0xc01 => Self::Time,
+            0xc02 => Self::Instret,
+            _ => Self::Other(x),
+        }
+    }
+}
+
+// variant of ITypeArgs
+#[derive(Clone, Copy, Debug)]
+pub struct CsrArgs {
+    pub csr: Csr,
+    pub rd: u8,
+    pub rs1: u8,
+}
+
+// variant of ITypeArgs
+#[derive(Clone, Copy, Debug)]
+pub struct CsrImmArgs {
+    pub csr: Csr,
+    pub rd: u8,
+    pub uimm: u8,
+}
@@ -121,6 +121,10 @@ impl ISBTypeSlots {
     pub(super) fn fence_succ(&self) -> FenceSet {
         ((self.0 & 0b1111) as u8).into()
     }
+
+    pub(super) fn csr(&self) -> Csr {
+        ((self.0 & 0xfff) as u16).into()
+    }
 }
 
 impl From<ISBTypeSlots> for ITypeArgs {
@@ -163,6 +167,26 @@ impl From<ISBTypeSlots> for FenceArgs {
     }
 }
 
+impl From<ISBTypeSlots> for CsrArgs {
+    fn from(x: ISBTypeSlots) -> Self {
+        Self {
+            csr: x.csr(),
+            rd: x.3,
+            rs1: x.1,
+        }
+    }
+}
+
+impl From<ISBTypeSlots> for CsrImmArgs {
+    fn from(x: ISBTypeSlots) -> Self {
+        Self {
+            csr: x.csr(),
+            rd: x.3,
+            uimm: x.1,
+        }
+    }
+}
+
 // U-type & J-type: imm, rd
 pub(super) struct UJTypeSlots(i32, u8);
 
@@ -226,7 +250,7 @@ pub(super) fn disas_i(insn: u32) -> ISBTypeSlots {
 }
 
 pub(super) fn disas_s(insn: u32) -> ISBTypeSlots {
-    let imm = (insn >> (25 - 5)) | ((insn >> 7) & 0b11111);
+    let imm = ((insn >> 25) << 5) | ((insn >> 7) & 0b11111);
     let imm = simm_from_uimm(imm, 12);
     let rs2 = ((insn >> 20) & 0b11111) as u8;
     let rs1 = ((insn >> 15) & 0b11111) as u8;
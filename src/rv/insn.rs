@@ -2,7 +2,7 @@ use super::args::*;
 use super::disas_helper::*;
 use super::rvc::RvCDecoder;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum RvInsn {
     // Invalid encoding
     Invalid(u32),
@@ -54,6 +54,14 @@ pub enum RvInsn {
     // Zifencei
     FenceI(ITypeArgs),
 
+    // Zicsr
+    Csrrw(CsrArgs),
+    Csrrs(CsrArgs),
+    Csrrc(CsrArgs),
+    Csrrwi(CsrImmArgs),
+    Csrrsi(CsrImmArgs),
+    Csrrci(CsrImmArgs),
+
     // RV64I
     Lwu(ITypeArgs),
     Ld(ITypeArgs),
@@ -556,9 +564,105 @@ fn disas_jalr(insn: u32) -> RvInsn {
 }
 
 fn disas_system(insn: u32) -> RvInsn {
-    match insn {
-        0x00000073 => RvInsn::Ecall,
-        0x00100073 => RvInsn::Ebreak,
+    let s = disas_i(insn);
+    match s.i_funct3() {
+        0b000 => match insn {
+            0x00000073 => RvInsn::Ecall,
+            0x00100073 => RvInsn::Ebreak,
+            _ => RvInsn::Invalid(insn),
+        },
+
+        // Zicsr
+        0b001 => RvInsn::Csrrw(s.into()),
+        0b010 => RvInsn::Csrrs(s.into()),
+        0b011 => RvInsn::Csrrc(s.into()),
+        0b101 => RvInsn::Csrrwi(s.into()),
+        0b110 => RvInsn::Csrrsi(s.into()),
+        0b111 => RvInsn::Csrrci(s.into()),
+
         _ => RvInsn::Invalid(insn),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_imm() {
+        // sd t0,176(a2)
+        assert!(matches!(
+            disas_32bit(0x0a563823),
+            RvInsn::Sd(SBTypeArgs {
+                rs1: 12,
+                rs2: 5,
+                imm: 176
+            })
+        ));
+
+        // sw a0,-4(sp)
+        assert!(matches!(
+            disas_32bit(0xfea12e23),
+            RvInsn::Sw(SBTypeArgs {
+                rs1: 2,
+                rs2: 10,
+                imm: -4
+            })
+        ));
+    }
+
+    #[test]
+    fn test_zicsr() {
+        // frcsr a0
+        assert!(matches!(
+            disas_32bit(0x00302573),
+            RvInsn::Csrrs(CsrArgs {
+                csr: Csr::Fcsr,
+                rd: 10,
+                rs1: 0
+            })
+        ));
+
+        // fsrm a1
+        assert!(matches!(
+            disas_32bit(0x00259073),
+            RvInsn::Csrrw(CsrArgs {
+                csr: Csr::Frm,
+                rd: 0,
+                rs1: 11
+            })
+        ));
+
+        // csrrwi a0,fflags,5
+        assert!(matches!(
+            disas_32bit(0x0012d573),
+            RvInsn::Csrrwi(CsrImmArgs {
+                csr: Csr::Fflags,
+                rd: 10,
+                uimm: 5
+            })
+        ));
+
+        // rdcycle a0
+        assert!(matches!(
+            disas_32bit(0xc0002573),
+            RvInsn::Csrrs(CsrArgs {
+                csr: Csr::Cycle,
+                rd: 10,
+                rs1: 0
+            })
+        ));
+
+        // csrr a0,mstatus
+        assert!(matches!(
+            disas_32bit(0x30002573),
+            RvInsn::Csrrs(CsrArgs {
+                csr: Csr::Other(0x300),
+                ..
+            })
+        ));
+
+        // funct3 = 0b100 is reserved
+        assert!(matches!(disas_32bit(0x00004073), RvInsn::Invalid(_)));
+    }
+}
@@ -0,0 +1,120 @@
+use crate::la::LaInsn;
+
+/// A position in a `CodeBuf` that branches can target before it is known.
+#[derive(Clone, Copy, Debug)]
+pub struct Label(usize);
+
+/// Addresses outside the buffer that its code refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelocTarget {
+    /// The runtime routine that exit stubs leave through.
+    Exit,
+}
+
+/// A `b` instruction to be pointed at its target once the code is placed.
+#[derive(Clone, Copy, Debug)]
+pub struct Reloc {
+    /// Byte offset of the instruction in the buffer.
+    pub offset: usize,
+    pub target: RelocTarget,
+}
+
+struct Fixup {
+    index: usize,
+    label: Label,
+    make: Box<dyn Fn(i32) -> LaInsn>,
+}
+
+/// LA64 code being generated, with branches to labels inside the buffer and
+/// relocations for those leaving it.
+#[derive(Default)]
+pub struct CodeBuf {
+    words: Vec<u32>,
+    labels: Vec<Option<usize>>,
+    fixups: Vec<Fixup>,
+    relocs: Vec<Reloc>,
+}
+
+impl CodeBuf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Byte offset of the next instruction.
+    pub fn offset(&self) -> usize {
+        self.words.len() * 4
+    }
+
+    pub fn emit(&mut self, insn: LaInsn) {
+        self.words.push(insn.encode());
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    pub fn bind(&mut self, label: Label) {
+        debug_assert!(self.labels[label.0].is_none(), "label bound twice");
+        self.labels[label.0] = Some(self.words.len());
+        let pos = self.words.len();
+        for f in self.fixups.iter().filter(|f| f.label.0 == label.0) {
+            self.words[f.index] = (f.make)((pos as i32 - f.index as i32) * 4).encode();
+        }
+        self.fixups.retain(|f| f.label.0 != label.0);
+    }
+
+    /// Emits a branch to `label`, built by `make` from the byte offset.
+    pub fn emit_branch(&mut self, label: Label, make: impl Fn(i32) -> LaInsn + 'static) {
+        let index = self.words.len();
+        match self.labels[label.0] {
+            Some(pos) => self
+                .words
+                .push(make((pos as i32 - index as i32) * 4).encode()),
+            None => {
+                self.words.push(make(0).encode());
+                self.fixups.push(Fixup {
+                    index,
+                    label,
+                    make: Box::new(make),
+                });
+            }
+        }
+    }
+
+    /// Emits a `b` to somewhere outside the buffer.
+    pub fn emit_reloc(&mut self, target: RelocTarget) {
+        self.relocs.push(Reloc {
+            offset: self.offset(),
+            target,
+        });
+        self.emit(LaInsn::B(0));
+    }
+
+    /// The instruction words, with relocations still unresolved.
+    pub fn words(&self) -> &[u32] {
+        debug_assert!(self.fixups.is_empty(), "unbound labels");
+        &self.words
+    }
+
+    pub fn relocs(&self) -> &[Reloc] {
+        &self.relocs
+    }
+
+    /// Resolves the relocations for the code placed at `base`. Returns `None`
+    /// if a target is out of the 128 MiB reach of `b`.
+    pub fn link(&self, base: u64, exit: u64) -> Option<Vec<u32>> {
+        let mut words = self.words().to_vec();
+        for r in &self.relocs {
+            let target = match r.target {
+                RelocTarget::Exit => exit,
+            };
+            let offs = target.wrapping_sub(base + r.offset as u64) as i64;
+            if !(-(1 << 27)..1 << 27).contains(&offs) || offs & 3 != 0 {
+                return None;
+            }
+            words[r.offset / 4] = LaInsn::B(offs as i32).encode();
+        }
+        Some(words)
+    }
+}
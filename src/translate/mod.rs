@@ -0,0 +1,873 @@
+//! One-pass translation of guest basic blocks to LA64 code.
+//!
+//! Guest registers x1 to x26 live in the host registers of the same number.
+//! The rest of the host registers are taken by the translated code itself,
+//! and x27 to x31 are kept in the guest register file instead:
+//!
+//! * r27 holds the guest base, which is added to every guest address
+//! * r28 points at the guest register file, a `[u64; 32]`
+//! * r29 to r31 are scratch
+//!
+//! As the numbers match, guest sp, gp and tp (x2 to x4) sit in the host's
+//! $tp, $sp and $a0, so the host thread and stack pointers are gone while
+//! translated code runs. The runtime routine that enters it must save them,
+//! and the one the exit stubs branch to must restore them before any host
+//! code runs. Host signals have to be blocked meanwhile, or handled on a
+//! sigaltstack by handlers that restore $tp before anything else.
+//!
+//! A block leaves through one of its exit stubs with the next guest pc in r29
+//! and the index of the exit in r30, branching to a runtime routine that is
+//! linked in when the code is placed. What the translator leaves to the
+//! interpreter (system calls, CSRs, FP, `fence.i`) also ends the block.
+
+mod codebuf;
+
+pub use codebuf::{CodeBuf, Label, Reloc, RelocTarget};
+
+use crate::la::*;
+use crate::rv::{self, RvInsn};
+
+const ZERO: u8 = 0;
+const FIRST_SPILLED: u8 = 27;
+const GBASE: u8 = 27;
+const CTX: u8 = 28;
+const T0: u8 = 29;
+const T1: u8 = 30;
+const T2: u8 = 31;
+
+/// How a block is left.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitKind {
+    /// Continue at a guest pc known at translation time.
+    Jump(u64),
+    /// Continue at a guest pc computed by the block.
+    Indirect,
+    /// Interpret the instruction at this pc, then continue after it.
+    Interp(u64),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Exit {
+    pub kind: ExitKind,
+    /// Byte offset of the exit stub in the code.
+    pub offset: usize,
+}
+
+pub struct TranslatedBlock {
+    pub code: CodeBuf,
+    /// Indexed by the number the stubs pass in r30.
+    pub exits: Vec<Exit>,
+}
+
+type R3Op = fn(R3Args) -> LaInsn;
+type R2I12Op = fn(R2I12Args) -> LaInsn;
+type ShiftOp = fn(ShiftArgs) -> LaInsn;
+
+struct Translator {
+    buf: CodeBuf,
+    // exits taken from the middle of the block, by branches to their labels
+    side_exits: Vec<(Label, ExitKind)>,
+}
+
+/// Translates the block of `insns` starting at guest `pc`, each with its
+/// length in bytes. Instructions after the first one ending the block are
+/// ignored.
+pub fn translate_block(pc: u64, insns: &[(RvInsn, usize)]) -> TranslatedBlock {
+    let mut t = Translator {
+        buf: CodeBuf::new(),
+        side_exits: Vec::new(),
+    };
+    let mut pc = pc;
+    let mut last = None;
+    for &(insn, len) in insns {
+        last = t.insn(&insn, pc, len as u64);
+        pc += len as u64;
+        if last.is_some() {
+            break;
+        }
+    }
+    t.finish(last.unwrap_or(ExitKind::Jump(pc)))
+}
+
+impl Translator {
+    fn emit(&mut self, insn: LaInsn) {
+        self.buf.emit(insn);
+    }
+
+    // host register holding guest x, loading it into tmp if it is spilled
+    fn src(&mut self, x: u8, tmp: u8) -> u8 {
+        if x < FIRST_SPILLED {
+            return x;
+        }
+        self.emit(LaInsn::LdD(R2I12Args {
+            rd: tmp,
+            rj: CTX,
+            imm: x as i32 * 8,
+        }));
+        tmp
+    }
+
+    // host register to compute guest x into, followed by `commit`
+    fn dst(&self, x: u8) -> u8 {
+        if x < FIRST_SPILLED {
+            x
+        } else {
+            T0
+        }
+    }
+
+    fn commit(&mut self, x: u8, r: u8) {
+        if x >= FIRST_SPILLED {
+            self.emit(LaInsn::StD(R2I12Args {
+                rd: r,
+                rj: CTX,
+                imm: x as i32 * 8,
+            }));
+        } else if x != r {
+            self.mov(x, r);
+        }
+    }
+
+    fn mov(&mut self, rd: u8, rj: u8) {
+        self.emit(LaInsn::Or(R3Args { rd, rj, rk: ZERO }));
+    }
+
+    // Loads the value with addi.d or ori, or lu12i.w and ori, for the low 32
+    // bits, then lu32i.d and lu52i.d for the rest, skipping each step that
+    // sign extension already takes care of. A value with only its top 12
+    // bits set is a single lu52i.d.
+    fn li(&mut self, rd: u8, v: i64) {
+        let sext = |x: i64, bits: u32| x << (64 - bits) >> (64 - bits);
+        let lo = sext(v, 32);
+        let mid = sext(v, 52);
+
+        if mid == 0 && v != 0 {
+            self.emit(LaInsn::Lu52iD(R2I12Args {
+                rd,
+                rj: ZERO,
+                imm: sext(v >> 52, 12) as i32,
+            }));
+            return;
+        }
+
+        if (-2048..2048).contains(&lo) {
+            self.emit(LaInsn::AddiD(R2I12Args {
+                rd,
+                rj: ZERO,
+                imm: lo as i32,
+            }));
+        } else if (0..4096).contains(&lo) {
+            self.emit(LaInsn::Ori(R2I12Args {
+                rd,
+                rj: ZERO,
+                imm: lo as i32,
+            }));
+        } else {
+            self.emit(LaInsn::Lu12iW(R1I20Args {
+                rd,
+                imm: sext(lo >> 12, 20) as i32,
+            }));
+            if lo & 0xfff != 0 {
+                self.emit(LaInsn::Ori(R2I12Args {
+                    rd,
+                    rj: rd,
+                    imm: (lo & 0xfff) as i32,
+                }));
+            }
+        }
+        if mid != lo {
+            self.emit(LaInsn::Lu32iD(R1I20Args {
+                rd,
+                imm: sext(v >> 32, 20) as i32,
+            }));
+        }
+        if v != mid {
+            self.emit(LaInsn::Lu52iD(R2I12Args {
+                rd,
+                rj: rd,
+                imm: sext(v >> 52, 12) as i32,
+            }));
+        }
+    }
+
+    fn set_const(&mut self, x: u8, v: u64) {
+        if x == 0 {
+            return;
+        }
+        let rd = self.dst(x);
+        self.li(rd, v as i64);
+        self.commit(x, rd);
+    }
+
+    fn r3(&mut self, a: rv::RTypeArgs, op: R3Op) {
+        let rj = self.src(a.rs1, T0);
+        let rk = self.src(a.rs2, T1);
+        let rd = self.dst(a.rd);
+        self.emit(op(R3Args { rd, rj, rk }));
+        self.commit(a.rd, rd);
+    }
+
+    fn r2i12(&mut self, a: rv::ITypeArgs, op: R2I12Op) {
+        let rj = self.src(a.rs1, T0);
+        let rd = self.dst(a.rd);
+        self.emit(op(R2I12Args { rd, rj, imm: a.imm }));
+        self.commit(a.rd, rd);
+    }
+
+    // the LA64 immediate is zero-extended, so negative ones need a register
+    fn logic_imm(&mut self, a: rv::ITypeArgs, op: R2I12Op, reg_op: R3Op) {
+        if a.imm >= 0 {
+            return self.r2i12(a, op);
+        }
+        let rj = self.src(a.rs1, T0);
+        self.li(T1, a.imm as i64);
+        let rd = self.dst(a.rd);
+        self.emit(reg_op(R3Args { rd, rj, rk: T1 }));
+        self.commit(a.rd, rd);
+    }
+
+    fn shift(&mut self, a: rv::ShiftArgs, op: ShiftOp) {
+        let rj = self.src(a.rs1, T0);
+        let rd = self.dst(a.rd);
+        self.emit(op(ShiftArgs {
+            rd,
+            rj,
+            shamt: a.shamt,
+        }));
+        self.commit(a.rd, rd);
+    }
+
+    // host address of guest x + 0, in T2
+    fn guest_addr(&mut self, x: u8) -> u8 {
+        let rj = self.src(x, T0);
+        self.emit(LaInsn::AddD(R3Args {
+            rd: T2,
+            rj,
+            rk: GBASE,
+        }));
+        T2
+    }
+
+    fn load(&mut self, a: rv::ITypeArgs, op: R2I12Op) {
+        let rj = self.guest_addr(a.rs1);
+        let rd = self.dst(a.rd);
+        self.emit(op(R2I12Args { rd, rj, imm: a.imm }));
+        self.commit(a.rd, rd);
+    }
+
+    fn store(&mut self, a: rv::SBTypeArgs, op: R2I12Op) {
+        let rj = self.guest_addr(a.rs1);
+        let rd = self.src(a.rs2, T1);
+        self.emit(op(R2I12Args { rd, rj, imm: a.imm }));
+    }
+
+    // multiplies signed rs1 by unsigned rs2: the unsigned high half, less rs2
+    // if rs1 is negative
+    fn mulhsu(&mut self, a: rv::RTypeArgs) {
+        let rj = self.src(a.rs1, T0);
+        let rk = self.src(a.rs2, T1);
+        self.emit(LaInsn::MulhDu(R3Args { rd: T2, rj, rk }));
+        self.emit(LaInsn::SraiD(ShiftArgs {
+            rd: T0,
+            rj,
+            shamt: 63,
+        }));
+        self.emit(LaInsn::And(R3Args { rd: T0, rj: T0, rk }));
+        let rd = self.dst(a.rd);
+        self.emit(LaInsn::SubD(R3Args { rd, rj: T2, rk: T0 }));
+        self.commit(a.rd, rd);
+    }
+
+    // LA64 leaves division by zero and overflow undefined, and the 32-bit
+    // forms need sign-extended operands, so both cases get their own paths
+    fn divrem(&mut self, a: rv::RTypeArgs, op: R3Op, signed: bool, rem: bool, word: bool) {
+        let mut rj = self.src(a.rs1, T0);
+        let mut rk = self.src(a.rs2, T1);
+        if word {
+            for (r, tmp) in [(&mut rj, T0), (&mut rk, T1)] {
+                self.emit(LaInsn::AddiW(R2I12Args {
+                    rd: tmp,
+                    rj: *r,
+                    imm: 0,
+                }));
+                *r = tmp;
+            }
+        }
+        let rd = self.dst(a.rd);
+        let zero = self.buf.new_label();
+        let end = self.buf.new_label();
+
+        self.buf
+            .emit_branch(zero, move |imm| LaInsn::Beqz(R1I21Args { rj: rk, imm }));
+        if signed {
+            // dividing by -1 is negating, which wraps on overflow
+            let neg = self.buf.new_label();
+            self.li(T2, -1);
+            self.buf.emit_branch(neg, move |imm| {
+                LaInsn::Beq(R2I16Args {
+                    rd: T2,
+                    rj: rk,
+                    imm,
+                })
+            });
+            self.emit(op(R3Args { rd, rj, rk }));
+            self.buf.emit_branch(end, LaInsn::B);
+            self.buf.bind(neg);
+            if rem {
+                self.mov(rd, ZERO);
+            } else if word {
+                self.emit(LaInsn::SubW(R3Args {
+                    rd,
+                    rj: ZERO,
+                    rk: rj,
+                }));
+            } else {
+                self.emit(LaInsn::SubD(R3Args {
+                    rd,
+                    rj: ZERO,
+                    rk: rj,
+                }));
+            }
+        } else {
+            self.emit(op(R3Args { rd, rj, rk }));
+        }
+        self.buf.emit_branch(end, LaInsn::B);
+
+        self.buf.bind(zero);
+        if rem {
+            self.mov(rd, rj);
+        } else {
+            self.li(rd, -1);
+        }
+        self.buf.bind(end);
+        self.commit(a.rd, rd);
+    }
+
+    fn amo(&mut self, a: rv::AmoArgs, op: R3Op) {
+        if a.rl {
+            self.emit(LaInsn::Dbar(0));
+        }
+        let rj = self.guest_addr(a.rs1);
+        let rk = self.src(a.rs2, T1);
+        // rd may be neither of the other operands
+        let rd = if a.rd == 0 || a.rd == a.rs2 {
+            T0
+        } else {
+            self.dst(a.rd)
+        };
+        self.emit(op(R3Args { rd, rj, rk }));
+        if a.rd != 0 {
+            self.commit(a.rd, rd);
+        }
+        if a.aq {
+            self.emit(LaInsn::Dbar(0));
+        }
+    }
+
+    fn lr(&mut self, a: rv::AmoLrArgs, op: fn(R2I14Args) -> LaInsn) {
+        if a.rl {
+            self.emit(LaInsn::Dbar(0));
+        }
+        let rj = self.guest_addr(a.rs1);
+        let rd = self.dst(a.rd);
+        self.emit(op(R2I14Args { rd, rj, imm: 0 }));
+        self.commit(a.rd, rd);
+        if a.aq {
+            self.emit(LaInsn::Dbar(0));
+        }
+    }
+
+    // sc.{w,d} overwrites the value with 1 on success, where RV wants 0
+    fn sc(&mut self, a: rv::AmoArgs, op: fn(R2I14Args) -> LaInsn) {
+        if a.rl {
+            self.emit(LaInsn::Dbar(0));
+        }
+        let rj = self.guest_addr(a.rs1);
+        let rs2 = self.src(a.rs2, T1);
+        if rs2 != T1 {
+            self.mov(T1, rs2);
+        }
+        self.emit(op(R2I14Args { rd: T1, rj, imm: 0 }));
+        if a.rd != 0 {
+            let rd = self.dst(a.rd);
+            self.emit(LaInsn::Xori(R2I12Args { rd, rj: T1, imm: 1 }));
+            self.commit(a.rd, rd);
+        }
+        if a.aq {
+            self.emit(LaInsn::Dbar(0));
+        }
+    }
+
+    // RV `blt a, b` is LA64 `bgt b, a`, the alias of `blt a, b` taking a in
+    // rj and b in rd
+    fn branch(&mut self, a: rv::SBTypeArgs, pc: u64, op: fn(R2I16Args) -> LaInsn) {
+        let rj = self.src(a.rs1, T0);
+        let rd = self.src(a.rs2, T1);
+        let taken = self.buf.new_label();
+        self.buf
+            .emit_branch(taken, move |imm| op(R2I16Args { rd, rj, imm }));
+        let target = pc.wrapping_add(a.imm as i64 as u64);
+        self.side_exits.push((taken, ExitKind::Jump(target)));
+    }
+
+    // Translates one instruction, returning how the block ends if it does.
+    fn insn(&mut self, insn: &RvInsn, pc: u64, len: u64) -> Option<ExitKind> {
+        match *insn {
+            RvInsn::Lui(a) => self.set_const(a.rd, a.imm as i64 as u64),
+            RvInsn::Auipc(a) => self.set_const(a.rd, pc.wrapping_add(a.imm as i64 as u64)),
+            RvInsn::Jal(a) => {
+                self.set_const(a.rd, pc + len);
+                return Some(ExitKind::Jump(pc.wrapping_add(a.imm as i64 as u64)));
+            }
+            RvInsn::Jalr(a) => {
+                // rs1 may be rd
+                let rj = self.src(a.rs1, T0);
+                self.emit(LaInsn::AddiD(R2I12Args {
+                    rd: T2,
+                    rj,
+                    imm: a.imm,
+                }));
+                self.emit(LaInsn::BstrinsD(BstrArgs {
+                    rd: T2,
+                    rj: ZERO,
+                    msb: 0,
+                    lsb: 0,
+                }));
+                self.set_const(a.rd, pc + len);
+                return Some(ExitKind::Indirect);
+            }
+            RvInsn::Beq(a) => self.branch(a, pc, LaInsn::Beq),
+            RvInsn::Bne(a) => self.branch(a, pc, LaInsn::Bne),
+            RvInsn::Blt(a) => self.branch(a, pc, LaInsn::Blt),
+            RvInsn::Bge(a) => self.branch(a, pc, LaInsn::Bge),
+            RvInsn::Bltu(a) => self.branch(a, pc, LaInsn::Bltu),
+            RvInsn::Bgeu(a) => self.branch(a, pc, LaInsn::Bgeu),
+            RvInsn::Lb(a) => self.load(a, LaInsn::LdB),
+            RvInsn::Lh(a) => self.load(a, LaInsn::LdH),
+            RvInsn::Lw(a) => self.load(a, LaInsn::LdW),
+            RvInsn::Lbu(a) => self.load(a, LaInsn::LdBu),
+            RvInsn::Lhu(a) => self.load(a, LaInsn::LdHu),
+            RvInsn::Sb(a) => self.store(a, LaInsn::StB),
+            RvInsn::Sh(a) => self.store(a, LaInsn::StH),
+            RvInsn::Sw(a) => self.store(a, LaInsn::StW),
+            RvInsn::Addi(a) => self.r2i12(a, LaInsn::AddiD),
+            RvInsn::Slti(a) => self.r2i12(a, LaInsn::Slti),
+            RvInsn::Sltiu(a) => self.r2i12(a, LaInsn::Sltui),
+            RvInsn::Xori(a) => self.logic_imm(a, LaInsn::Xori, LaInsn::Xor),
+            RvInsn::Ori(a) => self.logic_imm(a, LaInsn::Ori, LaInsn::Or),
+            RvInsn::Andi(a) => self.logic_imm(a, LaInsn::Andi, LaInsn::And),
+            RvInsn::Slli(a) => self.shift(a, LaInsn::SlliD),
+            RvInsn::Srli(a) => self.shift(a, LaInsn::SrliD),
+            RvInsn::Srai(a) => self.shift(a, LaInsn::SraiD),
+            RvInsn::Add(a) => self.r3(a, LaInsn::AddD),
+            RvInsn::Sub(a) => self.r3(a, LaInsn::SubD),
+            RvInsn::Sll(a) => self.r3(a, LaInsn::SllD),
+            RvInsn::Slt(a) => self.r3(a, LaInsn::Slt),
+            RvInsn::Sltu(a) => self.r3(a, LaInsn::Sltu),
+            RvInsn::Xor(a) => self.r3(a, LaInsn::Xor),
+            RvInsn::Srl(a) => self.r3(a, LaInsn::SrlD),
+            RvInsn::Sra(a) => self.r3(a, LaInsn::SraD),
+            RvInsn::Or(a) => self.r3(a, LaInsn::Or),
+            RvInsn::And(a) => self.r3(a, LaInsn::And),
+            RvInsn::Fence(_) => self.emit(LaInsn::Dbar(0)),
+
+            RvInsn::Lwu(a) => self.load(a, LaInsn::LdWu),
+            RvInsn::Ld(a) => self.load(a, LaInsn::LdD),
+            RvInsn::Sd(a) => self.store(a, LaInsn::StD),
+            RvInsn::Addiw(a) => self.r2i12(a, LaInsn::AddiW),
+            RvInsn::Slliw(a) => self.shift(a, LaInsn::SlliW),
+            RvInsn::Srliw(a) => self.shift(a, LaInsn::SrliW),
+            RvInsn::Sraiw(a) => self.shift(a, LaInsn::SraiW),
+            RvInsn::Addw(a) => self.r3(a, LaInsn::AddW),
+            RvInsn::Subw(a) => self.r3(a, LaInsn::SubW),
+            RvInsn::Sllw(a) => self.r3(a, LaInsn::SllW),
+            RvInsn::Srlw(a) => self.r3(a, LaInsn::SrlW),
+            RvInsn::Sraw(a) => self.r3(a, LaInsn::SraW),
+
+            RvInsn::Mul(a) => self.r3(a, LaInsn::MulD),
+            RvInsn::Mulh(a) => self.r3(a, LaInsn::MulhD),
+            RvInsn::Mulhsu(a) => self.mulhsu(a),
+            RvInsn::Mulhu(a) => self.r3(a, LaInsn::MulhDu),
+            RvInsn::Div(a) => self.divrem(a, LaInsn::DivD, true, false, false),
+            RvInsn::Divu(a) => self.divrem(a, LaInsn::DivDu, false, false, false),
+            RvInsn::Rem(a) => self.divrem(a, LaInsn::ModD, true, true, false),
+            RvInsn::Remu(a) => self.divrem(a, LaInsn::ModDu, false, true, false),
+            RvInsn::Mulw(a) => self.r3(a, LaInsn::MulW),
+            RvInsn::Divw(a) => self.divrem(a, LaInsn::DivW, true, false, true),
+            RvInsn::Divuw(a) => self.divrem(a, LaInsn::DivWu, false, false, true),
+            RvInsn::Remw(a) => self.divrem(a, LaInsn::ModW, true, true, true),
+            RvInsn::Remuw(a) => self.divrem(a, LaInsn::ModWu, false, true, true),
+
+            RvInsn::LrW(a) => self.lr(a, LaInsn::LlW),
+            RvInsn::ScW(a) => self.sc(a, LaInsn::ScW),
+            RvInsn::AmoSwapW(a) => self.amo(a, LaInsn::AmswapW),
+            RvInsn::AmoAddW(a) => self.amo(a, LaInsn::AmaddW),
+            RvInsn::AmoXorW(a) => self.amo(a, LaInsn::AmxorW),
+            RvInsn::AmoAndW(a) => self.amo(a, LaInsn::AmandW),
+            RvInsn::AmoOrW(a) => self.amo(a, LaInsn::AmorW),
+            RvInsn::AmoMinW(a) => self.amo(a, LaInsn::AmminW),
+            RvInsn::AmoMaxW(a) => self.amo(a, LaInsn::AmmaxW),
+            RvInsn::AmoMinuW(a) => self.amo(a, LaInsn::AmminWu),
+            RvInsn::AmoMaxuW(a) => self.amo(a, LaInsn::AmmaxWu),
+            RvInsn::LrD(a) => self.lr(a, LaInsn::LlD),
+            RvInsn::ScD(a) => self.sc(a, LaInsn::ScD),
+            RvInsn::AmoSwapD(a) => self.amo(a, LaInsn::AmswapD),
+            RvInsn::AmoAddD(a) => self.amo(a, LaInsn::AmaddD),
+            RvInsn::AmoXorD(a) => self.amo(a, LaInsn::AmxorD),
+            RvInsn::AmoAndD(a) => self.amo(a, LaInsn::AmandD),
+            RvInsn::AmoOrD(a) => self.amo(a, LaInsn::AmorD),
+            RvInsn::AmoMinD(a) => self.amo(a, LaInsn::AmminD),
+            RvInsn::AmoMaxD(a) => self.amo(a, LaInsn::AmmaxD),
+            RvInsn::AmoMinuD(a) => self.amo(a, LaInsn::AmminDu),
+            RvInsn::AmoMaxuD(a) => self.amo(a, LaInsn::AmmaxDu),
+
+            // system calls, CSRs, FP, fence.i (which has to flush translated
+            // code) and invalid encodings
+            _ => return Some(ExitKind::Interp(pc)),
+        }
+        None
+    }
+
+    // The stub for the last exit goes right after the block, the others after
+    // it in turn.
+    fn finish(mut self, last: ExitKind) -> TranslatedBlock {
+        let mut exits = Vec::new();
+        let side_exits = std::mem::take(&mut self.side_exits);
+        let stubs = side_exits.into_iter().map(|(l, k)| (Some(l), k));
+        for (label, kind) in std::iter::once((None, last)).chain(stubs) {
+            if let Some(label) = label {
+                self.buf.bind(label);
+            }
+            exits.push(Exit {
+                kind,
+                offset: self.buf.offset(),
+            });
+            match kind {
+                ExitKind::Jump(pc) | ExitKind::Interp(pc) => self.li(T0, pc as i64),
+                ExitKind::Indirect => self.mov(T0, T2),
+            }
+            self.li(T1, exits.len() as i64 - 1);
+            self.buf.emit_reloc(RelocTarget::Exit);
+        }
+        TranslatedBlock {
+            code: self.buf,
+            exits,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rv::{FenceArgs, ITypeArgs, RTypeArgs, SBTypeArgs};
+
+    fn words(insns: &[LaInsn]) -> Vec<u32> {
+        insns.iter().map(|x| x.encode()).collect()
+    }
+
+    fn r3(rd: u8, rj: u8, rk: u8) -> R3Args {
+        R3Args { rd, rj, rk }
+    }
+
+    fn i12(rd: u8, rj: u8, imm: i32) -> R2I12Args {
+        R2I12Args { rd, rj, imm }
+    }
+
+    fn mulhsu(rd: u8, rs1: u8, rs2: u8) -> (RvInsn, usize) {
+        (RvInsn::Mulhsu(RTypeArgs { rd, rs1, rs2 }), 4)
+    }
+
+    #[test]
+    fn test_straight_line() {
+        let b = translate_block(
+            0x10000,
+            &[
+                (
+                    RvInsn::Addi(ITypeArgs {
+                        rd: 10,
+                        rs1: 10,
+                        imm: 1,
+                    }),
+                    4,
+                ),
+                (
+                    RvInsn::Add(RTypeArgs {
+                        rd: 11,
+                        rs1: 10,
+                        rs2: 28,
+                    }),
+                    2,
+                ),
+                (
+                    RvInsn::Sd(SBTypeArgs {
+                        rs1: 2,
+                        rs2: 31,
+                        imm: -8,
+                    }),
+                    4,
+                ),
+            ],
+        );
+        assert_eq!(
+            b.code.words(),
+            words(&[
+                LaInsn::AddiD(i12(10, 10, 1)),
+                LaInsn::LdD(i12(T1, CTX, 28 * 8)),
+                LaInsn::AddD(r3(11, 10, T1)),
+                LaInsn::AddD(r3(T2, 2, GBASE)),
+                LaInsn::LdD(i12(T1, CTX, 31 * 8)),
+                LaInsn::StD(i12(T1, T2, -8)),
+                // the exit stub
+                LaInsn::Lu12iW(R1I20Args { rd: T0, imm: 0x10 }),
+                LaInsn::Ori(i12(T0, T0, 0xa)),
+                LaInsn::AddiD(i12(T1, ZERO, 0)),
+                LaInsn::B(0),
+            ])
+        );
+        assert_eq!(b.exits.len(), 1);
+        assert_eq!(b.exits[0].kind, ExitKind::Jump(0x1000a));
+        assert_eq!(b.exits[0].offset, 24);
+        assert_eq!(b.code.relocs().len(), 1);
+        assert_eq!(b.code.relocs()[0].offset, 36);
+    }
+
+    #[test]
+    fn test_branch() {
+        let b = translate_block(
+            0x1000,
+            &[(
+                RvInsn::Blt(SBTypeArgs {
+                    rs1: 10,
+                    rs2: 11,
+                    imm: 16,
+                }),
+                4,
+            )],
+        );
+        let w = b.code.words();
+        // blt $a6,$a7,20, which is bgt $a7,$a6,20
+        assert_eq!(w[0], 0x6000154b);
+        assert_eq!(b.exits[0].kind, ExitKind::Jump(0x1004));
+        assert_eq!(b.exits[0].offset, 4);
+        assert_eq!(b.exits[1].kind, ExitKind::Jump(0x1010));
+        assert_eq!(b.exits[1].offset, 20);
+        assert_eq!(w[7], LaInsn::AddiD(i12(T1, ZERO, 1)).encode());
+
+        // spilled operands are loaded first
+        let b = translate_block(
+            0,
+            &[(
+                RvInsn::Bgeu(SBTypeArgs {
+                    rs1: 30,
+                    rs2: 0,
+                    imm: -4,
+                }),
+                4,
+            )],
+        );
+        assert_eq!(
+            &b.code.words()[..2],
+            words(&[
+                LaInsn::LdD(i12(T0, CTX, 30 * 8)),
+                LaInsn::Bgeu(R2I16Args {
+                    rd: 0,
+                    rj: T0,
+                    imm: 16,
+                }),
+            ])
+        );
+        assert_eq!(b.exits[1].kind, ExitKind::Jump(u64::MAX - 3));
+    }
+
+    #[test]
+    fn test_mulhsu() {
+        let b = translate_block(0, &[mulhsu(10, 11, 12)]);
+        assert_eq!(
+            &b.code.words()[..4],
+            words(&[
+                LaInsn::MulhDu(r3(T2, 11, 12)),
+                LaInsn::SraiD(ShiftArgs {
+                    rd: T0,
+                    rj: 11,
+                    shamt: 63,
+                }),
+                LaInsn::And(r3(T0, T0, 12)),
+                LaInsn::SubD(r3(10, T2, T0)),
+            ])
+        );
+
+        // rd is rs2, and everything is spilled
+        let b = translate_block(0, &[mulhsu(30, 29, 30)]);
+        assert_eq!(
+            &b.code.words()[..7],
+            words(&[
+                LaInsn::LdD(i12(T0, CTX, 29 * 8)),
+                LaInsn::LdD(i12(T1, CTX, 30 * 8)),
+                LaInsn::MulhDu(r3(T2, T0, T1)),
+                LaInsn::SraiD(ShiftArgs {
+                    rd: T0,
+                    rj: T0,
+                    shamt: 63,
+                }),
+                LaInsn::And(r3(T0, T0, T1)),
+                LaInsn::SubD(r3(T0, T2, T0)),
+                LaInsn::StD(i12(T0, CTX, 30 * 8)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_divrem() {
+        let b = translate_block(
+            0,
+            &[(
+                RvInsn::Divu(RTypeArgs {
+                    rd: 10,
+                    rs1: 11,
+                    rs2: 12,
+                }),
+                4,
+            )],
+        );
+        assert_eq!(
+            &b.code.words()[..4],
+            words(&[
+                LaInsn::Beqz(R1I21Args { rj: 12, imm: 12 }),
+                LaInsn::DivDu(r3(10, 11, 12)),
+                LaInsn::B(8),
+                LaInsn::AddiD(i12(10, ZERO, -1)),
+            ])
+        );
+
+        let b = translate_block(
+            0,
+            &[(
+                RvInsn::Remw(RTypeArgs {
+                    rd: 10,
+                    rs1: 11,
+                    rs2: 12,
+                }),
+                4,
+            )],
+        );
+        assert_eq!(
+            &b.code.words()[..10],
+            words(&[
+                LaInsn::AddiW(i12(T0, 11, 0)),
+                LaInsn::AddiW(i12(T1, 12, 0)),
+                LaInsn::Beqz(R1I21Args { rj: T1, imm: 28 }),
+                LaInsn::AddiD(i12(T2, ZERO, -1)),
+                LaInsn::Beq(R2I16Args {
+                    rd: T2,
+                    rj: T1,
+                    imm: 12,
+                }),
+                LaInsn::ModW(r3(10, T0, T1)),
+                LaInsn::B(16),
+                LaInsn::Or(r3(10, ZERO, ZERO)),
+                LaInsn::B(8),
+                LaInsn::Or(r3(10, T0, ZERO)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_exits() {
+        let b = translate_block(
+            0x2000,
+            &[
+                (
+                    RvInsn::Jalr(ITypeArgs {
+                        rd: 1,
+                        rs1: 1,
+                        imm: 4,
+                    }),
+                    4,
+                ),
+                (RvInsn::Ecall, 4),
+            ],
+        );
+        assert_eq!(
+            b.code.words(),
+            words(&[
+                LaInsn::AddiD(i12(T2, 1, 4)),
+                LaInsn::BstrinsD(BstrArgs {
+                    rd: T2,
+                    rj: ZERO,
+                    msb: 0,
+                    lsb: 0,
+                }),
+                LaInsn::Lu12iW(R1I20Args { rd: 1, imm: 2 }),
+                LaInsn::Ori(i12(1, 1, 4)),
+                LaInsn::Or(r3(T0, T2, ZERO)),
+                LaInsn::AddiD(i12(T1, ZERO, 0)),
+                LaInsn::B(0),
+            ])
+        );
+        assert_eq!(b.exits[0].kind, ExitKind::Indirect);
+
+        let b = translate_block(
+            0x100,
+            &[
+                (
+                    RvInsn::Fence(FenceArgs {
+                        fm: 0,
+                        pred: 0b11u8.into(),
+                        succ: 0b11u8.into(),
+                    }),
+                    4,
+                ),
+                (RvInsn::Ecall, 4),
+            ],
+        );
+        assert_eq!(b.code.words()[0], LaInsn::Dbar(0).encode());
+        assert_eq!(b.exits[0].kind, ExitKind::Interp(0x104));
+
+        // exits are linked to the runtime
+        let code = b.code.link(0x1000_0000, 0x0fff_0000).unwrap();
+        let reloc = b.code.relocs()[0].offset;
+        assert_eq!(code[reloc / 4], LaInsn::B(-0x10000 - reloc as i32).encode());
+        assert!(b.code.link(0x1000_0000, 0x2000_0000).is_none());
+    }
+
+    #[test]
+    fn test_li() {
+        // runs the constant loading instructions
+        fn eval(words: &[u32]) -> u64 {
+            let d = LaDecoder::new();
+            let mut r = 0u64;
+            for &w in words {
+                r = match d.disas_32bit(w) {
+                    LaInsn::AddiD(a) => a.imm as i64 as u64,
+                    LaInsn::Ori(a) if a.rj == ZERO => a.imm as u64,
+                    LaInsn::Ori(a) => r | a.imm as u64,
+                    LaInsn::Lu12iW(a) => ((a.imm as i64) << 12) as u64,
+                    LaInsn::Lu32iD(a) => r as u32 as u64 | ((a.imm as i64) << 32) as u64,
+                    LaInsn::Lu52iD(a) if a.rj == ZERO => ((a.imm as i64) << 52) as u64,
+                    LaInsn::Lu52iD(a) => r & ((1 << 52) - 1) | ((a.imm as i64) << 52) as u64,
+                    x => panic!("{:?}", x),
+                };
+            }
+            r
+        }
+
+        #[rustfmt::skip]
+        let cases = [
+            (0, 1), (-2048, 1), (2047, 1), (4095, 1), (4096, 1), (-2049, 2),
+            (0x7fff_ffff, 2), (-0x8000_0000, 1), (0x8000_0000, 2), (0x1_0000_0000, 2),
+            (0x1_0000_0001, 2), (0x1_0000_0fff, 2), (0x7_ffff_ffff_ffff, 2),
+            (-0x8_0000_0000_0000, 2), (0x10_0000_0000_0000, 1), (0x10_0000_0000_0001, 2),
+            (0x1234_5678_9abc_def0, 4), (i64::MIN, 1), (i64::MAX, 2), (-1, 1),
+        ];
+        for (v, len) in cases {
+            let mut t = Translator {
+                buf: CodeBuf::new(),
+                side_exits: Vec::new(),
+            };
+            t.li(T0, v);
+            let w = t.buf.words();
+            assert_eq!(eval(w), v as u64, "{:#x}", v);
+            assert_eq!(w.len(), len, "{:#x}", v);
+        }
+    }
+}